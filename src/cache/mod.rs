@@ -0,0 +1,969 @@
+mod persist;
+mod shard;
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::dns::{
+    answer::ResourceRecord, question::Question, rcode::Rcode, rtype, DomainName, DNS,
+};
+
+pub use persist::FORMAT_VERSION;
+use shard::Shard;
+
+pub const DEFAULT_MAX_MEMORY: usize = 64 << 20;
+// Locks the lookups of different names rarely wait for each other.
+pub const DEFAULT_SHARDS: usize = 16;
+pub const DEFAULT_MIN_TTL: u32 = 0;
+pub const DEFAULT_MAX_TTL: u32 = 86400;
+// RFC 2308 5 suggests one to three hours.
+pub const DEFAULT_MAX_NEGATIVE_TTL: u32 = 3600;
+// RFC 8767 5 suggests keeping expired data for one to three days.
+pub const DEFAULT_STALE_WINDOW: u32 = 86400;
+// Percent of the TTL left when an answer is refreshed ahead of time, 0 never does.
+pub const DEFAULT_PREFETCH: u32 = 0;
+// TTL of records served stale (RFC 8767 4).
+pub const STALE_TTL: u32 = 30;
+// Longest CNAME chain answered from the cache.
+const MAX_CNAMES: usize = 8;
+
+// How much a cached RRset is believed, lowest first (RFC 2181 5.4.1). Data is only
+// replaced by data at least as trustworthy, and only `Answer` and above is ever returned
+// to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trust {
+    // additional sections and authority sections of non-authoritative replies
+    Additional,
+    // answer sections of non-authoritative replies
+    Answer,
+    // authority sections of authoritative replies
+    AuthAuthority,
+    // answer sections of authoritative replies, for the name asked about
+    AuthAnswer,
+}
+
+// `typ` is `None` for an NXDOMAIN, which covers every type of the name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    name: DomainName,
+    typ: Option<u16>,
+    class: u16,
+}
+
+// An RRset, or for a negative entry the SOA record that came with the NXDOMAIN or
+// NODATA reply (RFC 2308).
+#[derive(Debug, Clone)]
+struct Entry {
+    records: Vec<ResourceRecord>,
+    negative: bool,
+    trust: Trust,
+    // the TTL the entry was stored with
+    ttl: u32,
+    expires: Instant,
+}
+
+// RRsets keyed by name, type and class. The TTLs of cached records count down, what is
+// handed out carries the time left.
+// The entries are spread over shards by name, so all the entries of a name share a lock.
+// Each shard gets an equal part of the memory limit and evicts its least recently used
+// entries when it is full.
+// Expired entries are kept for `stale_window` more seconds to be served stale when
+// resolving fails (RFC 8767).
+#[derive(Debug)]
+pub struct Cache {
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+    max_memory: usize,
+    min_ttl: u32,
+    max_ttl: u32,
+    max_negative_ttl: u32,
+    stale_window: u32,
+    prefetch: u32,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl Cache {
+    // A cache using about `max_memory` bytes at most.
+    pub fn new(max_memory: usize) -> Self {
+        let mut cache = Cache {
+            shards: vec![],
+            hasher: RandomState::new(),
+            max_memory,
+            min_ttl: DEFAULT_MIN_TTL,
+            max_ttl: DEFAULT_MAX_TTL,
+            max_negative_ttl: DEFAULT_MAX_NEGATIVE_TTL,
+            stale_window: DEFAULT_STALE_WINDOW,
+            prefetch: DEFAULT_PREFETCH,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        };
+        cache.with_shards(DEFAULT_SHARDS);
+        return cache;
+    }
+
+    pub fn max_memory(&self) -> usize {
+        return self.max_memory;
+    }
+
+    pub fn shards(&self) -> usize {
+        return self.shards.len();
+    }
+
+    // Drops everything cached so far.
+    pub fn with_shards(&mut self, shards: usize) -> &mut Self {
+        let shards = shards.max(1);
+        self.shards = (0..shards)
+            .map(|_| Mutex::new(Shard::new(self.max_memory / shards)))
+            .collect();
+        return self;
+    }
+
+    // TTLs below are raised to it, 0 leaves records with TTL 0 uncached.
+    pub fn min_ttl(&self) -> u32 {
+        return self.min_ttl;
+    }
+
+    pub fn with_min_ttl(&mut self, min_ttl: u32) -> &mut Self {
+        self.min_ttl = min_ttl;
+        return self;
+    }
+
+    // TTLs above are lowered to it.
+    pub fn max_ttl(&self) -> u32 {
+        return self.max_ttl;
+    }
+
+    pub fn with_max_ttl(&mut self, max_ttl: u32) -> &mut Self {
+        self.max_ttl = max_ttl;
+        return self;
+    }
+
+    // Upper bound of the time NXDOMAIN and NODATA replies are cached.
+    pub fn max_negative_ttl(&self) -> u32 {
+        return self.max_negative_ttl;
+    }
+
+    pub fn with_max_negative_ttl(&mut self, max_negative_ttl: u32) -> &mut Self {
+        self.max_negative_ttl = max_negative_ttl;
+        return self;
+    }
+
+    // Seconds expired entries are still served stale, 0 disables serving stale data.
+    pub fn stale_window(&self) -> u32 {
+        return self.stale_window;
+    }
+
+    pub fn with_stale_window(&mut self, stale_window: u32) -> &mut Self {
+        self.stale_window = stale_window;
+        return self;
+    }
+
+    // Answers made of an entry with less than this percent of its TTL left are due for a
+    // refresh, see `answer_prefetch`.
+    pub fn prefetch(&self) -> u32 {
+        return self.prefetch;
+    }
+
+    pub fn with_prefetch(&mut self, percent: u32) -> &mut Self {
+        self.prefetch = percent;
+        return self;
+    }
+
+    // Number of cached RRsets and negative entries, expired ones included until they are
+    // evicted.
+    pub fn len(&self) -> usize {
+        return self.shards.iter().map(|s| s.lock().unwrap().len()).sum();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // Estimated bytes used by the entries.
+    pub fn memory(&self) -> usize {
+        return self.shards.iter().map(|s| s.lock().unwrap().memory()).sum();
+    }
+
+    // Questions answered from the cache.
+    pub fn hits(&self) -> u64 {
+        return self.hits.load(Ordering::Relaxed);
+    }
+
+    pub fn misses(&self) -> u64 {
+        return self.misses.load(Ordering::Relaxed);
+    }
+
+    // Entries dropped to make room for new ones.
+    pub fn evictions(&self) -> u64 {
+        return self.evictions.load(Ordering::Relaxed);
+    }
+
+    // Cache one RRset. `false` when it was not stored: a TTL of 0, no room or more
+    // trustworthy data already cached.
+    pub fn insert(&self, rrset: &[ResourceRecord], trust: Trust) -> bool {
+        return self.insert_at(rrset, trust, Instant::now());
+    }
+
+    // The RRset at `name`, if cached with at least `trust`.
+    pub fn get(
+        &self,
+        name: &DomainName,
+        typ: u16,
+        class: u16,
+        trust: Trust,
+    ) -> Option<Vec<ResourceRecord>> {
+        return self.get_at(name, typ, class, trust, Instant::now());
+    }
+
+    // Cache that `name` does not exist (`typ` is `None`) or has no data of type `typ`.
+    // `soa` is the SOA record of the negative reply, it decides how long the entry lives.
+    pub fn insert_negative(
+        &self,
+        name: &DomainName,
+        typ: Option<u16>,
+        class: u16,
+        soa: &ResourceRecord,
+        trust: Trust,
+    ) -> bool {
+        return self.insert_negative_at(name, typ, class, soa, trust, Instant::now());
+    }
+
+    // Cache every RRset of `reply` that is inside `zone`, ranked by its section, and an
+    // NXDOMAIN or NODATA reply as a negative entry.
+    pub fn store(&self, reply: &DNS, zone: &DomainName) {
+        self.store_at(reply, zone, Instant::now());
+    }
+
+    // A reply to `ques` made of cached answers, CNAMEs included. `None` when anything
+    // on the way is missing.
+    pub fn answer(&self, ques: &Question) -> Option<DNS> {
+        return self.answer_at(ques, Instant::now());
+    }
+
+    // Like `answer`, also telling whether the answer should be refreshed before it expires.
+    pub fn answer_prefetch(&self, ques: &Question) -> Option<(DNS, bool)> {
+        return self.answer_prefetch_at(ques, Instant::now());
+    }
+
+    // Like `answer`, but expired entries still inside the stale window are used as well,
+    // with a TTL of `STALE_TTL`. Not counted as a hit or miss.
+    pub fn answer_stale(&self, ques: &Question) -> Option<DNS> {
+        return self
+            .find_answer(ques, true, Instant::now())
+            .map(|(reply, _)| reply);
+    }
+
+    fn insert_at(&self, rrset: &[ResourceRecord], trust: Trust, now: Instant) -> bool {
+        let first = match rrset.first() {
+            Some(first) => first,
+            None => return false,
+        };
+        // RFC 2181 5.2, the TTLs of an RRset should be equal, the lowest one wins
+        let ttl = rrset.iter().map(|rr| rr.ttl()).min().unwrap_or(0);
+        let ttl = ttl.max(self.min_ttl).min(self.max_ttl);
+        let key = Key {
+            name: first.name().clone(),
+            typ: Some(first.typ()),
+            class: first.class(),
+        };
+
+        let mut shard = self.shard(&key).lock().unwrap();
+        if !self.put(&mut shard, key, rrset.to_vec(), false, ttl, trust, now) {
+            return false;
+        }
+        // the name exists after all
+        let nxdomain = Key {
+            name: first.name().clone(),
+            typ: None,
+            class: first.class(),
+        };
+        shard.remove(&nxdomain);
+
+        return true;
+    }
+
+    fn insert_negative_at(
+        &self,
+        name: &DomainName,
+        typ: Option<u16>,
+        class: u16,
+        soa: &ResourceRecord,
+        trust: Trust,
+        now: Instant,
+    ) -> bool {
+        // RFC 2308 5, the lower of the SOA TTL and its MINIMUM field
+        let minimum = match soa_minimum(soa) {
+            Some(minimum) => minimum,
+            None => return false,
+        };
+        let ttl = soa.ttl().min(minimum).min(self.max_negative_ttl);
+        let key = Key {
+            name: name.clone(),
+            typ,
+            class,
+        };
+
+        let mut shard = self.shard(&key).lock().unwrap();
+        return self.put(&mut shard, key, vec![soa.clone()], true, ttl, trust, now);
+    }
+
+    // The shard of all the entries of a name.
+    #[allow(clippy::manual_hash_one)]
+    fn shard(&self, key: &Key) -> &Mutex<Shard> {
+        let mut h = self.hasher.build_hasher();
+        (&key.name, key.class).hash(&mut h);
+        let hash = h.finish();
+        return &self.shards[hash as usize % self.shards.len()];
+    }
+
+    // Store `records` under `key` unless more trustworthy data is cached there.
+    #[allow(clippy::too_many_arguments)]
+    fn put(
+        &self,
+        shard: &mut Shard,
+        key: Key,
+        records: Vec<ResourceRecord>,
+        negative: bool,
+        ttl: u32,
+        trust: Trust,
+        now: Instant,
+    ) -> bool {
+        if ttl == 0 {
+            return false;
+        }
+        if let Some(old) = shard.peek(&key) {
+            if old.expires > now && old.trust > trust {
+                return false;
+            }
+        }
+
+        let entry = Entry {
+            records,
+            negative,
+            trust,
+            ttl,
+            expires: now + Duration::from_secs(ttl as u64),
+        };
+        match shard.insert(key, entry) {
+            Some(evicted) => {
+                self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+                return true;
+            }
+            None => return false,
+        }
+    }
+
+    // The unexpired entry under `key` with at least `trust`, TTLs set to the time left.
+    // With `stale` an expired entry inside the stale window is returned with `STALE_TTL`.
+    fn lookup(&self, key: &Key, trust: Trust, stale: bool, now: Instant) -> Option<Entry> {
+        let mut shard = self.shard(key).lock().unwrap();
+        let entry = shard.get(key)?;
+        if entry.expires <= now {
+            let window = Duration::from_secs(self.stale_window as u64);
+            if entry.expires + window <= now {
+                shard.remove(key);
+                return None;
+            }
+            if !stale {
+                return None;
+            }
+        }
+        if entry.trust < trust {
+            return None;
+        }
+
+        let ttl = match entry.expires.checked_duration_since(now) {
+            Some(left) if !left.is_zero() => left.as_secs() as u32,
+            _ => STALE_TTL,
+        };
+        let mut entry = entry.clone();
+        for rr in entry.records.iter_mut() {
+            rr.with_ttl(ttl);
+        }
+        return Some(entry);
+    }
+
+    fn get_at(
+        &self,
+        name: &DomainName,
+        typ: u16,
+        class: u16,
+        trust: Trust,
+        now: Instant,
+    ) -> Option<Vec<ResourceRecord>> {
+        let key = Key {
+            name: name.clone(),
+            typ: Some(typ),
+            class,
+        };
+        return self
+            .lookup(&key, trust, false, now)
+            .filter(|entry| !entry.negative)
+            .map(|entry| entry.records);
+    }
+
+    fn store_at(&self, reply: &DNS, zone: &DomainName, now: Instant) {
+        let aa = reply.head().aa() == 1;
+        let ques = reply.questions().iter().next();
+
+        for (owner, rrset) in rrsets(reply.answers().iter(), zone) {
+            let trust = match ques {
+                Some(ques) if aa && *ques.name() == owner => Trust::AuthAnswer,
+                _ => Trust::Answer,
+            };
+            self.insert_at(&rrset, trust, now);
+        }
+        let trust = if aa {
+            Trust::AuthAuthority
+        } else {
+            Trust::Additional
+        };
+        for (_, rrset) in rrsets(reply.authorities().iter(), zone) {
+            self.insert_at(&rrset, trust, now);
+        }
+        for (_, rrset) in rrsets(reply.additionals().iter(), zone) {
+            self.insert_at(&rrset, Trust::Additional, now);
+        }
+
+        let ques = match ques {
+            Some(ques) if ques.typ() != rtype::ANY => ques,
+            _ => return,
+        };
+        // the name at the end of the CNAME chain is the one that has no data
+        let mut name = ques.name().clone();
+        for _ in 0..MAX_CNAMES {
+            let cname = reply
+                .answers()
+                .iter()
+                .find(|rr| rr.typ() == rtype::CNAME && *rr.name() == name);
+            match cname.and_then(|rr| DomainName::decode(rr.rdata(), 0).ok()) {
+                Some((target, _)) if ques.typ() != rtype::CNAME => name = target,
+                _ => break,
+            }
+        }
+        let typ = match reply.rcode() {
+            Rcode::NXDomain => None,
+            Rcode::NoError
+                if !reply
+                    .answers()
+                    .iter()
+                    .any(|rr| *rr.name() == name && rr.typ() == ques.typ()) =>
+            {
+                Some(ques.typ())
+            }
+            _ => return,
+        };
+        let soa = reply.authorities().iter().find(|rr| {
+            rr.typ() == rtype::SOA
+                && rr.name().is_subdomain_of(zone)
+                && name.is_subdomain_of(rr.name())
+        });
+        if let Some(soa) = soa {
+            let trust = if aa { Trust::AuthAnswer } else { Trust::Answer };
+            self.insert_negative_at(&name, typ, ques.class(), soa, trust, now);
+        }
+    }
+
+    fn answer_at(&self, ques: &Question, now: Instant) -> Option<DNS> {
+        return self.answer_prefetch_at(ques, now).map(|(reply, _)| reply);
+    }
+
+    fn answer_prefetch_at(&self, ques: &Question, now: Instant) -> Option<(DNS, bool)> {
+        let found = self.find_answer(ques, false, now);
+        match found {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        return found;
+    }
+
+    // Whether `entry` is in the last `prefetch` percent of its TTL.
+    fn expiring(&self, entry: &Entry, now: Instant) -> bool {
+        let left = entry.expires.saturating_duration_since(now).as_secs();
+        return left * 100 < entry.ttl as u64 * self.prefetch as u64;
+    }
+
+    // The reply to `ques` and whether any entry it is made of is expiring.
+    fn find_answer(&self, ques: &Question, stale: bool, now: Instant) -> Option<(DNS, bool)> {
+        if ques.typ() == rtype::ANY {
+            return None;
+        }
+
+        let mut reply = DNS::new();
+        reply.head_mut().with_qr(1);
+        let mut name = ques.name().clone();
+        let mut expiring = false;
+        for _ in 0..MAX_CNAMES {
+            let key = |typ| Key {
+                name: name.clone(),
+                typ,
+                class: ques.class(),
+            };
+            if let Some(entry) = self.lookup(&key(Some(ques.typ())), Trust::Answer, stale, now) {
+                expiring |= self.expiring(&entry, now);
+                // a negative entry is a NODATA, its SOA goes to the authority section
+                for rr in entry.records {
+                    if entry.negative {
+                        reply.with_authority(rr);
+                    } else {
+                        reply.with_answer(rr);
+                    }
+                }
+                return Some((reply, expiring));
+            }
+            let cname = self
+                .lookup(&key(Some(rtype::CNAME)), Trust::Answer, stale, now)
+                .filter(|entry| !entry.negative);
+            if let Some(cname) = cname {
+                expiring |= self.expiring(&cname, now);
+                let cname = cname.records.into_iter().next()?;
+                name = DomainName::decode(cname.rdata(), 0).ok()?.0;
+                reply.with_answer(cname);
+                continue;
+            }
+            let entry = self.lookup(&key(None), Trust::Answer, stale, now)?;
+            expiring |= self.expiring(&entry, now);
+            reply.with_rcode(Rcode::NXDomain);
+            for rr in entry.records {
+                reply.with_authority(rr);
+            }
+            return Some((reply, expiring));
+        }
+
+        return None;
+    }
+}
+
+// The MINIMUM field of an SOA record, the last one of its RDATA.
+fn soa_minimum(soa: &ResourceRecord) -> Option<u32> {
+    if soa.typ() != rtype::SOA || soa.rdata().len() < 22 {
+        return None;
+    }
+    let minimum = &soa.rdata()[soa.rdata().len() - 4..];
+    return Some(u32::from_be_bytes(minimum.try_into().ok()?));
+}
+
+// Group the records inside `zone` into RRsets, in the order they first appear.
+fn rrsets<'a>(
+    records: impl Iterator<Item = &'a ResourceRecord>,
+    zone: &DomainName,
+) -> Vec<(DomainName, Vec<ResourceRecord>)> {
+    let mut sets = Vec::<(DomainName, Vec<ResourceRecord>)>::new();
+    for rr in records.filter(|rr| rr.name().is_subdomain_of(zone)) {
+        match sets.iter_mut().find(|(name, set)| {
+            *name == *rr.name() && set[0].typ() == rr.typ() && set[0].class() == rr.class()
+        }) {
+            Some((_, set)) => set.push(rr.clone()),
+            None => sets.push((rr.name().clone(), vec![rr.clone()])),
+        }
+    }
+    return sets;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn a(name: &str, ttl: u32, last: u8) -> ResourceRecord {
+        let mut rr = ResourceRecord::new();
+        rr.with_name(name)
+            .unwrap()
+            .with_type(rtype::A)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(ttl)
+            .with_rdata(Ipv4Addr::new(192, 0, 2, last));
+        return rr;
+    }
+
+    fn get(cache: &Cache, name: &str, trust: Trust, now: Instant) -> Option<Vec<ResourceRecord>> {
+        return cache.get_at(
+            &name.parse().unwrap(),
+            rtype::A,
+            rtype::CLASS_IN,
+            trust,
+            now,
+        );
+    }
+
+    #[test]
+    pub fn test_cache_ttl_counts_down() {
+        let cache = Cache::new(DEFAULT_MAX_MEMORY);
+        let now = Instant::now();
+        assert!(cache.insert_at(
+            &[a("www.example.", 300, 1), a("www.example.", 300, 2)],
+            Trust::Answer,
+            now
+        ));
+        assert_eq!(1, cache.len());
+
+        let rrset = get(&cache, "WWW.example.", Trust::Answer, now).unwrap();
+        assert_eq!(
+            vec![300, 300],
+            rrset.iter().map(|rr| rr.ttl()).collect::<Vec<u32>>()
+        );
+        let later = now + Duration::from_secs(100);
+        assert_eq!(
+            200,
+            get(&cache, "www.example.", Trust::Answer, later).unwrap()[0].ttl()
+        );
+        assert!(get(
+            &cache,
+            "www.example.",
+            Trust::Answer,
+            now + Duration::from_secs(300)
+        )
+        .is_none());
+
+        // TTL 0 is not cached
+        assert!(!cache.insert_at(&[a("zero.example.", 0, 1)], Trust::Answer, now));
+    }
+
+    #[test]
+    pub fn test_cache_ttl_clamping() {
+        let mut cache = Cache::new(DEFAULT_MAX_MEMORY);
+        cache.with_min_ttl(60).with_max_ttl(3600);
+        let now = Instant::now();
+        cache.insert_at(&[a("short.example.", 5, 1)], Trust::Answer, now);
+        cache.insert_at(&[a("long.example.", 604800, 1)], Trust::Answer, now);
+        cache.insert_at(&[a("zero.example.", 0, 1)], Trust::Answer, now);
+
+        assert_eq!(
+            60,
+            get(&cache, "short.example.", Trust::Answer, now).unwrap()[0].ttl()
+        );
+        assert_eq!(
+            3600,
+            get(&cache, "long.example.", Trust::Answer, now).unwrap()[0].ttl()
+        );
+        assert_eq!(
+            60,
+            get(&cache, "zero.example.", Trust::Answer, now).unwrap()[0].ttl()
+        );
+    }
+
+    #[test]
+    pub fn test_cache_stale() {
+        let mut cache = Cache::new(DEFAULT_MAX_MEMORY);
+        cache.with_stale_window(600);
+        let now = Instant::now();
+        cache.insert_at(&[a("www.example.", 300, 1)], Trust::Answer, now);
+        let ques = question("www.example.", rtype::A);
+
+        // fresh data keeps its TTL
+        let (reply, _) = cache.find_answer(&ques, true, now).unwrap();
+        assert_eq!(300, reply.answers().iter().next().unwrap().ttl());
+
+        let expired = now + Duration::from_secs(400);
+        assert!(cache.answer_at(&ques, expired).is_none());
+        let (reply, _) = cache.find_answer(&ques, true, expired).unwrap();
+        assert_eq!(STALE_TTL, reply.answers().iter().next().unwrap().ttl());
+        assert_eq!(1, cache.len());
+
+        // beyond the window the entry is gone
+        assert!(cache
+            .find_answer(&ques, true, now + Duration::from_secs(900))
+            .is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    pub fn test_cache_prefetch() {
+        let mut cache = Cache::new(DEFAULT_MAX_MEMORY);
+        let now = Instant::now();
+        cache.insert_at(&[a("www.example.", 100, 1)], Trust::Answer, now);
+        let ques = question("www.example.", rtype::A);
+        let late = now + Duration::from_secs(95);
+        assert!(!cache.answer_prefetch_at(&ques, late).unwrap().1);
+
+        cache.with_prefetch(10);
+        assert!(!cache.answer_prefetch_at(&ques, now).unwrap().1);
+        assert!(
+            !cache
+                .answer_prefetch_at(&ques, now + Duration::from_secs(89))
+                .unwrap()
+                .1
+        );
+        assert!(cache.answer_prefetch_at(&ques, late).unwrap().1);
+        assert_eq!(4, cache.hits());
+    }
+
+    #[test]
+    pub fn test_cache_trust() {
+        let cache = Cache::new(DEFAULT_MAX_MEMORY);
+        let now = Instant::now();
+        cache.insert_at(&[a("ns.example.", 300, 1)], Trust::Additional, now);
+        // glue is never an answer
+        assert!(get(&cache, "ns.example.", Trust::Answer, now).is_none());
+        assert!(get(&cache, "ns.example.", Trust::Additional, now).is_some());
+
+        assert!(cache.insert_at(&[a("ns.example.", 300, 2)], Trust::AuthAnswer, now));
+        assert!(!cache.insert_at(&[a("ns.example.", 300, 3)], Trust::Answer, now));
+        let rrset = get(&cache, "ns.example.", Trust::Answer, now).unwrap();
+        assert_eq!(&[192, 0, 2, 2], rrset[0].rdata());
+        assert_eq!(1, cache.len());
+
+        // expired data is replaced by anything
+        let later = now + Duration::from_secs(300);
+        assert!(cache.insert_at(&[a("ns.example.", 300, 3)], Trust::Additional, later));
+    }
+
+    #[test]
+    pub fn test_cache_memory_limit() {
+        // room for two entries in a single shard
+        let one = Cache::new(DEFAULT_MAX_MEMORY);
+        one.insert(&[a("a.example.", 300, 1)], Trust::Answer);
+        let mut cache = Cache::new(2 * one.memory());
+        cache.with_shards(1);
+        let now = Instant::now();
+
+        assert!(cache.insert_at(&[a("a.example.", 300, 1)], Trust::Answer, now));
+        assert!(cache.insert_at(&[a("b.example.", 300, 1)], Trust::Answer, now));
+        assert!(cache
+            .answer_at(&question("a.example.", rtype::A), now)
+            .is_some());
+        assert!(cache.insert_at(&[a("c.example.", 300, 1)], Trust::Answer, now));
+        // b was the least recently used one
+        assert!(cache
+            .answer_at(&question("b.example.", rtype::A), now)
+            .is_none());
+        assert!(cache
+            .answer_at(&question("a.example.", rtype::A), now)
+            .is_some());
+        assert_eq!(2, cache.len());
+        assert_eq!(1, cache.evictions());
+        assert_eq!(2, cache.hits());
+        assert_eq!(1, cache.misses());
+        assert!(cache.memory() <= cache.max_memory());
+
+        // an RRset larger than the cache is not stored
+        let big: Vec<ResourceRecord> = (0..10).map(|i| a("big.example.", 300, i)).collect();
+        assert!(!cache.insert_at(&big, Trust::Answer, now));
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    pub fn test_cache_store_and_answer() {
+        let cache = Cache::new(DEFAULT_MAX_MEMORY);
+        let now = Instant::now();
+
+        let mut reply = DNS::new();
+        reply.head_mut().with_qr(1).with_aa(1);
+        let qname: DomainName = "alias.example.".parse().unwrap();
+        reply.with_question(Question::from_parts(
+            qname.clone(),
+            rtype::A,
+            rtype::CLASS_IN,
+        ));
+        let mut cname = ResourceRecord::new();
+        cname
+            .with_name("alias.example.")
+            .unwrap()
+            .with_type(rtype::CNAME)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(600)
+            .with_raw_rdata("www.example.".parse::<DomainName>().unwrap().encode());
+        reply
+            .with_answer(cname)
+            .with_answer(a("www.example.", 300, 1))
+            .with_answer(a("www.example.", 300, 2))
+            .with_additional(a("ns.example.", 300, 53))
+            .with_additional(a("www.other.", 300, 9));
+        cache.store_at(&reply, &"example.".parse().unwrap(), now);
+        // www.other. is outside the zone
+        assert_eq!(3, cache.len());
+
+        let ques = Question::from_parts(qname, rtype::A, rtype::CLASS_IN);
+        let answer = cache
+            .answer_at(&ques, now + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(Rcode::NoError, answer.rcode());
+        let ttls: Vec<u32> = answer.answers().iter().map(|rr| rr.ttl()).collect();
+        assert_eq!(vec![590, 290, 290], ttls);
+
+        let ques = Question::from_parts("ns.example.".parse().unwrap(), rtype::A, rtype::CLASS_IN);
+        assert!(cache.answer_at(&ques, now).is_none());
+    }
+
+    fn soa(ttl: u32, minimum: u32) -> ResourceRecord {
+        let mut rdata = "ns.example.".parse::<DomainName>().unwrap().encode();
+        rdata.extend(
+            "hostmaster.example."
+                .parse::<DomainName>()
+                .unwrap()
+                .encode(),
+        );
+        rdata.extend([0, 0, 0, 1, 0, 0, 14, 16, 0, 0, 3, 132, 0, 9, 58, 128]);
+        rdata.extend(minimum.to_be_bytes());
+        let mut rr = ResourceRecord::new();
+        rr.with_name("example.")
+            .unwrap()
+            .with_type(rtype::SOA)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(ttl)
+            .with_raw_rdata(rdata);
+        return rr;
+    }
+
+    fn negative_reply(qname: &str, qtype: u16, rcode: Rcode) -> DNS {
+        let mut reply = DNS::new();
+        reply.head_mut().with_qr(1).with_aa(1);
+        reply.with_question(Question::from_parts(
+            qname.parse().unwrap(),
+            qtype,
+            rtype::CLASS_IN,
+        ));
+        reply.with_rcode(rcode).with_authority(soa(3600, 300));
+        return reply;
+    }
+
+    fn question(name: &str, typ: u16) -> Question {
+        return Question::from_parts(name.parse().unwrap(), typ, rtype::CLASS_IN);
+    }
+
+    #[test]
+    pub fn test_cache_nxdomain() {
+        let cache = Cache::new(DEFAULT_MAX_MEMORY);
+        let now = Instant::now();
+        let zone: DomainName = "example.".parse().unwrap();
+        cache.store_at(
+            &negative_reply("missing.example.", rtype::A, Rcode::NXDomain),
+            &zone,
+            now,
+        );
+
+        // the whole name is gone, whatever the type
+        let later = now + Duration::from_secs(100);
+        let reply = cache
+            .answer_at(&question("missing.example.", rtype::AAAA), later)
+            .unwrap();
+        assert_eq!(Rcode::NXDomain, reply.rcode());
+        assert!(reply.answers().is_empty());
+        let soa = reply.authorities().iter().next().unwrap();
+        assert_eq!(rtype::SOA, soa.typ());
+        // the SOA minimum is lower than its TTL
+        assert_eq!(200, soa.ttl());
+        assert!(cache
+            .answer_at(
+                &question("missing.example.", rtype::A),
+                now + Duration::from_secs(300)
+            )
+            .is_none());
+
+        // data for the name ends the NXDOMAIN
+        cache.insert_at(&[a("missing.example.", 300, 1)], Trust::Answer, later);
+        assert!(cache
+            .answer_at(&question("missing.example.", rtype::AAAA), later)
+            .is_none());
+        // the A record and the SOA of the zone
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    pub fn test_cache_nodata() {
+        let cache = Cache::new(DEFAULT_MAX_MEMORY);
+        let now = Instant::now();
+        let zone: DomainName = "example.".parse().unwrap();
+        cache.insert_at(&[a("www.example.", 300, 1)], Trust::Answer, now);
+        cache.store_at(
+            &negative_reply("www.example.", rtype::AAAA, Rcode::NoError),
+            &zone,
+            now,
+        );
+
+        let reply = cache
+            .answer_at(&question("www.example.", rtype::AAAA), now)
+            .unwrap();
+        assert_eq!(Rcode::NoError, reply.rcode());
+        assert!(reply.answers().is_empty());
+        assert_eq!(300, reply.authorities().iter().next().unwrap().ttl());
+        let reply = cache
+            .answer_at(&question("www.example.", rtype::A), now)
+            .unwrap();
+        assert_eq!(1, reply.answers().len());
+        assert!(reply.authorities().is_empty());
+        assert!(cache
+            .answer_at(&question("www.example.", rtype::MX), now)
+            .is_none());
+
+        // without a SOA, or for a SOA outside the zone, nothing is cached
+        let mut reply = negative_reply("ftp.example.", rtype::A, Rcode::NoError);
+        reply.with_authority(ResourceRecord::new());
+        cache.store_at(&reply, &"other.".parse().unwrap(), now);
+        let mut bare = DNS::new();
+        bare.head_mut().with_qr(1);
+        bare.with_question(question("ftp.example.", rtype::A));
+        cache.store_at(&bare, &zone, now);
+        assert!(cache
+            .answer_at(&question("ftp.example.", rtype::A), now)
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_cache_negative_ttl() {
+        let mut cache = Cache::new(DEFAULT_MAX_MEMORY);
+        cache.with_max_negative_ttl(60);
+        let now = Instant::now();
+        let name: DomainName = "missing.example.".parse().unwrap();
+        assert!(cache.insert_negative_at(
+            &name,
+            None,
+            rtype::CLASS_IN,
+            &soa(3600, 900),
+            Trust::Answer,
+            now
+        ));
+        let reply = cache
+            .answer_at(&question("missing.example.", rtype::A), now)
+            .unwrap();
+        assert_eq!(60, reply.authorities().iter().next().unwrap().ttl());
+
+        assert!(!cache.insert_negative_at(
+            &name,
+            None,
+            rtype::CLASS_IN,
+            &a("x.example.", 60, 1),
+            Trust::Answer,
+            now
+        ));
+    }
+
+    #[test]
+    pub fn test_cache_cname_to_nxdomain() {
+        let cache = Cache::new(DEFAULT_MAX_MEMORY);
+        let now = Instant::now();
+        let mut reply = negative_reply("alias.example.", rtype::A, Rcode::NXDomain);
+        let mut cname = ResourceRecord::new();
+        cname
+            .with_name("alias.example.")
+            .unwrap()
+            .with_type(rtype::CNAME)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(600)
+            .with_raw_rdata("gone.example.".parse::<DomainName>().unwrap().encode());
+        reply.with_answer(cname);
+        cache.store_at(&reply, &"example.".parse().unwrap(), now);
+
+        let reply = cache
+            .answer_at(&question("alias.example.", rtype::A), now)
+            .unwrap();
+        assert_eq!(Rcode::NXDomain, reply.rcode());
+        assert_eq!(rtype::CNAME, reply.answers().iter().next().unwrap().typ());
+        assert_eq!(1, reply.authorities().len());
+        // the alias itself exists
+        let reply = cache
+            .answer_at(&question("alias.example.", rtype::CNAME), now)
+            .unwrap();
+        assert_eq!(Rcode::NoError, reply.rcode());
+    }
+}
@@ -0,0 +1,294 @@
+// Saving the cache to a file and loading it back, so a restart does not begin cold.
+//
+// The file starts with a magic string and a format version, followed by the entries,
+// least recently used first:
+//
+// +-----------+-----------+-------------------------------------------------+
+// | Field     | Type      | Description                                     |
+// +-----------+-----------+-------------------------------------------------+
+// | NAME      | name      | owner name, uncompressed                        |
+// | FLAGS     | u8        | 1: has TYPE (not an NXDOMAIN), 2: negative      |
+// | TYPE      | u16       | 0 without the type flag                         |
+// | CLASS     | u16       |                                                 |
+// | TRUST     | u8        | `Trust` from 0 (Additional) to 3 (AuthAnswer)   |
+// | TTL       | u32       | TTL the entry was stored with                   |
+// | EXPIRES   | i64       | expiry in seconds since the Unix epoch          |
+// | COUNT     | u16       | number of records                               |
+// | RECORDS   | RRs       | in wire format, uncompressed                    |
+// +-----------+-----------+-------------------------------------------------+
+//
+// Absolute expiry times let the TTLs count down across the downtime.
+use anyhow::Error;
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use super::{Cache, Entry, Key, Trust};
+use crate::dns::{answer::ResourceRecord, DomainName};
+
+const MAGIC: &[u8; 8] = b"DNSCACHE";
+// Bump on any change of the layout, files of other versions are not loaded.
+pub const FORMAT_VERSION: u16 = 1;
+
+const HAS_TYPE: u8 = 1;
+const NEGATIVE: u8 = 2;
+
+impl Cache {
+    // Write every entry to `path`, replacing the file at once. Returns the number of
+    // entries written.
+    pub fn save(&self, path: &Path) -> Result<usize, Error> {
+        let (data, count) = self.encode_at(Instant::now(), unix_now());
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        return Ok(count);
+    }
+
+    // Add the entries saved in `path` that are not past the stale window yet. Returns the
+    // number of entries loaded.
+    pub fn load(&self, path: &Path) -> Result<usize, Error> {
+        let data = fs::read(path)?;
+        return self.decode_at(&data, Instant::now(), unix_now());
+    }
+
+    fn encode_at(&self, now: Instant, unix_now: i64) -> (Vec<u8>, usize) {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        let mut count = 0;
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            for (key, entry) in shard.entries() {
+                encode_entry(&mut data, key, entry, now, unix_now);
+                count += 1;
+            }
+        }
+        return (data, count);
+    }
+
+    fn decode_at(&self, data: &[u8], now: Instant, unix_now: i64) -> Result<usize, Error> {
+        if data.len() < MAGIC.len() + 2 || &data[..MAGIC.len()] != MAGIC {
+            return Err(Error::msg("not a cache file"));
+        }
+        let version = u16::from_be_bytes([data[MAGIC.len()], data[MAGIC.len() + 1]]);
+        if version != FORMAT_VERSION {
+            return Err(Error::msg(format!(
+                "unsupported cache file version {}",
+                version
+            )));
+        }
+
+        // decode everything before storing anything, a damaged file is not half loaded
+        let mut entries = Vec::<(Key, Entry)>::new();
+        let mut pos = MAGIC.len() + 2;
+        while pos < data.len() {
+            let (key, entry, next) = decode_entry(data, pos, now, unix_now)?;
+            if let Some(entry) = entry {
+                entries.push((key, entry));
+            }
+            pos = next;
+        }
+
+        let window = Duration::from_secs(self.stale_window as u64);
+        let mut count = 0;
+        for (key, entry) in entries {
+            if entry.expires + window <= now {
+                continue;
+            }
+            let mut shard = self.shard(&key).lock().unwrap();
+            if let Some(evicted) = shard.insert(key, entry) {
+                self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+                count += 1;
+            }
+        }
+        return Ok(count);
+    }
+}
+
+fn unix_now() -> i64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+}
+
+fn encode_entry(data: &mut Vec<u8>, key: &Key, entry: &Entry, now: Instant, unix_now: i64) {
+    let expires = match entry.expires.checked_duration_since(now) {
+        Some(left) => unix_now + left.as_secs() as i64,
+        None => unix_now - now.duration_since(entry.expires).as_secs() as i64,
+    };
+    let mut flags = 0;
+    if key.typ.is_some() {
+        flags |= HAS_TYPE;
+    }
+    if entry.negative {
+        flags |= NEGATIVE;
+    }
+
+    data.extend_from_slice(&key.name.encode());
+    data.push(flags);
+    data.extend_from_slice(&key.typ.unwrap_or(0).to_be_bytes());
+    data.extend_from_slice(&key.class.to_be_bytes());
+    data.push(entry.trust as u8);
+    data.extend_from_slice(&entry.ttl.to_be_bytes());
+    data.extend_from_slice(&expires.to_be_bytes());
+    data.extend_from_slice(&(entry.records.len() as u16).to_be_bytes());
+    for rr in entry.records.iter() {
+        data.extend_from_slice(&rr.encode());
+    }
+}
+
+fn decode_entry(
+    data: &[u8],
+    offset: usize,
+    now: Instant,
+    unix_now: i64,
+) -> Result<(Key, Option<Entry>, usize), Error> {
+    let (name, mut pos) = DomainName::decode(data, offset)?;
+    if pos + 20 > data.len() {
+        return Err(Error::msg("the cache entry is incomplete"));
+    }
+    let flags = data[pos];
+    let typ = u16::from_be_bytes(data[pos + 1..pos + 3].try_into()?);
+    let class = u16::from_be_bytes(data[pos + 3..pos + 5].try_into()?);
+    let trust = match data[pos + 5] {
+        0 => Trust::Additional,
+        1 => Trust::Answer,
+        2 => Trust::AuthAuthority,
+        3 => Trust::AuthAnswer,
+        other => return Err(Error::msg(format!("unknown trust {}", other))),
+    };
+    let ttl = u32::from_be_bytes(data[pos + 6..pos + 10].try_into()?);
+    let expires = i64::from_be_bytes(data[pos + 10..pos + 18].try_into()?);
+    let count = u16::from_be_bytes(data[pos + 18..pos + 20].try_into()?);
+    pos += 20;
+
+    let mut records = Vec::<ResourceRecord>::new();
+    for _ in 0..count {
+        let (rr, next) = ResourceRecord::decode(data, pos)?;
+        records.push(rr);
+        pos = next;
+    }
+
+    let key = Key {
+        name,
+        typ: (flags & HAS_TYPE != 0).then_some(typ),
+        class,
+    };
+    let left = expires - unix_now;
+    let expires = if left >= 0 {
+        Some(now + Duration::from_secs(left as u64))
+    } else {
+        // `None` when it expired before the monotonic clock began
+        now.checked_sub(Duration::from_secs(left.unsigned_abs()))
+    };
+    let entry = expires.map(|expires| Entry {
+        records,
+        negative: flags & NEGATIVE != 0,
+        trust,
+        ttl,
+        expires,
+    });
+    return Ok((key, entry, pos));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{question::Question, rcode::Rcode, rtype};
+    use std::net::Ipv4Addr;
+
+    fn question(name: &str, typ: u16) -> Question {
+        return Question::from_parts(name.parse().unwrap(), typ, rtype::CLASS_IN);
+    }
+
+    #[test]
+    pub fn test_cache_save_and_load() {
+        let cache = Cache::new(1 << 20);
+        let now = Instant::now();
+        let mut rr = ResourceRecord::new();
+        rr.with_name("www.example.")
+            .unwrap()
+            .with_type(rtype::A)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(300)
+            .with_rdata(Ipv4Addr::new(192, 0, 2, 1));
+        cache.insert_at(&[rr.clone()], Trust::AuthAnswer, now);
+        rr.with_name("old.example.").unwrap();
+        cache.insert_at(&[rr], Trust::Answer, now);
+        let mut soa = ResourceRecord::new();
+        let mut rdata = vec![0, 0];
+        rdata.extend([0; 16]);
+        rdata.extend(60_u32.to_be_bytes());
+        soa.with_name("example.")
+            .unwrap()
+            .with_type(rtype::SOA)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(60)
+            .with_raw_rdata(rdata);
+        let missing: DomainName = "missing.example.".parse().unwrap();
+        cache.insert_negative_at(&missing, None, rtype::CLASS_IN, &soa, Trust::Answer, now);
+
+        let (data, count) = cache.encode_at(now, 1_000_000);
+        assert_eq!(3, count);
+
+        // restarted 100 seconds later
+        let mut restarted = Cache::new(1 << 20);
+        restarted.with_stale_window(0);
+        let later = now + Duration::from_secs(5);
+        assert_eq!(2, restarted.decode_at(&data, later, 1_000_100).unwrap());
+        let reply = restarted
+            .answer_at(&question("www.example.", rtype::A), later)
+            .unwrap();
+        assert_eq!(200, reply.answers().iter().next().unwrap().ttl());
+        let rrset = restarted.get_at(
+            &"www.example.".parse().unwrap(),
+            rtype::A,
+            rtype::CLASS_IN,
+            Trust::AuthAnswer,
+            later,
+        );
+        assert!(rrset.is_some());
+        // the NXDOMAIN expired during the restart
+        assert!(restarted
+            .answer_at(&question("missing.example.", rtype::A), later)
+            .is_none());
+
+        // with a stale window it is kept
+        let restarted = Cache::new(1 << 20);
+        assert_eq!(3, restarted.decode_at(&data, later, 1_000_100).unwrap());
+        let reply = restarted
+            .answer_stale(&question("missing.example.", rtype::A))
+            .unwrap();
+        assert_eq!(Rcode::NXDomain, reply.rcode());
+    }
+
+    #[test]
+    pub fn test_cache_load_invalid() {
+        let cache = Cache::new(1 << 20);
+        let now = Instant::now();
+        assert!(cache.decode_at(b"garbage", now, 0).is_err());
+
+        let (mut data, _) = cache.encode_at(now, 0);
+        data[MAGIC.len() + 1] = 99;
+        let err = cache.decode_at(&data, now, 0).unwrap_err();
+        assert_eq!("unsupported cache file version 99", err.to_string());
+
+        // a truncated entry loads nothing
+        let mut rr = ResourceRecord::new();
+        rr.with_name("www.example.").unwrap().with_ttl(300);
+        cache.insert_at(&[rr.clone()], Trust::Answer, now);
+        rr.with_name("mail.example.").unwrap();
+        cache.insert_at(&[rr], Trust::Answer, now);
+        let (data, _) = cache.encode_at(now, 0);
+        let empty = Cache::new(1 << 20);
+        assert!(empty.decode_at(&data[..data.len() - 3], now, 0).is_err());
+        assert!(empty.is_empty());
+        assert_eq!(2, empty.decode_at(&data, now, 0).unwrap());
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    mem::size_of,
+};
+
+use super::{Entry, Key};
+use crate::dns::answer::ResourceRecord;
+
+// One part of the cache behind its own lock. Entries are evicted least recently used
+// first once their estimated size exceeds `limit` bytes.
+#[derive(Debug)]
+pub(super) struct Shard {
+    map: HashMap<Key, Slot>,
+    // last use of every entry, oldest first
+    order: BTreeMap<u64, Key>,
+    tick: u64,
+    memory: usize,
+    limit: usize,
+}
+
+#[derive(Debug)]
+struct Slot {
+    entry: Entry,
+    used: u64,
+    cost: usize,
+}
+
+impl Shard {
+    pub(super) fn new(limit: usize) -> Self {
+        return Shard {
+            map: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            memory: 0,
+            limit,
+        };
+    }
+
+    pub(super) fn len(&self) -> usize {
+        return self.map.len();
+    }
+
+    // Estimated bytes used by the entries.
+    pub(super) fn memory(&self) -> usize {
+        return self.memory;
+    }
+
+    // The entry under `key` without marking it as used.
+    pub(super) fn peek(&self, key: &Key) -> Option<&Entry> {
+        return self.map.get(key).map(|slot| &slot.entry);
+    }
+
+    // The entry under `key`, which becomes the most recently used one.
+    pub(super) fn get(&mut self, key: &Key) -> Option<&Entry> {
+        let tick = self.next_tick();
+        let slot = self.map.get_mut(key)?;
+        self.order.remove(&slot.used);
+        self.order.insert(tick, key.clone());
+        slot.used = tick;
+        return Some(&slot.entry);
+    }
+
+    // Store `entry`, evicting the least recently used entries until it fits. Returns the
+    // number of evicted entries, `None` when the entry is larger than the whole shard.
+    pub(super) fn insert(&mut self, key: Key, entry: Entry) -> Option<usize> {
+        let cost = cost(&key, &entry);
+        if cost > self.limit {
+            return None;
+        }
+        self.remove(&key);
+
+        let mut evicted = 0;
+        while self.memory + cost > self.limit {
+            let oldest = match self.order.first_key_value() {
+                Some((_, oldest)) => oldest.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+            evicted += 1;
+        }
+
+        let tick = self.next_tick();
+        self.order.insert(tick, key.clone());
+        self.memory += cost;
+        let slot = Slot {
+            entry,
+            used: tick,
+            cost,
+        };
+        self.map.insert(key, slot);
+
+        return Some(evicted);
+    }
+
+    // Every entry, least recently used first.
+    pub(super) fn entries(&self) -> impl Iterator<Item = (&Key, &Entry)> {
+        return self
+            .order
+            .values()
+            .filter_map(|key| self.map.get(key).map(|slot| (key, &slot.entry)));
+    }
+
+    pub(super) fn remove(&mut self, key: &Key) {
+        if let Some(slot) = self.map.remove(key) {
+            self.order.remove(&slot.used);
+            self.memory -= slot.cost;
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        return self.tick;
+    }
+}
+
+// What an entry takes on the heap and in the two maps, close enough to bound the memory
+// of the cache.
+fn cost(key: &Key, entry: &Entry) -> usize {
+    let records: usize = entry
+        .records
+        .iter()
+        .map(|rr| size_of::<ResourceRecord>() + rr.name().wire_length() + rr.rdata().len())
+        .sum();
+    return 2 * size_of::<Key>()
+        + key.name.wire_length()
+        + size_of::<Slot>()
+        + size_of::<u64>()
+        + records;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::Trust, dns::rtype};
+    use std::time::Instant;
+
+    fn key(name: &str) -> Key {
+        return Key {
+            name: name.parse().unwrap(),
+            typ: Some(rtype::A),
+            class: rtype::CLASS_IN,
+        };
+    }
+
+    fn entry() -> Entry {
+        let mut rr = ResourceRecord::new();
+        rr.with_name("www.example.")
+            .unwrap()
+            .with_raw_rdata(vec![192, 0, 2, 1]);
+        return Entry {
+            records: vec![rr],
+            negative: false,
+            trust: Trust::Answer,
+            ttl: 300,
+            expires: Instant::now(),
+        };
+    }
+
+    #[test]
+    pub fn test_shard_lru() {
+        let size = cost(&key("a.example."), &entry());
+        let mut shard = Shard::new(3 * size);
+        assert_eq!(Some(0), shard.insert(key("a.example."), entry()));
+        assert_eq!(Some(0), shard.insert(key("b.example."), entry()));
+        assert_eq!(Some(0), shard.insert(key("c.example."), entry()));
+        assert_eq!(3 * size, shard.memory());
+
+        // a is used, b is the oldest now
+        assert!(shard.get(&key("a.example.")).is_some());
+        assert_eq!(Some(1), shard.insert(key("d.example."), entry()));
+        assert!(shard.peek(&key("b.example.")).is_none());
+        assert!(shard.peek(&key("a.example.")).is_some());
+        assert_eq!(3, shard.len());
+
+        // replacing an entry evicts nothing
+        assert_eq!(Some(0), shard.insert(key("d.example."), entry()));
+        shard.remove(&key("d.example."));
+        assert_eq!(2 * size, shard.memory());
+
+        assert_eq!(
+            None,
+            Shard::new(size - 1).insert(key("a.example."), entry())
+        );
+    }
+}
@@ -0,0 +1,943 @@
+pub mod toml;
+
+use anyhow::Error;
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    cache,
+    dns::DomainName,
+    log::Level,
+    resolver::{
+        cached, coalesce, forward,
+        recursive::{self, Recursor},
+    },
+    server::{
+        acl::{Acl, Network},
+        listener::Listener,
+        pool::DEFAULT_QUEUE_SIZE,
+        tcp::TcpOptions,
+        udp::UdpOptions,
+        Transport,
+    },
+};
+use toml::{Table, Value};
+
+// Port of upstream servers given without one.
+pub const DNS_PORT: u16 = 53;
+// Memory per record assumed for the deprecated `cache.size`, which counted records.
+pub const CACHE_BYTES_PER_RECORD: usize = 256;
+
+// Targets with the socket calls for several UDP sockets per address and batching.
+const MULTI_SOCKET: bool = cfg!(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+));
+
+// Server configuration, read from a TOML file. Every key is optional and the defaults
+// are what the server does without a file.
+#[derive(Debug, Clone)]
+pub struct Config {
+    listeners: Vec<Listener>,
+    limits: Limits,
+    log_level: Level,
+    acl: Acl,
+    cache_max_memory: usize,
+    cache_shards: usize,
+    cache_min_ttl: u32,
+    cache_max_ttl: u32,
+    cache_max_negative_ttl: u32,
+    cache_stale_window: u32,
+    cache_stale_answer_timeout: Duration,
+    cache_prefetch: u32,
+    cache_file: Option<PathBuf>,
+    cache_save_interval: Duration,
+    forwarders: Vec<SocketAddr>,
+    forward_timeout: Duration,
+    forward_retries: usize,
+    coalesce_timeout: Duration,
+    max_waiters: usize,
+    recursion: bool,
+    root_hints: Vec<SocketAddr>,
+    recursion_timeout: Duration,
+    zones: Vec<Zone>,
+    warnings: Vec<String>,
+}
+
+// Sizes of the worker pool and of the listeners.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    workers: usize,
+    queue_size: usize,
+    udp: UdpOptions,
+    tcp: TcpOptions,
+}
+
+// A zone served from a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    name: DomainName,
+    file: PathBuf,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        let mut udp = UdpOptions::new();
+        if MULTI_SOCKET {
+            udp.with_socket_per_core().with_batch_size(32);
+        }
+
+        return Config {
+            listeners: Listener::defaults(),
+            limits: Limits {
+                workers: default_workers(),
+                queue_size: DEFAULT_QUEUE_SIZE,
+                udp,
+                tcp: TcpOptions::new(),
+            },
+            log_level: Level::Info,
+            acl: Acl::new(),
+            cache_max_memory: cache::DEFAULT_MAX_MEMORY,
+            cache_shards: cache::DEFAULT_SHARDS,
+            cache_min_ttl: cache::DEFAULT_MIN_TTL,
+            cache_max_ttl: cache::DEFAULT_MAX_TTL,
+            cache_max_negative_ttl: cache::DEFAULT_MAX_NEGATIVE_TTL,
+            cache_stale_window: cache::DEFAULT_STALE_WINDOW,
+            cache_stale_answer_timeout: cached::DEFAULT_STALE_ANSWER_TIMEOUT,
+            cache_prefetch: cache::DEFAULT_PREFETCH,
+            cache_file: None,
+            cache_save_interval: Duration::ZERO,
+            forwarders: vec![],
+            forward_timeout: forward::DEFAULT_TIMEOUT,
+            forward_retries: forward::DEFAULT_RETRIES,
+            coalesce_timeout: coalesce::DEFAULT_TIMEOUT,
+            max_waiters: coalesce::DEFAULT_MAX_WAITERS,
+            recursion: false,
+            root_hints: Recursor::root_hints(),
+            recursion_timeout: recursive::DEFAULT_TIMEOUT,
+            zones: vec![],
+            warnings: vec![],
+        };
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::msg(format!("{}: {}", path.display(), e)))?;
+        return Config::parse(&text).map_err(|e| Error::msg(format!("{}: {}", path.display(), e)));
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let root = toml::parse(text)?;
+        let root = Section::root(&root);
+        root.check_keys(&[
+            "listeners",
+            "limits",
+            "logging",
+            "acl",
+            "cache",
+            "forwarding",
+            "recursion",
+            "zones",
+        ])?;
+        let mut config = Config::new();
+
+        if let Some(listeners) = root.tables("listeners")? {
+            config.listeners.clear();
+            for listener in listeners {
+                listener.check_keys(&["address", "transport"])?;
+                let addr: SocketAddr = listener
+                    .parse("address")?
+                    .ok_or_else(|| listener.missing("address"))?;
+                let transports = match listener.string("transport")? {
+                    Some("udp") => vec![Transport::Udp],
+                    Some("tcp") => vec![Transport::Tcp],
+                    None | Some("both") => vec![Transport::Udp, Transport::Tcp],
+                    Some(_) => {
+                        return Err(
+                            listener.error("transport", "expected \"udp\", \"tcp\" or \"both\"")
+                        )
+                    }
+                };
+                for transport in transports {
+                    let new = Listener::new(addr, transport);
+                    if config.listeners.contains(&new) {
+                        return Err(listener.error("address", &format!("{} is listed twice", new)));
+                    }
+                    config.listeners.push(new);
+                }
+            }
+            if config.listeners.is_empty() {
+                return Err(root.error("listeners", "at least one listener is needed"));
+            }
+        }
+
+        if let Some(limits) = root.table("limits")? {
+            limits.check_keys(&[
+                "workers",
+                "queue_size",
+                "udp_sockets",
+                "udp_batch_size",
+                "udp_recv_buffer",
+                "udp_send_buffer",
+                "tcp_idle_timeout",
+                "tcp_max_connections",
+                "tcp_max_pipelined",
+            ])?;
+            let l = &mut config.limits;
+            if let Some(workers) = limits.integer("workers", 0, 4096)? {
+                l.workers = if workers == 0 {
+                    default_workers()
+                } else {
+                    workers
+                };
+            }
+            if let Some(size) = limits.integer("queue_size", 1, 1 << 20)? {
+                l.queue_size = size;
+            }
+            if let Some(sockets) = limits.integer("udp_sockets", 0, 1024)? {
+                match sockets {
+                    0 => l.udp.with_socket_per_core(),
+                    _ => l.udp.with_sockets(sockets),
+                };
+            }
+            if let Some(batch) = limits.integer("udp_batch_size", 1, 1024)? {
+                l.udp.with_batch_size(batch);
+            }
+            if let Some(size) = limits.integer("udp_recv_buffer", 1, i32::MAX as usize)? {
+                l.udp.with_recv_buffer(size);
+            }
+            if let Some(size) = limits.integer("udp_send_buffer", 1, i32::MAX as usize)? {
+                l.udp.with_send_buffer(size);
+            }
+            if let Some(secs) = limits.integer("tcp_idle_timeout", 1, 3600)? {
+                l.tcp.with_idle_timeout(Duration::from_secs(secs as u64));
+            }
+            if let Some(max) = limits.integer("tcp_max_connections", 1, 1 << 20)? {
+                l.tcp.with_max_connections(max);
+            }
+            if let Some(max) = limits.integer("tcp_max_pipelined", 1, 1024)? {
+                l.tcp.with_max_pipelined(max);
+            }
+            if !MULTI_SOCKET && l.udp.sockets() > 1 {
+                return Err(limits.error("udp_sockets", "more than one socket needs Linux"));
+            }
+        }
+
+        if let Some(logging) = root.table("logging")? {
+            logging.check_keys(&["level"])?;
+            if let Some(level) = logging.parse("level")? {
+                config.log_level = level;
+            }
+        }
+
+        if let Some(acl) = root.table("acl")? {
+            acl.check_keys(&["allow", "deny"])?;
+            for net in acl.parse_list::<Network>("allow")?.unwrap_or_default() {
+                config.acl.with_allow(net);
+            }
+            for net in acl.parse_list::<Network>("deny")?.unwrap_or_default() {
+                config.acl.with_deny(net);
+            }
+        }
+
+        if let Some(cache) = root.table("cache")? {
+            cache.check_keys(&[
+                "size",
+                "max_memory",
+                "shards",
+                "min_ttl",
+                "max_ttl",
+                "max_negative_ttl",
+                "stale_window",
+                "stale_answer_timeout",
+                "prefetch",
+                "file",
+                "save_interval",
+            ])?;
+            if let Some(records) = cache.integer("size", 0, 1 << 30)? {
+                let msg = if cache.table.get("max_memory").is_some() {
+                    "is deprecated and ignored next to max_memory".to_string()
+                } else {
+                    config.cache_max_memory = records * CACHE_BYTES_PER_RECORD;
+                    format!(
+                        "is deprecated, use max_memory; taken as {} bytes",
+                        config.cache_max_memory
+                    )
+                };
+                config.warnings.push(cache.error("size", &msg).to_string());
+            }
+            if let Some(bytes) = cache.integer("max_memory", 0, 1 << 40)? {
+                config.cache_max_memory = bytes;
+            }
+            if let Some(shards) = cache.integer("shards", 1, 1024)? {
+                config.cache_shards = shards;
+            }
+            if let Some(ttl) = cache.integer("min_ttl", 0, u32::MAX as usize)? {
+                config.cache_min_ttl = ttl as u32;
+            }
+            if let Some(ttl) = cache.integer("max_ttl", 0, u32::MAX as usize)? {
+                config.cache_max_ttl = ttl as u32;
+            }
+            if let Some(ttl) = cache.integer("max_negative_ttl", 0, u32::MAX as usize)? {
+                config.cache_max_negative_ttl = ttl as u32;
+            }
+            if let Some(window) = cache.integer("stale_window", 0, u32::MAX as usize)? {
+                config.cache_stale_window = window as u32;
+            }
+            if let Some(ms) = cache.integer("stale_answer_timeout", 0, 60_000)? {
+                config.cache_stale_answer_timeout = Duration::from_millis(ms as u64);
+            }
+            if let Some(percent) = cache.integer("prefetch", 0, 99)? {
+                config.cache_prefetch = percent as u32;
+            }
+            if let Some(file) = cache.string("file")? {
+                config.cache_file = Some(PathBuf::from(file));
+            }
+            if let Some(secs) = cache.integer("save_interval", 0, 86400)? {
+                config.cache_save_interval = Duration::from_secs(secs as u64);
+            }
+            if config.cache_min_ttl > config.cache_max_ttl {
+                return Err(cache.error("min_ttl", "must not be larger than max_ttl"));
+            }
+        }
+
+        if let Some(forwarding) = root.table("forwarding")? {
+            forwarding.check_keys(&[
+                "upstreams",
+                "timeout",
+                "retries",
+                "coalesce_timeout",
+                "max_waiters",
+            ])?;
+            config.forwarders = forwarding
+                .parse_list::<Upstream>("upstreams")?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|upstream| upstream.addr())
+                .collect();
+            if let Some(ms) = forwarding.integer("timeout", 1, 60_000)? {
+                config.forward_timeout = Duration::from_millis(ms as u64);
+            }
+            if let Some(retries) = forwarding.integer("retries", 0, 10)? {
+                config.forward_retries = retries;
+            }
+            if let Some(ms) = forwarding.integer("coalesce_timeout", 1, 60_000)? {
+                config.coalesce_timeout = Duration::from_millis(ms as u64);
+            }
+            if let Some(waiters) = forwarding.integer("max_waiters", 0, 65_536)? {
+                config.max_waiters = waiters;
+            }
+        }
+
+        if let Some(recursion) = root.table("recursion")? {
+            recursion.check_keys(&["enabled", "root_hints", "timeout"])?;
+            if let Some(enabled) = recursion.boolean("enabled")? {
+                config.recursion = enabled;
+            }
+            if let Some(hints) = recursion.parse_list::<Upstream>("root_hints")? {
+                if hints.is_empty() {
+                    return Err(recursion.error("root_hints", "at least one server is needed"));
+                }
+                config.root_hints = hints.iter().map(|hint| hint.addr()).collect();
+            }
+            if let Some(ms) = recursion.integer("timeout", 1, 60_000)? {
+                config.recursion_timeout = Duration::from_millis(ms as u64);
+            }
+            if config.recursion && !config.forwarders.is_empty() {
+                return Err(recursion.error("enabled", "cannot be combined with forwarding"));
+            }
+        }
+
+        for zone in root.tables("zones")?.unwrap_or_default() {
+            zone.check_keys(&["name", "file"])?;
+            let name: DomainName = zone.parse("name")?.ok_or_else(|| zone.missing("name"))?;
+            let file = zone.string("file")?.ok_or_else(|| zone.missing("file"))?;
+            if config.zones.iter().any(|z| z.name == name) {
+                return Err(zone.error("name", &format!("zone {} is listed twice", name)));
+            }
+            config.zones.push(Zone {
+                name,
+                file: PathBuf::from(file),
+            });
+        }
+
+        return Ok(config);
+    }
+
+    pub fn listeners(&self) -> &[Listener] {
+        return &self.listeners;
+    }
+
+    pub fn with_listeners(&mut self, listeners: Vec<Listener>) -> &mut Self {
+        self.listeners = listeners;
+        return self;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        return &self.limits;
+    }
+
+    pub fn log_level(&self) -> Level {
+        return self.log_level;
+    }
+
+    pub fn acl(&self) -> &Acl {
+        return &self.acl;
+    }
+
+    // Bytes the cache may use, 0 disables it.
+    pub fn cache_max_memory(&self) -> usize {
+        return self.cache_max_memory;
+    }
+
+    pub fn cache_shards(&self) -> usize {
+        return self.cache_shards;
+    }
+
+    // Bounds the TTLs of cached records are clamped to, in seconds.
+    pub fn cache_min_ttl(&self) -> u32 {
+        return self.cache_min_ttl;
+    }
+
+    pub fn cache_max_ttl(&self) -> u32 {
+        return self.cache_max_ttl;
+    }
+
+    // How long NXDOMAIN and NODATA replies are cached at most, 0 turns it off.
+    pub fn cache_max_negative_ttl(&self) -> u32 {
+        return self.cache_max_negative_ttl;
+    }
+
+    // Seconds expired data is still answered when resolving fails, 0 turns it off.
+    pub fn cache_stale_window(&self) -> u32 {
+        return self.cache_stale_window;
+    }
+
+    // How long clients wait for fresh data before expired data is answered.
+    pub fn cache_stale_answer_timeout(&self) -> Duration {
+        return self.cache_stale_answer_timeout;
+    }
+
+    // Percent of the TTL left when a queried answer is refreshed ahead, 0 turns it off.
+    pub fn cache_prefetch(&self) -> u32 {
+        return self.cache_prefetch;
+    }
+
+    // Where the cache is saved on shutdown and loaded from at startup, none keeps it in
+    // memory only.
+    pub fn cache_file(&self) -> Option<&Path> {
+        return self.cache_file.as_deref();
+    }
+
+    // How often the cache is saved while running as well, zero saves on shutdown only.
+    pub fn cache_save_interval(&self) -> Duration {
+        return self.cache_save_interval;
+    }
+
+    // Upstream servers queries are forwarded to, none means no forwarding.
+    pub fn forwarders(&self) -> &[SocketAddr] {
+        return &self.forwarders;
+    }
+
+    pub fn with_forwarders(&mut self, forwarders: Vec<SocketAddr>) -> &mut Self {
+        self.forwarders = forwarders;
+        return self;
+    }
+
+    // Resolve from the root servers instead of forwarding, the two exclude each other.
+    pub fn recursion(&self) -> bool {
+        return self.recursion;
+    }
+
+    pub fn with_recursion(&mut self, recursion: bool) -> &mut Self {
+        self.recursion = recursion;
+        return self;
+    }
+
+    pub fn root_hints(&self) -> &[SocketAddr] {
+        return &self.root_hints;
+    }
+
+    // How long to wait for the reply of one name server while recursing.
+    pub fn recursion_timeout(&self) -> Duration {
+        return self.recursion_timeout;
+    }
+
+    // How long to wait for one upstream reply.
+    pub fn forward_timeout(&self) -> Duration {
+        return self.forward_timeout;
+    }
+
+    // Attempts after the first one, each goes to the next upstream.
+    pub fn forward_retries(&self) -> usize {
+        return self.forward_retries;
+    }
+
+    // How long a query waits for an identical upstream query that is running already.
+    pub fn coalesce_timeout(&self) -> Duration {
+        return self.coalesce_timeout;
+    }
+
+    // Queries waiting for one upstream query at most, 0 turns coalescing off.
+    pub fn max_waiters(&self) -> usize {
+        return self.max_waiters;
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        return &self.zones;
+    }
+
+    // Problems that do not stop the file from being used, like deprecated keys.
+    pub fn warnings(&self) -> &[String] {
+        return &self.warnings;
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Limits {
+    pub fn workers(&self) -> usize {
+        return self.workers;
+    }
+
+    pub fn queue_size(&self) -> usize {
+        return self.queue_size;
+    }
+
+    pub fn udp(&self) -> &UdpOptions {
+        return &self.udp;
+    }
+
+    pub fn tcp(&self) -> &TcpOptions {
+        return &self.tcp;
+    }
+}
+
+impl Zone {
+    pub fn name(&self) -> &DomainName {
+        return &self.name;
+    }
+
+    pub fn file(&self) -> &Path {
+        return &self.file;
+    }
+}
+
+fn default_workers() -> usize {
+    return thread::available_parallelism().map_or(1, |n| n.get());
+}
+
+// An upstream server address, the port defaults to 53.
+pub struct Upstream(SocketAddr);
+
+impl Upstream {
+    pub fn addr(&self) -> SocketAddr {
+        return self.0;
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = SocketAddr::from_str(s) {
+            return Ok(Upstream(addr));
+        }
+        return match IpAddr::from_str(s) {
+            Ok(ip) => Ok(Upstream(SocketAddr::new(ip, DNS_PORT))),
+            Err(_) => Err(Error::msg(format!("invalid address {:?}", s))),
+        };
+    }
+}
+
+// A table of the file together with its path, for error messages that name the key.
+struct Section<'a> {
+    table: &'a Table,
+    path: String,
+}
+
+impl<'a> Section<'a> {
+    fn root(table: &'a Table) -> Self {
+        return Section {
+            table,
+            path: String::new(),
+        };
+    }
+
+    fn key_path(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            return key.to_string();
+        }
+        return format!("{}.{}", self.path, key);
+    }
+
+    fn error(&self, key: &str, msg: &str) -> Error {
+        let line = self.table.line(key);
+        if line == 0 {
+            return Error::msg(format!("{}: {}", self.key_path(key), msg));
+        }
+        return Error::msg(format!("{} (line {}): {}", self.key_path(key), line, msg));
+    }
+
+    fn missing(&self, key: &str) -> Error {
+        return Error::msg(format!("{}: missing", self.key_path(key)));
+    }
+
+    fn check_keys(&self, known: &[&str]) -> Result<(), Error> {
+        match self
+            .table
+            .iter()
+            .find(|(key, _, _)| !known.contains(&key.as_str()))
+        {
+            Some((key, _, _)) => return Err(self.error(key, "unknown key")),
+            None => return Ok(()),
+        }
+    }
+
+    fn mismatch(&self, key: &str, expected: &str, value: &Value) -> Error {
+        return self.error(
+            key,
+            &format!("expected {}, found {}", expected, value.type_name()),
+        );
+    }
+
+    fn table(&self, key: &str) -> Result<Option<Section<'a>>, Error> {
+        match self.table.get(key) {
+            None => return Ok(None),
+            Some(Value::Table(table)) => {
+                return Ok(Some(Section {
+                    table,
+                    path: self.key_path(key),
+                }))
+            }
+            Some(value) => return Err(self.mismatch(key, "a table", value)),
+        }
+    }
+
+    fn tables(&self, key: &str) -> Result<Option<Vec<Section<'a>>>, Error> {
+        let values = match self.table.get(key) {
+            None => return Ok(None),
+            Some(Value::Array(values)) => values,
+            Some(value) => return Err(self.mismatch(key, "an array of tables", value)),
+        };
+        let mut tables = Vec::<Section>::new();
+        for (i, value) in values.iter().enumerate() {
+            match value {
+                Value::Table(table) => tables.push(Section {
+                    table,
+                    path: format!("{}[{}]", self.key_path(key), i),
+                }),
+                value => return Err(self.mismatch(key, "an array of tables", value)),
+            }
+        }
+        return Ok(Some(tables));
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'a str>, Error> {
+        match self.table.get(key) {
+            None => return Ok(None),
+            Some(Value::String(s)) => return Ok(Some(s)),
+            Some(value) => return Err(self.mismatch(key, "a string", value)),
+        }
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, Error> {
+        match self.table.get(key) {
+            None => return Ok(None),
+            Some(Value::Boolean(b)) => return Ok(Some(*b)),
+            Some(value) => return Err(self.mismatch(key, "a boolean", value)),
+        }
+    }
+
+    fn integer(&self, key: &str, min: usize, max: usize) -> Result<Option<usize>, Error> {
+        let n = match self.table.get(key) {
+            None => return Ok(None),
+            Some(Value::Integer(n)) => *n,
+            Some(value) => return Err(self.mismatch(key, "an integer", value)),
+        };
+        if n < min as i64 || n > max as i64 {
+            return Err(self.error(key, &format!("must be between {} and {}", min, max)));
+        }
+        return Ok(Some(n as usize));
+    }
+
+    fn parse<T>(&self, key: &str) -> Result<Option<T>, Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.string(key)? {
+            None => return Ok(None),
+            Some(s) => {
+                return T::from_str(s)
+                    .map(Some)
+                    .map_err(|e| self.error(key, &e.to_string()))
+            }
+        }
+    }
+
+    fn parse_list<T>(&self, key: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let values = match self.table.get(key) {
+            None => return Ok(None),
+            Some(Value::Array(values)) => values,
+            Some(value) => return Err(self.mismatch(key, "an array of strings", value)),
+        };
+        let mut items = Vec::<T>::new();
+        for (i, value) in values.iter().enumerate() {
+            let item = match value {
+                Value::String(s) => T::from_str(s).map_err(|e| e.to_string()),
+                value => Err(format!("expected a string, found {}", value.type_name())),
+            };
+            match item {
+                Ok(item) => items.push(item),
+                Err(e) => return Err(self.error(key, &format!("item {}: {}", i, e))),
+            }
+        }
+        return Ok(Some(items));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_config_defaults() {
+        let config = Config::parse("").unwrap();
+        let defaults = Config::new();
+        assert_eq!(Listener::defaults(), config.listeners());
+        assert_eq!(DEFAULT_QUEUE_SIZE, config.limits().queue_size());
+        assert_eq!(defaults.limits().workers(), config.limits().workers());
+        assert_eq!(
+            defaults.limits().udp().batch_size(),
+            config.limits().udp().batch_size()
+        );
+        assert_eq!(
+            TcpOptions::new().idle_timeout(),
+            config.limits().tcp().idle_timeout()
+        );
+        assert_eq!(Level::Info, config.log_level());
+        assert!(config.acl().allows("192.0.2.1".parse().unwrap()));
+        assert!(config.forwarders().is_empty());
+        assert!(!config.recursion());
+        assert_eq!(Recursor::root_hints(), config.root_hints());
+        assert!(config.zones().is_empty());
+    }
+
+    #[test]
+    pub fn test_config_example() {
+        let config = Config::parse(include_str!("../../config.example.toml")).unwrap();
+        assert_eq!(Listener::defaults(), config.listeners());
+        assert_eq!(cache::DEFAULT_MAX_MEMORY, config.cache_max_memory());
+        assert!(config.warnings().is_empty());
+    }
+
+    #[test]
+    pub fn test_config_deprecated_cache_size() {
+        let config = Config::parse("[cache]\nsize = 1000\n").unwrap();
+        assert_eq!(1000 * CACHE_BYTES_PER_RECORD, config.cache_max_memory());
+        assert_eq!(
+            vec!["cache.size (line 2): is deprecated, use max_memory; taken as 256000 bytes"],
+            config.warnings()
+        );
+
+        let config = Config::parse("[cache]\nsize = 1000\nmax_memory = 4096\n").unwrap();
+        assert_eq!(4096, config.cache_max_memory());
+        assert_eq!(1, config.warnings().len());
+        assert!(config.warnings()[0].ends_with("ignored next to max_memory"));
+    }
+
+    #[test]
+    pub fn test_config_parse() {
+        let config = Config::parse(
+            r#"
+[[listeners]]
+address = "[::]:53"
+transport = "udp"
+
+[[listeners]]
+address = "0.0.0.0:53"
+
+[limits]
+workers = 2
+queue_size = 64
+udp_batch_size = 1
+tcp_idle_timeout = 5
+tcp_max_pipelined = 4
+
+[logging]
+level = "debug"
+
+[acl]
+allow = ["127.0.0.0/8", "::1"]
+deny = ["127.0.0.2"]
+
+[cache]
+max_memory = 1_048_576
+shards = 4
+min_ttl = 30
+max_ttl = 3600
+max_negative_ttl = 900
+stale_window = 7200
+stale_answer_timeout = 1000
+prefetch = 10
+file = "/var/cache/dns/cache.bin"
+save_interval = 600
+
+[forwarding]
+upstreams = ["192.0.2.53", "[2001:db8::53]:5353"]
+timeout = 500
+retries = 0
+coalesce_timeout = 3000
+max_waiters = 0
+
+[[zones]]
+name = "example.com"
+file = "zones/example.com.zone"
+"#,
+        )
+        .unwrap();
+
+        let listeners: Vec<String> = config.listeners().iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            vec!["udp:[::]:53", "udp:0.0.0.0:53", "tcp:0.0.0.0:53"],
+            listeners
+        );
+        assert_eq!(2, config.limits().workers());
+        assert_eq!(64, config.limits().queue_size());
+        assert_eq!(1, config.limits().udp().batch_size());
+        assert_eq!(Duration::from_secs(5), config.limits().tcp().idle_timeout());
+        assert_eq!(4, config.limits().tcp().max_pipelined());
+        assert_eq!(Level::Debug, config.log_level());
+        assert!(config.acl().allows("127.0.0.1".parse().unwrap()));
+        assert!(!config.acl().allows("127.0.0.2".parse().unwrap()));
+        assert!(!config.acl().allows("192.0.2.1".parse().unwrap()));
+        assert_eq!(1 << 20, config.cache_max_memory());
+        assert_eq!(4, config.cache_shards());
+        assert_eq!(30, config.cache_min_ttl());
+        assert_eq!(3600, config.cache_max_ttl());
+        assert_eq!(900, config.cache_max_negative_ttl());
+        assert_eq!(7200, config.cache_stale_window());
+        assert_eq!(
+            Duration::from_millis(1000),
+            config.cache_stale_answer_timeout()
+        );
+        assert_eq!(10, config.cache_prefetch());
+        assert_eq!(
+            Some(Path::new("/var/cache/dns/cache.bin")),
+            config.cache_file()
+        );
+        assert_eq!(Duration::from_secs(600), config.cache_save_interval());
+        assert_eq!(
+            vec![
+                "192.0.2.53:53".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::53]:5353".parse().unwrap()
+            ],
+            config.forwarders()
+        );
+        assert_eq!(Duration::from_millis(500), config.forward_timeout());
+        assert_eq!(0, config.forward_retries());
+        assert_eq!(Duration::from_secs(3), config.coalesce_timeout());
+        assert_eq!(0, config.max_waiters());
+        assert_eq!("example.com.", config.zones()[0].name().to_string());
+        assert_eq!(
+            Path::new("zones/example.com.zone"),
+            config.zones()[0].file()
+        );
+    }
+
+    #[test]
+    pub fn test_config_errors() {
+        let cases = [
+            (
+                "[limits]\nworkers = \"2\"",
+                "limits.workers (line 2): expected an integer, found a string",
+            ),
+            (
+                "[limits]\n\nqueue_size = 0",
+                "limits.queue_size (line 3): must be between 1 and 1048576",
+            ),
+            (
+                "[limits]\nthreads = 2",
+                "limits.threads (line 2): unknown key",
+            ),
+            ("[server]\n", "server (line 1): unknown key"),
+            (
+                "[logging]\nlevel = \"loud\"",
+                r#"logging.level (line 2): unknown log level "loud", expected error, warn, info or debug"#,
+            ),
+            (
+                "[acl]\nallow = [\"10.0.0.0/8\", \"10.0.0/8\"]",
+                r#"acl.allow (line 2): item 1: invalid network "10.0.0/8": invalid IP address syntax"#,
+            ),
+            (
+                "[[listeners]]\ntransport = \"udp\"",
+                "listeners[0].address: missing",
+            ),
+            (
+                "[[listeners]]\naddress = \"127.0.0.1\"",
+                "listeners[0].address (line 2): invalid socket address syntax",
+            ),
+            (
+                "[[listeners]]\naddress = \"127.0.0.1:53\"\ntransport = \"quic\"",
+                r#"listeners[0].transport (line 3): expected "udp", "tcp" or "both""#,
+            ),
+            (
+                "[[listeners]]\naddress = \"127.0.0.1:53\"\n\
+                 [[listeners]]\naddress = \"127.0.0.1:53\"\ntransport = \"tcp\"",
+                "listeners[1].address (line 4): tcp:127.0.0.1:53 is listed twice",
+            ),
+            (
+                "listeners = []",
+                "listeners (line 1): at least one listener is needed",
+            ),
+            (
+                "[[zones]]\nname = \"a..b\"\nfile = \"x\"",
+                "zones[0].name (line 2): empty label in domain name: a..b",
+            ),
+            (
+                "[forwarding]\nupstreams = \"192.0.2.1\"",
+                "forwarding.upstreams (line 2): expected an array of strings, found a string",
+            ),
+            (
+                "[recursion]\nenabled = \"yes\"",
+                "recursion.enabled (line 2): expected a boolean, found a string",
+            ),
+            (
+                "[forwarding]\nupstreams = [\"192.0.2.1\"]\n[recursion]\nenabled = true",
+                "recursion.enabled (line 4): cannot be combined with forwarding",
+            ),
+            (
+                "[cache]\nmax_ttl = 60\nmin_ttl = 120",
+                "cache.min_ttl (line 3): must not be larger than max_ttl",
+            ),
+            (
+                "logging = 1",
+                "logging (line 1): expected a table, found an integer",
+            ),
+        ];
+        for (text, msg) in cases {
+            assert_eq!(
+                msg,
+                Config::parse(text).unwrap_err().to_string(),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,471 @@
+// A parser for the subset of TOML the configuration needs: tables, arrays of tables,
+// dotted keys, basic and literal strings, integers, booleans, arrays and inline tables.
+// Dates, floats and multi-line strings are not supported.
+use anyhow::Error;
+use std::{collections::BTreeMap, iter::Peekable, str::Chars};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        return match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+            Value::Table(_) => "a table",
+        };
+    }
+}
+
+// Keys with their values and the line each key was defined on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table(BTreeMap<String, (Value, usize)>);
+
+impl Table {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        return self.0.get(key).map(|(value, _)| value);
+    }
+
+    // Line the key was defined on, 0 for tables that were created implicitly.
+    pub fn line(&self, key: &str) -> usize {
+        return self.0.get(key).map_or(0, |(_, line)| *line);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value, usize)> {
+        return self
+            .0
+            .iter()
+            .map(|(key, (value, line))| (key, value, *line));
+    }
+
+    fn insert(&mut self, key: String, value: Value, line: usize) -> Result<(), Error> {
+        if self.0.contains_key(&key) {
+            return Err(Error::msg(format!(
+                "line {}: duplicate key `{}`",
+                line, key
+            )));
+        }
+        self.0.insert(key, (value, line));
+        return Ok(());
+    }
+
+    // The table at `path`, creating missing ones. Arrays of tables resolve to their last
+    // element.
+    fn table_mut(&mut self, path: &[String], line: usize) -> Result<&mut Table, Error> {
+        let mut table = self;
+        for key in path {
+            let entry = table
+                .0
+                .entry(key.clone())
+                .or_insert_with(|| (Value::Table(Table::new()), line));
+            table = match &mut entry.0 {
+                Value::Table(table) => table,
+                Value::Array(array) => match array.last_mut() {
+                    Some(Value::Table(table)) => table,
+                    _ => {
+                        return Err(Error::msg(format!(
+                            "line {}: `{}` is not a table",
+                            line, key
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(Error::msg(format!(
+                        "line {}: `{}` is not a table",
+                        line, key
+                    )))
+                }
+            };
+        }
+        return Ok(table);
+    }
+}
+
+pub fn parse(text: &str) -> Result<Table, Error> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+        line: 1,
+    };
+    let mut root = Table::new();
+    let mut current = Vec::<String>::new();
+    // headers seen so far, a table may only be opened once
+    let mut defined = Vec::<Vec<String>>::new();
+
+    loop {
+        parser.skip_blank(true);
+        let line = parser.line;
+        match parser.chars.peek() {
+            None => return Ok(root),
+            Some('[') => {
+                parser.chars.next();
+                let array = parser.eat('[');
+                parser.skip_blank(false);
+                let path = parser.key()?;
+                parser.skip_blank(false);
+                parser.expect(']')?;
+                if array {
+                    parser.expect(']')?;
+                }
+                parser.end_of_line()?;
+
+                let (last, parent) = path.split_last().expect("keys are never empty");
+                let parent = root.table_mut(parent, line)?;
+                if array {
+                    let entry = parent
+                        .0
+                        .entry(last.clone())
+                        .or_insert_with(|| (Value::Array(vec![]), line));
+                    match &mut entry.0 {
+                        Value::Array(tables) => tables.push(Value::Table(Table::new())),
+                        _ => {
+                            return Err(Error::msg(format!(
+                                "line {}: `{}` is not an array of tables",
+                                line,
+                                path.join(".")
+                            )))
+                        }
+                    }
+                } else {
+                    if defined.contains(&path) {
+                        return Err(Error::msg(format!(
+                            "line {}: table `{}` defined twice",
+                            line,
+                            path.join(".")
+                        )));
+                    }
+                    parent.table_mut(std::slice::from_ref(last), line)?;
+                    defined.push(path.clone());
+                }
+                current = path;
+            }
+            Some(_) => {
+                let path = parser.key()?;
+                parser.skip_blank(false);
+                parser.expect('=')?;
+                parser.skip_blank(false);
+                let value = parser.value()?;
+                parser.end_of_line()?;
+
+                let (last, parent) = path.split_last().expect("keys are never empty");
+                let table = root.table_mut(&current, line)?.table_mut(parent, line)?;
+                table.insert(last.clone(), value, line)?;
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, msg: &str) -> Result<T, Error> {
+        return Err(Error::msg(format!("line {}: {}", self.line, msg)));
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.chars.peek() == Some(&c) {
+            self.chars.next();
+            return true;
+        }
+        return false;
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Error> {
+        if !self.eat(c) {
+            return self.error(&format!("expected `{}`", c));
+        }
+        return Ok(());
+    }
+
+    // Skip spaces and comments, and newlines too when `newlines` is set.
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                '\n' if newlines => self.line += 1,
+                '#' => {
+                    while self.chars.peek().is_some_and(|c| *c != '\n') {
+                        self.chars.next();
+                    }
+                    continue;
+                }
+                _ => return,
+            }
+            self.chars.next();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.skip_blank(false);
+        match self.chars.next() {
+            None => return Ok(()),
+            Some('\n') => {
+                self.line += 1;
+                return Ok(());
+            }
+            Some(c) => return self.error(&format!("unexpected `{}` after value", c)),
+        }
+    }
+
+    // A bare, quoted or dotted key.
+    fn key(&mut self) -> Result<Vec<String>, Error> {
+        let mut path = Vec::<String>::new();
+        loop {
+            let part = match self.chars.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let mut part = String::new();
+                    while let Some(&c) = self.chars.peek() {
+                        if !(c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                            break;
+                        }
+                        part.push(c);
+                        self.chars.next();
+                    }
+                    if part.is_empty() {
+                        return self.error("expected a key");
+                    }
+                    part
+                }
+            };
+            path.push(part);
+            self.skip_blank(false);
+            if !self.eat('.') {
+                return Ok(path);
+            }
+            self.skip_blank(false);
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        match self.chars.peek() {
+            Some('"') => return Ok(Value::String(self.basic_string()?)),
+            Some('\'') => return Ok(Value::String(self.literal_string()?)),
+            Some('[') => return self.array(),
+            Some('{') => return self.inline_table(),
+            Some(_) => {}
+            None => return self.error("expected a value"),
+        }
+
+        let mut word = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+') {
+                break;
+            }
+            word.push(c);
+            self.chars.next();
+        }
+        match word.as_str() {
+            "true" => return Ok(Value::Boolean(true)),
+            "false" => return Ok(Value::Boolean(false)),
+            "" => return self.error("expected a value"),
+            _ => {}
+        }
+        let digits = word.trim_start_matches(['+', '-']);
+        if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+            return self.error(&format!("invalid value `{}`", word));
+        }
+        match word.replace('_', "").parse::<i64>() {
+            Ok(n) => return Ok(Value::Integer(n)),
+            Err(_) => return self.error(&format!("invalid value `{}`", word)),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, Error> {
+        self.expect('[')?;
+        let mut values = Vec::<Value>::new();
+        loop {
+            self.skip_blank(true);
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank(true);
+            if !self.eat(',') {
+                self.skip_blank(true);
+                self.expect(']')?;
+                return Ok(Value::Array(values));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, Error> {
+        self.expect('{')?;
+        let mut table = Table::new();
+        self.skip_blank(false);
+        if self.eat('}') {
+            return Ok(Value::Table(table));
+        }
+        loop {
+            self.skip_blank(false);
+            let line = self.line;
+            let path = self.key()?;
+            self.skip_blank(false);
+            self.expect('=')?;
+            self.skip_blank(false);
+            let value = self.value()?;
+            let (last, parent) = path.split_last().expect("keys are never empty");
+            table
+                .table_mut(parent, line)?
+                .insert(last.clone(), value, line)?;
+            self.skip_blank(false);
+            if !self.eat(',') {
+                self.expect('}')?;
+                return Ok(Value::Table(table));
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(u @ ('u' | 'U')) => {
+                            let len = if u == 'u' { 4 } else { 8 };
+                            let hex: String = (0..len).filter_map(|_| self.chars.next()).collect();
+                            match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                                Some(c) if hex.len() == len => c,
+                                _ => return self.error(&format!("invalid escape \\{}{}", u, hex)),
+                            }
+                        }
+                        Some(c) => return self.error(&format!("invalid escape \\{}", c)),
+                        None => return self.error("unterminated string"),
+                    };
+                    s.push(c);
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, Error> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse() {
+        let table = parse(
+            r#"
+# comment
+title = "dns" # trailing comment
+"quoted key" = 'C:\path'
+
+[server]
+port = 2_053
+negative = -5
+enabled = true
+list = [
+    "a", # first
+    "b\t\u00fc",
+]
+point = { x = 1, y.z = 2 }
+
+[[zones]]
+name = "example.com."
+
+[[zones]]
+name = "example.org."
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(Some(&Value::String("dns".into())), table.get("title"));
+        assert_eq!(3, table.line("title"));
+        assert_eq!(
+            Some(&Value::String("C:\\path".into())),
+            table.get("quoted key")
+        );
+
+        let server = match table.get("server") {
+            Some(Value::Table(server)) => server,
+            v => panic!("unexpected {:?}", v),
+        };
+        assert_eq!(Some(&Value::Integer(2053)), server.get("port"));
+        assert_eq!(7, server.line("port"));
+        assert_eq!(Some(&Value::Integer(-5)), server.get("negative"));
+        assert_eq!(Some(&Value::Boolean(true)), server.get("enabled"));
+        assert_eq!(
+            Some(&Value::Array(vec![
+                Value::String("a".into()),
+                Value::String("b\t\u{00fc}".into())
+            ])),
+            server.get("list")
+        );
+        let point = match server.get("point") {
+            Some(Value::Table(point)) => point,
+            v => panic!("unexpected {:?}", v),
+        };
+        assert_eq!(Some(&Value::Integer(1)), point.get("x"));
+
+        let zones = match table.get("zones") {
+            Some(Value::Array(zones)) => zones,
+            v => panic!("unexpected {:?}", v),
+        };
+        assert_eq!(2, zones.len());
+        assert_eq!(
+            Value::String("example.org.".into()),
+            match &zones[1] {
+                Value::Table(zone) => zone.get("name").unwrap().clone(),
+                v => panic!("unexpected {:?}", v),
+            }
+        );
+    }
+
+    #[test]
+    pub fn test_parse_errors() {
+        let cases = [
+            ("a = ", "line 1: expected a value"),
+            ("a = 1\na = 2", "line 2: duplicate key `a`"),
+            ("\n\na = \"open", "line 3: unterminated string"),
+            ("a = 1 2", "line 1: unexpected `2` after value"),
+            ("a = 1_", "line 1: invalid value `1_`"),
+            ("a = 1.5", "line 1: unexpected `.` after value"),
+            ("[a]\n[a]", "line 2: table `a` defined twice"),
+            ("a = 1\n[a]", "line 2: `a` is not a table"),
+            ("a = [1, 2", "line 1: expected `]`"),
+            ("a = \"\\q\"", "line 1: invalid escape \\q"),
+            ("= 1", "line 1: expected a key"),
+        ];
+        for (text, msg) in cases {
+            assert_eq!(msg, parse(text).unwrap_err().to_string(), "{}", text);
+        }
+    }
+}
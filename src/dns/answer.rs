@@ -1,29 +1,147 @@
+use anyhow::Error;
 use std::net::Ipv4Addr;
 
+use super::{name::DomainName, rtype};
+
+// RDATA of the record at `start..end` of `msg`. Names in the RDATA of the RFC 1035 types
+// may be compressed (RFC 3597 4), they are expanded so the RDATA stands on its own.
+fn expand_rdata(msg: &[u8], typ: u16, start: usize, end: usize) -> Result<Vec<u8>, Error> {
+    let mut rdata = Vec::<u8>::new();
+    let mut pos = start;
+    let (names, fixed) = match typ {
+        rtype::NS | rtype::CNAME | rtype::PTR => (1, 0),
+        // preference before the exchange
+        rtype::MX => {
+            if start + 2 > end {
+                return Err(Error::msg("the MX record data is incomplete"));
+            }
+            rdata.extend_from_slice(&msg[start..start + 2]);
+            pos += 2;
+            (1, 0)
+        }
+        // MNAME and RNAME, then serial, refresh, retry, expire and minimum
+        rtype::SOA => (2, 20),
+        _ => return Ok(msg[start..end].to_vec()),
+    };
+
+    for _ in 0..names {
+        let (name, next) = DomainName::decode(&msg[..end], pos)?;
+        rdata.extend_from_slice(&name.encode());
+        pos = next;
+    }
+    if pos + fixed != end {
+        return Err(Error::msg(format!(
+            "type {} record data does not match its rdlength",
+            typ
+        )));
+    }
+    rdata.extend_from_slice(&msg[pos..end]);
+
+    return Ok(rdata);
+}
+
 // RR
+#[derive(Debug, Clone)]
 pub struct ResourceRecord {
-    names: Vec<String>,
+    name: DomainName,
     typ: u16,
     class: u16,
     ttl: u32,
-    rdlength: u16,
     rdata: Vec<u8>,
 }
 
 impl ResourceRecord {
     pub fn new() -> Self {
         Self {
-            names: vec![],
+            name: DomainName::root(),
             typ: 0,
             class: 0,
             ttl: 0,
-            rdlength: 0,
             rdata: vec![],
         }
     }
 
-    pub fn with_name(&mut self, name: &str) -> &mut Self {
-        self.names.push(name.to_string());
+    // Decode the RR located at `offset` of the whole message `msg`.
+    // Returns the record and the offset of the byte following it.
+    pub fn decode(msg: &[u8], offset: usize) -> Result<(Self, usize), Error> {
+        let (name, mut pos) = DomainName::decode(msg, offset)?;
+        if pos + 10 > msg.len() {
+            return Err(Error::msg("the resource record is incomplete"));
+        }
+
+        let typ = u16::from_be_bytes(msg[pos..pos + 2].try_into()?);
+        let class = u16::from_be_bytes(msg[pos + 2..pos + 4].try_into()?);
+        let ttl = u32::from_be_bytes(msg[pos + 4..pos + 8].try_into()?);
+        let rdlength = u16::from_be_bytes(msg[pos + 8..pos + 10].try_into()?);
+        pos += 10;
+
+        let end = pos + rdlength as usize;
+        if end > msg.len() {
+            return Err(Error::msg("the resource record data is incomplete"));
+        }
+        let rr = Self {
+            name,
+            typ,
+            class,
+            ttl,
+            rdata: expand_rdata(msg, typ, pos, end)?,
+        };
+
+        return Ok((rr, end));
+    }
+
+    pub fn name(&self) -> &DomainName {
+        return &self.name;
+    }
+
+    pub fn typ(&self) -> u16 {
+        return self.typ;
+    }
+
+    pub fn class(&self) -> u16 {
+        return self.class;
+    }
+
+    pub fn ttl(&self) -> u32 {
+        return self.ttl;
+    }
+
+    pub fn rdata(&self) -> &[u8] {
+        return &self.rdata;
+    }
+
+    // RDLENGTH is always derived from the RDATA.
+    pub fn rdlength(&self) -> u16 {
+        return self.rdata.len() as u16;
+    }
+
+    // Check that the RDATA length fits the record type, for types with a fixed size.
+    pub fn check_rdlength(&self) -> Result<(), Error> {
+        let expected = match self.typ {
+            rtype::A => 4,
+            rtype::AAAA => 16,
+            _ => return Ok(()),
+        };
+        if self.rdata.len() != expected {
+            return Err(Error::msg(format!(
+                "type {} record with rdlength {}, expected {}",
+                self.typ,
+                self.rdata.len(),
+                expected
+            )));
+        }
+
+        return Ok(());
+    }
+
+    // Owner name in text form, an error if `name` is not a valid name.
+    pub fn with_name(&mut self, name: &str) -> Result<&mut Self, Error> {
+        self.name = name.parse()?;
+        return Ok(self);
+    }
+
+    pub fn with_domain_name(&mut self, name: DomainName) -> &mut Self {
+        self.name = name;
         return self;
     }
 
@@ -47,25 +165,25 @@ impl ResourceRecord {
         return self;
     }
 
+    // RDATA of any type, already in wire format.
+    pub fn with_raw_rdata(&mut self, rdata: Vec<u8>) -> &mut Self {
+        self.rdata = rdata;
+        return self;
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut result = Vec::<u8>::new();
         // encode names
-        for name in &self.names {
-            result.push(name.len() as u8);
-            for v in name.as_bytes() {
-                result.push(*v);
-            }
-        }
-        result.push(b'\x00');
+        result.extend_from_slice(&self.name.encode());
 
         // encode type
         result.extend_from_slice(&self.typ.to_be_bytes());
         // encode class
         result.extend_from_slice(&self.class.to_be_bytes());
-        // encode class
+        // encode ttl
         result.extend_from_slice(&self.ttl.to_be_bytes());
         // encode length
-        result.extend_from_slice(&self.rdlength.to_be_bytes());
+        result.extend_from_slice(&self.rdlength().to_be_bytes());
         // encode data
         result.extend_from_slice(&self.rdata);
 
@@ -73,6 +191,13 @@ impl ResourceRecord {
     }
 }
 
+impl Default for ResourceRecord {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Answers(Vec<ResourceRecord>);
 
 impl Answers {
@@ -84,6 +209,18 @@ impl Answers {
         self.0.push(rr);
     }
 
+    pub fn len(&self) -> usize {
+        return self.0.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.0.is_empty();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, ResourceRecord> {
+        return self.0.iter();
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut result = Vec::<u8>::new();
 
@@ -96,19 +233,33 @@ impl Answers {
     }
 }
 
+impl From<Vec<ResourceRecord>> for Answers {
+    fn from(records: Vec<ResourceRecord>) -> Self {
+        return Self(records);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
     pub fn test_rr_with_name() {
         let mut rr = ResourceRecord::new();
-        rr.with_name("google.com");
-        assert_eq!(1, rr.names.len());
-        assert_eq!(&"google.com", &rr.names.get(0).unwrap().as_str());
+        rr.with_name("google.com").unwrap();
+        assert_eq!(2, rr.name.len());
+        assert_eq!("google.com.", rr.name.to_string());
+
+        rr.with_name("www.amazon.com.").unwrap();
+        assert_eq!(3, rr.name.len());
+        assert_eq!("www.amazon.com.", rr.name.to_string());
 
-        rr.with_name("amazon.com");
-        assert_eq!(2, rr.names.len());
-        assert_eq!(&"amazon.com", &rr.names.get(1).unwrap().as_str());
+        // the record keeps its owner
+        assert!(rr.with_name("invalid..name").is_err());
+        assert!(rr.with_name(&"a".repeat(64)).is_err());
+        assert_eq!("www.amazon.com.", rr.name.to_string());
+
+        rr.with_domain_name(DomainName::root());
+        assert!(rr.name.is_root());
     }
 
     #[test]
@@ -150,4 +301,98 @@ mod tests {
         rr.with_rdata(Ipv4Addr::new(10, 0, 0, 2));
         assert_eq!(vec![10_u8, 0, 0, 2], rr.rdata);
     }
+
+    #[test]
+    pub fn test_rr_encode_rdlength() {
+        let mut rr = ResourceRecord::new();
+        rr.with_name("google.com")
+            .unwrap()
+            .with_type(1)
+            .with_class(1)
+            .with_ttl(60)
+            .with_rdata(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(4, rr.rdlength());
+
+        let raw = rr.encode();
+        assert_eq!([0x00, 0x04, 10, 0, 0, 1], raw[raw.len() - 6..]);
+        let (decoded, _) = ResourceRecord::decode(&raw, 0).unwrap();
+        assert_eq!(&[10, 0, 0, 1], decoded.rdata());
+        assert!(decoded.check_rdlength().is_ok());
+    }
+
+    #[test]
+    pub fn test_rr_check_rdlength() {
+        let mut rr = ResourceRecord::new();
+        rr.with_type(28).with_rdata(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(rr.check_rdlength().is_err());
+        rr.with_type(16);
+        assert!(rr.check_rdlength().is_ok());
+    }
+
+    #[test]
+    pub fn test_rr_decode() {
+        let mut msg = vec![
+            // google com
+            0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+            // type A, class IN, ttl 60, rdlength 4
+            0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x04,
+        ];
+        // 10.0.0.1
+        msg.extend_from_slice(&[0x0a, 0x00, 0x00, 0x01]);
+        let (rr, next) = ResourceRecord::decode(&msg, 0).unwrap();
+        assert_eq!("google.com.", rr.name().to_string());
+        assert_eq!(1, rr.typ());
+        assert_eq!(1, rr.class());
+        assert_eq!(60, rr.ttl());
+        assert_eq!(&[10, 0, 0, 1], rr.rdata());
+        assert_eq!(msg.len(), next);
+        assert_eq!(msg, rr.encode());
+
+        // rdata is shorter than rdlength
+        assert!(ResourceRecord::decode(&msg[..msg.len() - 1], 0).is_err());
+        // fixed fields are missing
+        assert!(ResourceRecord::decode(&msg[..20], 0).is_err());
+    }
+
+    #[test]
+    pub fn test_rr_decode_expands_rdata_names() {
+        // google com at offset 0
+        let mut msg = b"\x06google\x03com\x00".to_vec();
+        let owner = msg.len();
+        // CNAME www.google.com -> mail + pointer to google.com
+        msg.extend_from_slice(&[0xc0, 0x00, 0x00, 0x05, 0x00, 0x01, 0, 0, 0, 60, 0x00, 0x07]);
+        msg.extend_from_slice(b"\x04mail\xc0\x00");
+        let (cname, next) = ResourceRecord::decode(&msg, owner).unwrap();
+        assert_eq!(b"\x04mail\x06google\x03com\x00".to_vec(), cname.rdata());
+        assert_eq!(17, cname.rdlength());
+        assert_eq!(msg.len(), next);
+
+        // MX 10 pointer to google.com
+        let mx_at = msg.len();
+        msg.extend_from_slice(&[0xc0, 0x00, 0x00, 0x0f, 0x00, 0x01, 0, 0, 0, 60, 0x00, 0x04]);
+        msg.extend_from_slice(&[0x00, 0x0a, 0xc0, 0x00]);
+        let (mx, _) = ResourceRecord::decode(&msg, mx_at).unwrap();
+        assert_eq!(b"\x00\x0a\x06google\x03com\x00".to_vec(), mx.rdata());
+
+        // SOA with both names compressed and the five counters
+        let soa_at = msg.len();
+        msg.extend_from_slice(&[0xc0, 0x00, 0x00, 0x06, 0x00, 0x01, 0, 0, 0, 60, 0x00, 24]);
+        msg.extend_from_slice(&[0xc0, 0x00, 0xc0, 0x00]);
+        msg.extend_from_slice(&[1; 20]);
+        let (soa, _) = ResourceRecord::decode(&msg, soa_at).unwrap();
+        let mut expected = b"\x06google\x03com\x00\x06google\x03com\x00".to_vec();
+        expected.extend_from_slice(&[1; 20]);
+        assert_eq!(expected, soa.rdata());
+
+        // a name running past the rdlength
+        let mut bad = b"\x06google\x03com\x00".to_vec();
+        bad.extend_from_slice(&[0xc0, 0x00, 0x00, 0x02, 0x00, 0x01, 0, 0, 0, 60, 0x00, 0x02]);
+        bad.extend_from_slice(b"\x04mail\xc0\x00");
+        assert!(ResourceRecord::decode(&bad, 12).is_err());
+        // SOA counters missing
+        let mut bad = b"\x06google\x03com\x00".to_vec();
+        bad.extend_from_slice(&[0xc0, 0x00, 0x00, 0x06, 0x00, 0x01, 0, 0, 0, 60, 0x00, 4]);
+        bad.extend_from_slice(&[0xc0, 0x00, 0xc0, 0x00]);
+        assert!(ResourceRecord::decode(&bad, 12).is_err());
+    }
 }
@@ -1,33 +1,809 @@
-use super::{answer::Answers, header::Header, question::Question};
+use anyhow::Error;
 
+use super::{
+    answer::{Answers, ResourceRecord},
+    edns::{Edns, OPT_TYPE},
+    header::Header,
+    name::DomainName,
+    opcode::Opcode,
+    question::{Question, Questions},
+    rcode::Rcode,
+    rtype,
+};
+
+// Maximum UDP message size without EDNS (RFC 1035 4.2.1).
+pub const UDP_MAX_SIZE: usize = 512;
+
+// Opcodes the server knows how to answer, everything else gets NOTIMP (RFC 1035 4.1.1).
+pub const SUPPORTED_OPCODES: [Opcode; 1] = [Opcode::Query];
+
+#[derive(Debug, Clone)]
 pub struct DNS {
     raw: Vec<u8>,
     head: Header,
-    ques: Question,
+    ques: Questions,
     answers: Answers,
+    authorities: Answers,
+    additionals: Answers,
+    edns: Option<Edns>,
 }
 
 impl DNS {
-    pub fn from(raw: &[u8]) -> Self {
+    // An empty message, all header fields zero.
+    pub fn new() -> Self {
         Self {
+            raw: vec![],
+            head: Header::new([0; 12]),
+            ques: Questions::new(),
+            answers: Answers::new(),
+            authorities: Answers::new(),
+            additionals: Answers::new(),
+            edns: None,
+        }
+    }
+
+    // Start the reply to `query`: ID, opcode, RD, CD and the question section are copied and
+    // QR is set. Queries with an unsupported opcode are answered with NOTIMP, an OPT record in
+    // the query is answered with our own one (BADVERS for EDNS versions other than 0).
+    pub fn response_for(query: &DNS) -> Self {
+        let mut resp = Self::new();
+        resp.head
+            .with_id(query.head.id())
+            .with_qr(1)
+            .with_opcode(query.head.opcode())
+            .with_rd(query.head.rd())
+            .with_cd(query.head.cd());
+        resp.ques = query.ques.clone();
+
+        if let Some(edns) = &query.edns {
+            let mut own = Edns::new();
+            own.with_dnssec_ok(edns.dnssec_ok());
+            resp.edns = Some(own);
+            if edns.version() != 0 {
+                resp.with_rcode(Rcode::BadVers);
+                return resp;
+            }
+        }
+        if !SUPPORTED_OPCODES.contains(&query.head.opcode()) {
+            resp.with_rcode(Rcode::NotImp);
+        }
+
+        return resp;
+    }
+
+    // FORMERR reply for a packet that could not be decoded, built from the readable part of
+    // its header. `None` when not even the ID is readable or the packet is itself a response,
+    // such packets are dropped.
+    pub fn formerr_for(raw: &[u8]) -> Option<Self> {
+        if raw.len() < 2 {
+            return None;
+        }
+
+        let mut resp = Self::new();
+        resp.head.with_id(u16::from_be_bytes([raw[0], raw[1]]));
+        if let Some(flags) = raw.get(2) {
+            let mut partial = [0; 12];
+            partial[2] = *flags;
+            let partial = Header::new(partial);
+            if partial.qr() == 1 {
+                return None;
+            }
+            resp.head
+                .with_opcode(partial.opcode())
+                .with_rd(partial.rd());
+        }
+        resp.head.with_qr(1).with_rcode(Rcode::FormErr);
+
+        return Some(resp);
+    }
+
+    // Decode a message, records announced by the header but missing at the end of the
+    // packet and trailing bytes are tolerated.
+    pub fn decode(raw: &[u8]) -> Result<Self, Error> {
+        return Self::decode_with(raw, false);
+    }
+
+    // Decode a message, rejecting header counts that do not match the sections, trailing
+    // bytes and RDATA whose length does not fit its type.
+    pub fn decode_strict(raw: &[u8]) -> Result<Self, Error> {
+        return Self::decode_with(raw, true);
+    }
+
+    fn decode_with(raw: &[u8], strict: bool) -> Result<Self, Error> {
+        if raw.len() < 12 {
+            return Err(Error::msg("the header package is incomplete"));
+        }
+        let mut dns = Self {
             raw: raw.to_vec(),
-            head: Header::new(raw[..12].try_into().expect("slice covert to array error")),
-            ques: todo!(),
-            answers: todo!(),
+            head: Header::new(raw[..12].try_into()?),
+            ques: Questions::new(),
+            answers: Answers::new(),
+            authorities: Answers::new(),
+            additionals: Answers::new(),
+            edns: None,
+        };
+        dns.decode_sections(strict)?;
+
+        return Ok(dns);
+    }
+
+    // Parse the question, answer, authority and additional sections following the header.
+    // The OPT pseudo-RR is pulled out of the additional section into `edns`.
+    fn decode_sections(&mut self, strict: bool) -> Result<(), Error> {
+        let raw = &self.raw;
+        let mut offset = 12;
+        // in lenient mode a section simply ends where the packet ends
+        let exhausted = |offset: usize| !strict && offset >= raw.len();
+
+        for _ in 0..self.head.qdcount() {
+            if exhausted(offset) {
+                break;
+            }
+            let ques = Question::decode(raw, offset)?;
+            offset += ques.length();
+            self.ques.extend(ques);
+        }
+        for _ in 0..self.head.ancount() {
+            if exhausted(offset) {
+                break;
+            }
+            let (rr, next) = ResourceRecord::decode(raw, offset)?;
+            offset = next;
+            if strict {
+                rr.check_rdlength()?;
+            }
+            self.answers.extend(rr);
+        }
+        for _ in 0..self.head.nscount() {
+            if exhausted(offset) {
+                break;
+            }
+            let (rr, next) = ResourceRecord::decode(raw, offset)?;
+            offset = next;
+            if strict {
+                rr.check_rdlength()?;
+            }
+            self.authorities.extend(rr);
+        }
+        for _ in 0..self.head.arcount() {
+            if exhausted(offset) {
+                break;
+            }
+            let (rr, next) = ResourceRecord::decode(raw, offset)?;
+            offset = next;
+            if strict {
+                rr.check_rdlength()?;
+            }
+            if rr.typ() == OPT_TYPE {
+                if self.edns.is_some() {
+                    return Err(Error::msg("more than one OPT record"));
+                }
+                self.edns = Some(Edns::from_rr(&rr)?);
+                continue;
+            }
+            self.additionals.extend(rr);
         }
+
+        if strict && offset != raw.len() {
+            return Err(Error::msg(format!(
+                "{} trailing bytes after the last section",
+                raw.len() - offset
+            )));
+        }
+
+        return Ok(());
+    }
+
+    pub fn raw(&self) -> &[u8] {
+        return &self.raw;
     }
 
     pub fn head(&self) -> &Header {
         return &self.head;
     }
 
+    pub fn head_mut(&mut self) -> &mut Header {
+        return &mut self.head;
+    }
+
+    pub fn questions(&self) -> &Questions {
+        return &self.ques;
+    }
+
+    pub fn answers(&self) -> &Answers {
+        return &self.answers;
+    }
+
+    pub fn authorities(&self) -> &Answers {
+        return &self.authorities;
+    }
+
+    pub fn additionals(&self) -> &Answers {
+        return &self.additionals;
+    }
+
+    pub fn edns(&self) -> Option<&Edns> {
+        return self.edns.as_ref();
+    }
+
+    pub fn with_question(&mut self, ques: Question) -> &mut Self {
+        self.ques.extend(ques);
+        return self;
+    }
+
+    pub fn with_answer(&mut self, rr: ResourceRecord) -> &mut Self {
+        self.answers.extend(rr);
+        return self;
+    }
+
+    pub fn with_authority(&mut self, rr: ResourceRecord) -> &mut Self {
+        self.authorities.extend(rr);
+        return self;
+    }
+
+    pub fn with_additional(&mut self, rr: ResourceRecord) -> &mut Self {
+        self.additionals.extend(rr);
+        return self;
+    }
+
+    pub fn with_edns(&mut self, edns: Option<Edns>) -> &mut Self {
+        self.edns = edns;
+        return self;
+    }
+
+    // The full 12-bit RCODE, combined from the header and the OPT record.
+    pub fn rcode(&self) -> Rcode {
+        let high = self.edns.as_ref().map_or(0, |edns| edns.ext_rcode());
+        return Rcode::from_parts(self.head.rcode().low(), high);
+    }
+
+    // Store the lower 4 bits in the header and the upper 8 bits in the OPT record.
+    // An OPT record is added when an extended rcode needs to be expressed.
+    pub fn with_rcode(&mut self, rcode: Rcode) -> &mut Self {
+        self.head.with_rcode(rcode);
+        if rcode.is_extended() && self.edns.is_none() {
+            self.edns = Some(Edns::new());
+        }
+        if let Some(edns) = self.edns.as_mut() {
+            edns.with_ext_rcode(rcode.high());
+        }
+
+        return self;
+    }
+
+    // The section counts of the header are always taken from the sections themselves.
     pub fn encode(&self) -> Vec<u8> {
         let mut result = Vec::<u8>::new();
 
-        result.extend_from_slice(&self.head.get_0());
+        let mut head = self.head;
+        head.with_qdcount(self.ques.len() as u16)
+            .with_ancount(self.answers.len() as u16)
+            .with_nscount(self.authorities.len() as u16)
+            .with_arcount((self.additionals.len() + self.edns.is_some() as usize) as u16);
+        result.extend_from_slice(&head.get_0());
         result.extend_from_slice(&self.ques.encode());
         result.extend_from_slice(&self.answers.encode());
+        result.extend_from_slice(&self.authorities.encode());
+        result.extend_from_slice(&self.additionals.encode());
+        if let Some(edns) = &self.edns {
+            result.extend_from_slice(&edns.encode());
+        }
 
         return result;
     }
+
+    // Size budget of a UDP reply to `query`: 512 bytes, or the payload size the client
+    // advertised in its OPT record capped by our own one.
+    pub fn udp_limit_for(query: &DNS) -> usize {
+        return match &query.edns {
+            Some(edns) => (edns.udp_payload_size() as usize)
+                .min(Edns::new().udp_payload_size() as usize)
+                .max(UDP_MAX_SIZE),
+            None => UDP_MAX_SIZE,
+        };
+    }
+
+    // Encode within `limit` bytes (RFC 2181 9). Whole RRsets are dropped from the end of the
+    // additional and then the authority section. In a referral the NS RRset and the glue of
+    // in-domain name servers are required (RFC 9471). When the answers or the required
+    // referral data do not fit, TC is set and only the answer RRsets that fit are kept.
+    // Header, questions and OPT record are always kept, even when they alone exceed `limit`.
+    pub fn encode_with_limit(&self, limit: usize) -> Vec<u8> {
+        let full = self.encode();
+        if full.len() <= limit {
+            return full;
+        }
+
+        let size = |records: &[ResourceRecord]| -> usize {
+            return records.iter().map(|rr| rr.encode().len()).sum();
+        };
+        let fixed =
+            12 + self.ques.encode().len() + self.edns.as_ref().map_or(0, |e| e.encode().len());
+        let mut answers: Vec<ResourceRecord> = self.answers.iter().cloned().collect();
+        let mut authorities: Vec<ResourceRecord> = self.authorities.iter().cloned().collect();
+        let mut additionals: Vec<ResourceRecord> = self.additionals.iter().cloned().collect();
+
+        let referral = answers.is_empty() && authorities.iter().any(|rr| rr.typ() == rtype::NS);
+        let glue = if referral {
+            in_domain_glue_names(&authorities)
+        } else {
+            vec![]
+        };
+        let is_glue = |rr: &ResourceRecord| -> bool {
+            return (rr.typ() == rtype::A || rr.typ() == rtype::AAAA) && glue.contains(rr.name());
+        };
+
+        let fits = |an: &[ResourceRecord], ns: &[ResourceRecord], ar: &[ResourceRecord]| {
+            fixed + size(an) + size(ns) + size(ar) <= limit
+        };
+        while !fits(&answers, &authorities, &additionals) {
+            if !drop_last_rrset(&mut additionals, is_glue) {
+                break;
+            }
+        }
+        while !fits(&answers, &authorities, &additionals) {
+            if !drop_last_rrset(&mut authorities, |rr| referral && rr.typ() == rtype::NS) {
+                break;
+            }
+        }
+
+        let mut resp = self.clone();
+        if !fits(&answers, &authorities, &additionals) {
+            authorities.clear();
+            additionals.clear();
+            while !fits(&answers, &authorities, &additionals) {
+                if !drop_last_rrset(&mut answers, |_| false) {
+                    break;
+                }
+            }
+            resp.head.with_tc(1);
+        }
+        resp.answers = Answers::from(answers);
+        resp.authorities = Answers::from(authorities);
+        resp.additionals = Answers::from(additionals);
+
+        return resp.encode();
+    }
+}
+
+// Name server names of the NS records in `authorities` that sit inside the delegated zone,
+// their addresses are required glue.
+fn in_domain_glue_names(authorities: &[ResourceRecord]) -> Vec<DomainName> {
+    return authorities
+        .iter()
+        .filter(|rr| rr.typ() == rtype::NS)
+        .filter_map(|rr| {
+            let (target, _) = DomainName::decode(rr.rdata(), 0).ok()?;
+            return Some(target).filter(|t| t.is_subdomain_of(rr.name()));
+        })
+        .collect();
+}
+
+// Remove every record of the last RRset (by first appearance) that `keep` does not protect.
+// Returns false when nothing could be removed.
+fn drop_last_rrset<F>(records: &mut Vec<ResourceRecord>, keep: F) -> bool
+where
+    F: Fn(&ResourceRecord) -> bool,
+{
+    let same_set = |a: &ResourceRecord, b: &ResourceRecord| {
+        a.typ() == b.typ() && a.class() == b.class() && a.name() == b.name()
+    };
+    let last = match records.iter().rev().find(|rr| !keep(rr)) {
+        Some(rr) => rr.clone(),
+        None => return false,
+    };
+    records.retain(|rr| keep(rr) || !same_set(rr, &last));
+
+    return true;
+}
+
+impl Default for DNS {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query_with_opt(ext_rcode: u8) -> Vec<u8> {
+        let mut raw = vec![
+            // id 1234, qr, rcode 7 (lower bits), qdcount 1, arcount 1
+            0x04, 0xd2, 0x80, 0x07, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+        ];
+        // google com, type A, class IN
+        raw.extend_from_slice(b"\x06google\x03com\x00\x00\x01\x00\x01");
+        // OPT, payload 1232, ext rcode, version 0, no options
+        raw.extend_from_slice(&[0x00, 0x00, 0x29, 0x04, 0xd0, ext_rcode, 0, 0, 0, 0, 0]);
+
+        return raw;
+    }
+
+    #[test]
+    pub fn test_dns_decode() {
+        let raw = query_with_opt(0);
+        let dns = DNS::decode(&raw).unwrap();
+        assert_eq!(1234, dns.head().id());
+        assert_eq!(1, dns.questions().len());
+        assert_eq!(0, dns.answers().len());
+        assert_eq!(0, dns.additionals().len());
+        assert_eq!(1232, dns.edns().unwrap().udp_payload_size());
+        assert_eq!(raw, dns.encode());
+    }
+
+    #[test]
+    pub fn test_dns_decode_short() {
+        assert!(DNS::decode(&[0; 11]).is_err());
+        assert!(DNS::decode_strict(&[0; 11]).is_err());
+        assert_eq!(0, DNS::decode(&[0; 12]).unwrap().questions().len());
+    }
+
+    #[test]
+    pub fn test_dns_decode_count_mismatch() {
+        // ancount says 1 but the answer section is missing
+        let mut raw = query_with_opt(0);
+        raw[7] = 1;
+        raw.truncate(raw.len() - 11);
+        raw[11] = 0;
+        assert!(DNS::decode_strict(&raw).is_err());
+        let dns = DNS::decode(&raw).unwrap();
+        assert_eq!(0, dns.answers().len());
+        // counts are fixed when encoding
+        let encoded = dns.encode();
+        assert_eq!(0, DNS::decode_strict(&encoded).unwrap().head().ancount());
+
+        // trailing bytes
+        let mut raw = query_with_opt(0);
+        raw.push(0);
+        assert!(DNS::decode_strict(&raw).is_err());
+        assert!(DNS::decode(&raw).is_ok());
+        assert!(DNS::decode_strict(&query_with_opt(0)).is_ok());
+    }
+
+    #[test]
+    pub fn test_dns_decode_strict_rdlength() {
+        let mut raw = query_with_opt(0);
+        raw[7] = 1;
+        let len = raw.len();
+        // google com, type A, class IN, ttl 60 with 3 bytes of RDATA
+        raw.splice(
+            len - 11..len - 11,
+            [
+                0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0, 0, 0, 60, 0x00, 0x03, 10, 0, 0,
+            ],
+        );
+        assert!(DNS::decode(&raw).is_ok());
+        assert!(DNS::decode_strict(&raw).is_err());
+    }
+
+    #[test]
+    pub fn test_dns_encode_counts() {
+        let mut dns = DNS::decode(&query_with_opt(0)).unwrap();
+        let mut rr = ResourceRecord::new();
+        rr.with_name("google.com")
+            .unwrap()
+            .with_type(1)
+            .with_class(1)
+            .with_ttl(60)
+            .with_rdata(std::net::Ipv4Addr::new(10, 0, 0, 1));
+        dns.with_answer(rr.clone())
+            .with_answer(rr.clone())
+            .with_authority(rr.clone())
+            .with_additional(rr);
+
+        let decoded = DNS::decode_strict(&dns.encode()).unwrap();
+        assert_eq!(1, decoded.head().qdcount());
+        assert_eq!(2, decoded.head().ancount());
+        assert_eq!(1, decoded.head().nscount());
+        // the OPT record counts as an additional record
+        assert_eq!(2, decoded.head().arcount());
+        assert_eq!(1, decoded.additionals().len());
+
+        dns.with_edns(None);
+        assert_eq!(
+            1,
+            DNS::decode_strict(&dns.encode()).unwrap().head().arcount()
+        );
+    }
+
+    #[test]
+    pub fn test_dns_response_for() {
+        let mut raw = query_with_opt(0);
+        // rd and cd set, no rcode
+        raw[2] = 0b0000_0001;
+        raw[3] = 0b0001_0000;
+        let query = DNS::decode(&raw).unwrap();
+
+        let mut resp = DNS::response_for(&query);
+        assert_eq!(1234, resp.head().id());
+        assert_eq!(1, resp.head().qr());
+        assert_eq!(Opcode::Query, resp.head().opcode());
+        assert_eq!(1, resp.head().rd());
+        assert_eq!(1, resp.head().cd());
+        assert_eq!(0, resp.head().aa());
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(1, resp.questions().len());
+        assert!(resp.edns().is_some());
+
+        let mut rr = ResourceRecord::new();
+        rr.with_name("google.com")
+            .unwrap()
+            .with_type(1)
+            .with_class(1)
+            .with_ttl(60)
+            .with_rdata(std::net::Ipv4Addr::new(10, 0, 0, 1));
+        resp.with_answer(rr).with_rcode(Rcode::NoError);
+
+        let decoded = DNS::decode_strict(&resp.encode()).unwrap();
+        assert_eq!(1, decoded.head().ancount());
+        assert_eq!(
+            "google.com.",
+            decoded
+                .questions()
+                .iter()
+                .next()
+                .unwrap()
+                .name()
+                .to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_dns_response_for_notimp() {
+        let mut raw = query_with_opt(0);
+        // opcode STATUS
+        raw[2] = 0b0001_0000;
+        raw[3] = 0;
+        let resp = DNS::response_for(&DNS::decode(&raw).unwrap());
+        assert_eq!(Opcode::Status, resp.head().opcode());
+        assert_eq!(Rcode::NotImp, resp.rcode());
+
+        raw[2] = 0b0010_0000;
+        assert_eq!(
+            Rcode::NotImp,
+            DNS::response_for(&DNS::decode(&raw).unwrap()).rcode()
+        );
+    }
+
+    #[test]
+    pub fn test_dns_response_for_badvers() {
+        let mut raw = query_with_opt(0);
+        raw[3] = 0;
+        // EDNS version 1
+        let len = raw.len();
+        raw[len - 5] = 1;
+        let resp = DNS::response_for(&DNS::decode(&raw).unwrap());
+        assert_eq!(Rcode::BadVers, resp.rcode());
+        assert_eq!(0, resp.edns().unwrap().version());
+        assert_eq!(Rcode::BadVers, DNS::decode(&resp.encode()).unwrap().rcode());
+    }
+
+    #[test]
+    pub fn test_dns_formerr_for() {
+        assert!(DNS::formerr_for(&[]).is_none());
+        assert!(DNS::formerr_for(&[0x04]).is_none());
+
+        let resp = DNS::formerr_for(&[0x04, 0xd2]).unwrap();
+        assert_eq!(1234, resp.head().id());
+        assert_eq!(1, resp.head().qr());
+        assert_eq!(Rcode::FormErr, resp.rcode());
+
+        // opcode NOTIFY and RD are kept
+        let resp = DNS::formerr_for(&[0x04, 0xd2, 0b0010_0001, 0x00, 0x00]).unwrap();
+        assert_eq!(Opcode::Notify, resp.head().opcode());
+        assert_eq!(1, resp.head().rd());
+        assert_eq!(12, resp.encode().len());
+
+        // responses are never answered
+        assert!(DNS::formerr_for(&[0x04, 0xd2, 0x80]).is_none());
+    }
+
+    fn a_record(name: &str, last: u8) -> ResourceRecord {
+        let mut rr = ResourceRecord::new();
+        rr.with_name(name)
+            .unwrap()
+            .with_type(rtype::A)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(60)
+            .with_rdata(std::net::Ipv4Addr::new(10, 0, 0, last));
+        return rr;
+    }
+
+    fn ns_record(zone: &str, target: &str) -> ResourceRecord {
+        let mut rr = ResourceRecord::new();
+        rr.with_name(zone)
+            .unwrap()
+            .with_type(rtype::NS)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(60)
+            .with_raw_rdata(target.parse::<DomainName>().unwrap().encode());
+        return rr;
+    }
+
+    #[test]
+    pub fn test_dns_udp_limit_for() {
+        let mut query = DNS::decode(&query_with_opt(0)).unwrap();
+        assert_eq!(1232, DNS::udp_limit_for(&query));
+        let mut edns = Edns::new();
+        edns.with_udp_payload_size(100);
+        query.with_edns(Some(edns.clone()));
+        assert_eq!(512, DNS::udp_limit_for(&query));
+        edns.with_udp_payload_size(4096);
+        query.with_edns(Some(edns));
+        assert_eq!(1232, DNS::udp_limit_for(&query));
+        query.with_edns(None);
+        assert_eq!(512, DNS::udp_limit_for(&query));
+    }
+
+    #[test]
+    pub fn test_dns_encode_with_limit_drops_additional() {
+        let mut resp = DNS::response_for(&DNS::decode(&query_with_opt(0)).unwrap());
+        resp.with_answer(a_record("google.com", 1));
+        for i in 0..20 {
+            resp.with_authority(ns_record("google.com", &format!("ns{}.example.net", i)));
+            resp.with_additional(a_record(&format!("extra{}.example.net", i), i));
+        }
+        assert!(resp.encode().len() > 512);
+        assert_eq!(resp.encode(), resp.encode_with_limit(65535));
+
+        let raw = resp.encode_with_limit(512);
+        assert!(raw.len() <= 512);
+        let decoded = DNS::decode_strict(&raw).unwrap();
+        assert_eq!(0, decoded.head().tc());
+        assert_eq!(1, decoded.answers().len());
+        assert!(decoded.edns().is_some());
+        // additional RRsets go first, the authority NS RRset is all or nothing
+        assert_eq!(0, decoded.additionals().len());
+        assert!(decoded.authorities().is_empty() || decoded.authorities().len() == 20);
+    }
+
+    #[test]
+    pub fn test_dns_encode_with_limit_keeps_glue() {
+        let mut resp = DNS::response_for(&DNS::decode(&query_with_opt(0)).unwrap());
+        resp.with_authority(ns_record("google.com", "ns1.google.com"))
+            .with_authority(ns_record("google.com", "ns.example.net"))
+            .with_additional(a_record("ns1.google.com", 1))
+            .with_additional(a_record("ns.example.net", 2));
+        for i in 0..30 {
+            resp.with_additional(a_record(&format!("extra{}.example.net", i), i));
+        }
+
+        let decoded = DNS::decode_strict(&resp.encode_with_limit(512)).unwrap();
+        assert_eq!(0, decoded.head().tc());
+        assert_eq!(2, decoded.authorities().len());
+        assert!(decoded.additionals().len() < 32);
+
+        // just enough room for the referral and the in-domain glue
+        let mut minimal = DNS::response_for(&DNS::decode(&query_with_opt(0)).unwrap());
+        minimal
+            .with_authority(ns_record("google.com", "ns1.google.com"))
+            .with_authority(ns_record("google.com", "ns.example.net"))
+            .with_additional(a_record("ns1.google.com", 1));
+        let limit = minimal.encode().len();
+        let raw = resp.encode_with_limit(limit);
+        assert_eq!(minimal.encode(), raw);
+
+        // required glue does not fit
+        let decoded = DNS::decode_strict(&resp.encode_with_limit(100)).unwrap();
+        assert_eq!(1, decoded.head().tc());
+        assert_eq!(0, decoded.authorities().len());
+        assert_eq!(0, decoded.additionals().len());
+    }
+
+    #[test]
+    pub fn test_dns_encode_with_limit_sets_tc() {
+        let mut resp = DNS::response_for(&DNS::decode(&query_with_opt(0)).unwrap());
+        for i in 0..40 {
+            resp.with_answer(a_record("google.com", i));
+        }
+        for i in 0..5 {
+            resp.with_answer(a_record("www.google.com", i));
+        }
+        resp.with_additional(a_record("extra.example.net", 1));
+
+        let raw = resp.encode_with_limit(512);
+        assert!(raw.len() <= 512);
+        let decoded = DNS::decode_strict(&raw).unwrap();
+        assert_eq!(1, decoded.head().tc());
+        // the google.com RRset alone is too large as well
+        assert_eq!(0, decoded.answers().len());
+        assert_eq!(1, decoded.questions().len());
+
+        // dropping the additional section is enough
+        let raw = resp.encode_with_limit(1232);
+        let decoded = DNS::decode_strict(&raw).unwrap();
+        assert_eq!(0, decoded.head().tc());
+        assert_eq!(45, decoded.answers().len());
+        assert_eq!(0, decoded.additionals().len());
+
+        let raw = resp.encode_with_limit(1100);
+        let decoded = DNS::decode_strict(&raw).unwrap();
+        assert_eq!(1, decoded.head().tc());
+        assert_eq!(40, decoded.answers().len());
+    }
+
+    #[test]
+    pub fn test_dns_encode_with_limit_fixed_part_too_large() {
+        let mut resp = DNS::response_for(&DNS::decode(&query_with_opt(0)).unwrap());
+        resp.with_answer(a_record("google.com", 1))
+            .with_authority(ns_record("google.com", "ns1.google.com"))
+            .with_additional(a_record("ns1.google.com", 1));
+
+        // header, question and OPT record alone take 39 bytes
+        let decoded = DNS::decode_strict(&resp.encode_with_limit(20)).unwrap();
+        assert_eq!(1, decoded.head().tc());
+        assert_eq!(1, decoded.questions().len());
+        assert_eq!(0, decoded.answers().len());
+        assert_eq!(0, decoded.authorities().len());
+        assert_eq!(0, decoded.additionals().len());
+        assert!(decoded.edns().is_some());
+    }
+
+    #[test]
+    pub fn test_dns_extended_rcode() {
+        let dns = DNS::decode(&query_with_opt(0)).unwrap();
+        assert_eq!(Rcode::YXRRSet, dns.rcode());
+        let dns = DNS::decode(&query_with_opt(1)).unwrap();
+        assert_eq!(Rcode::BadCookie, dns.rcode());
+    }
+
+    #[test]
+    pub fn test_dns_with_rcode() {
+        let mut dns = DNS::decode(&query_with_opt(0)).unwrap();
+        dns.with_rcode(Rcode::BadVers);
+        assert_eq!(Rcode::BadVers, dns.rcode());
+        assert_eq!(Rcode::NoError, dns.head().rcode());
+        assert_eq!(Rcode::BadVers, DNS::decode(&dns.encode()).unwrap().rcode());
+
+        dns.with_rcode(Rcode::NXDomain);
+        assert_eq!(Rcode::NXDomain, DNS::decode(&dns.encode()).unwrap().rcode());
+    }
+
+    #[test]
+    pub fn test_dns_with_rcode_adds_opt() {
+        let mut raw = query_with_opt(0);
+        raw.truncate(raw.len() - 11);
+        raw[11] = 0;
+        let mut dns = DNS::decode(&raw).unwrap();
+        assert!(dns.edns().is_none());
+
+        dns.with_rcode(Rcode::Refused);
+        assert!(dns.edns().is_none());
+
+        dns.with_rcode(Rcode::BadTime);
+        let decoded = DNS::decode(&dns.encode()).unwrap();
+        assert_eq!(1, decoded.head().arcount());
+        assert_eq!(Rcode::BadTime, decoded.rcode());
+    }
+
+    // The reply of 8.8.8.8 to `dig example.com A`, as captured on the wire.
+    const CAPTURED_REPLY: [u8; 56] = [
+        // id 0x5c1e, qr rd ra, NOERROR, qdcount 1, ancount 1, arcount 1
+        0x5c, 0x1e, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+        // example com, type A, class IN
+        0x07, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x01,
+        0x00, 0x01,
+        // pointer to the question, type A, class IN, ttl 3600, 93.184.215.14
+        0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0x5d, 0xb8, 0xd7,
+        0x0e, // OPT, payload 512
+        0x00, 0x00, 0x29, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    pub fn test_dns_decode_captured_reply() {
+        let reply = DNS::decode_strict(&CAPTURED_REPLY).unwrap();
+        assert_eq!(0x5c1e, reply.head().id());
+        assert_eq!(1, reply.head().qr());
+        assert_eq!(Opcode::Query, reply.head().opcode());
+        assert_eq!("qr rd ra", reply.head().flags().to_string());
+        assert_eq!(Rcode::NoError, reply.rcode());
+        let rr = reply.answers().iter().next().unwrap();
+        assert_eq!("example.com.", rr.name().to_string());
+        assert_eq!(3600, rr.ttl());
+        assert_eq!(&[93, 184, 215, 14], rr.rdata());
+        assert_eq!(512, reply.edns().unwrap().udp_payload_size());
+        // the flags go back on the wire as they came
+        assert_eq!(&CAPTURED_REPLY[..4], &reply.encode()[..4]);
+    }
 }
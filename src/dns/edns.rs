@@ -0,0 +1,204 @@
+use anyhow::Error;
+
+use super::{answer::ResourceRecord, rtype};
+
+// OPT pseudo-RR type (RFC 6891).
+pub const OPT_TYPE: u16 = rtype::OPT;
+
+// Requestor's UDP payload size advertised when we create the OPT record ourselves.
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 1232;
+
+// Extended DNS Error option (RFC 8914).
+pub const OPTION_EDE: u16 = 15;
+// EDE info code of an answer served from expired cache data (RFC 8767).
+pub const EDE_STALE_ANSWER: u16 = 3;
+
+// EDNS(0) information carried by the OPT pseudo-RR in the additional section.
+//
+// +------------+--------------+------------------------------+
+// | Field Name | Field Type   | Description                  |
+// +------------+--------------+------------------------------+
+// | NAME       | domain name  | MUST be 0 (root domain)      |
+// | TYPE       | u_int16_t    | OPT (41)                     |
+// | CLASS      | u_int16_t    | requestor's UDP payload size |
+// | TTL        | u_int32_t    | extended RCODE and flags     |
+// | RDLEN      | u_int16_t    | length of all RDATA          |
+// | RDATA      | octet stream | {attribute,value} pairs      |
+// +------------+--------------+------------------------------+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    udp_payload_size: u16,
+    ext_rcode: u8,
+    version: u8,
+    dnssec_ok: bool,
+    options: Vec<(u16, Vec<u8>)>,
+}
+
+impl Edns {
+    pub fn new() -> Self {
+        Self {
+            udp_payload_size: DEFAULT_UDP_PAYLOAD_SIZE,
+            ext_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            options: vec![],
+        }
+    }
+
+    pub fn from_rr(rr: &ResourceRecord) -> Result<Self, Error> {
+        if rr.typ() != OPT_TYPE {
+            return Err(Error::msg("the resource record is not an OPT record"));
+        }
+        if !rr.name().is_root() {
+            return Err(Error::msg("the OPT record owner must be the root domain"));
+        }
+
+        let ttl = rr.ttl().to_be_bytes();
+        let mut edns = Self {
+            udp_payload_size: rr.class(),
+            ext_rcode: ttl[0],
+            version: ttl[1],
+            dnssec_ok: ttl[2] & 0b1000_0000 == 0b1000_0000,
+            options: vec![],
+        };
+
+        let rdata = rr.rdata();
+        let mut pos = 0;
+        while pos < rdata.len() {
+            if pos + 4 > rdata.len() {
+                return Err(Error::msg("the OPT option is incomplete"));
+            }
+            let code = u16::from_be_bytes(rdata[pos..pos + 2].try_into()?);
+            let length = u16::from_be_bytes(rdata[pos + 2..pos + 4].try_into()?) as usize;
+            pos += 4;
+            if pos + length > rdata.len() {
+                return Err(Error::msg("the OPT option data is incomplete"));
+            }
+            edns.options.push((code, rdata[pos..pos + length].to_vec()));
+            pos += length;
+        }
+
+        return Ok(edns);
+    }
+
+    pub fn udp_payload_size(&self) -> u16 {
+        return self.udp_payload_size;
+    }
+
+    pub fn with_udp_payload_size(&mut self, size: u16) -> &mut Self {
+        self.udp_payload_size = size;
+        return self;
+    }
+
+    // Upper 8 bits of the 12-bit RCODE.
+    pub fn ext_rcode(&self) -> u8 {
+        return self.ext_rcode;
+    }
+
+    pub fn with_ext_rcode(&mut self, ext_rcode: u8) -> &mut Self {
+        self.ext_rcode = ext_rcode;
+        return self;
+    }
+
+    pub fn version(&self) -> u8 {
+        return self.version;
+    }
+
+    pub fn with_version(&mut self, version: u8) -> &mut Self {
+        self.version = version;
+        return self;
+    }
+
+    // DNSSEC OK (DO): 1 bit
+    pub fn dnssec_ok(&self) -> bool {
+        return self.dnssec_ok;
+    }
+
+    pub fn with_dnssec_ok(&mut self, dnssec_ok: bool) -> &mut Self {
+        self.dnssec_ok = dnssec_ok;
+        return self;
+    }
+
+    pub fn options(&self) -> &Vec<(u16, Vec<u8>)> {
+        return &self.options;
+    }
+
+    pub fn with_option(&mut self, code: u16, data: Vec<u8>) -> &mut Self {
+        self.options.push((code, data));
+        return self;
+    }
+
+    // Add an Extended DNS Error with an optional UTF-8 explanation.
+    pub fn with_extended_error(&mut self, info_code: u16, text: &str) -> &mut Self {
+        let mut data = info_code.to_be_bytes().to_vec();
+        data.extend_from_slice(text.as_bytes());
+        return self.with_option(OPTION_EDE, data);
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut rdata = Vec::<u8>::new();
+        for (code, data) in &self.options {
+            rdata.extend_from_slice(&code.to_be_bytes());
+            rdata.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            rdata.extend_from_slice(data);
+        }
+
+        let flags: u8 = if self.dnssec_ok { 0b1000_0000 } else { 0 };
+        let ttl = u32::from_be_bytes([self.ext_rcode, self.version, flags, 0]);
+
+        let mut result = Vec::<u8>::new();
+        // root domain
+        result.push(b'\x00');
+        result.extend_from_slice(&OPT_TYPE.to_be_bytes());
+        result.extend_from_slice(&self.udp_payload_size.to_be_bytes());
+        result.extend_from_slice(&ttl.to_be_bytes());
+        result.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        result.extend_from_slice(&rdata);
+
+        return result;
+    }
+}
+
+impl Default for Edns {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_edns_round_trip() {
+        let mut raw = Vec::<u8>::new();
+        // root, type OPT, payload 4096
+        raw.extend_from_slice(&[0x00, 0x00, 0x29, 0x10, 0x00]);
+        // ext rcode 1, version 0, DO
+        raw.extend_from_slice(&[0x01, 0x00, 0x80, 0x00]);
+        // rdlength 6, option 10 (cookie) with 2 bytes
+        raw.extend_from_slice(&[0x00, 0x06, 0x00, 0x0a, 0x00, 0x02, 0xab, 0xcd]);
+        let (rr, _) = ResourceRecord::decode(&raw, 0).unwrap();
+        let edns = Edns::from_rr(&rr).unwrap();
+        assert_eq!(4096, edns.udp_payload_size());
+        assert_eq!(1, edns.ext_rcode());
+        assert_eq!(0, edns.version());
+        assert!(edns.dnssec_ok());
+        assert_eq!(vec![(10_u16, vec![0xab_u8, 0xcd])], *edns.options());
+        assert_eq!(raw, edns.encode());
+    }
+
+    #[test]
+    pub fn test_edns_invalid() {
+        let mut rr = ResourceRecord::new();
+        rr.with_type(1);
+        assert!(Edns::from_rr(&rr).is_err());
+
+        // truncated option header
+        let raw = vec![
+            0x00, 0x00, 0x29, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x0a,
+        ];
+        let (rr, _) = ResourceRecord::decode(&raw, 0).unwrap();
+        assert!(Edns::from_rr(&rr).is_err());
+    }
+}
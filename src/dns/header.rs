@@ -1,3 +1,7 @@
+use std::fmt;
+
+use super::{opcode::Opcode, rcode::Rcode};
+
 trait BitOption {
     fn set_0(&mut self, pos: u8);
     fn set_1(&mut self, pos: u8);
@@ -14,7 +18,7 @@ impl BitOption for &mut u8 {
             return;
         }
         // pos位是1，将其置为0
-        **self = **self & (!value);
+        **self &= !value;
     }
 
     fn set_1(&mut self, pos: u8) {
@@ -27,10 +31,11 @@ impl BitOption for &mut u8 {
             return;
         }
         // pos位是0，将其置为1
-        **self = **self & u8::MAX | value;
+        **self |= value;
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Header([u8; 12]);
 
 impl Header {
@@ -70,47 +75,31 @@ impl Header {
     // 1 for a reply packet, 0 for a question packet.
     // Expected value: 1.
     pub fn qr(&self) -> u8 {
-        return self.0[2] & 0b0000_0001;
+        return (self.0[2] & 0b1000_0000) >> 7;
     }
 
     pub fn with_qr(&mut self, qr: u8) -> &mut Self {
         if qr > 1 {
             return self;
         }
-        return self.set_bit(2, 0, qr);
+        return self.set_bit(2, 7, qr);
     }
 
     // Operation Code (OPCODE): 4 bits
     // Specifies the kind of query in a message.
     // Expected value: 0.
-    pub fn opcode(&self) -> u8 {
-        return (self.0[2] & 0b0001_1110) >> 1;
+    pub fn opcode(&self) -> Opcode {
+        return Opcode::from((self.0[2] & 0b0111_1000) >> 3);
     }
 
-    pub fn with_opcode(&mut self, opcode: u8) -> &mut Self {
-        if opcode << 1 > 0b0001_1110 {
+    pub fn with_opcode(&mut self, opcode: Opcode) -> &mut Self {
+        let opcode = u8::from(opcode);
+        if opcode > 0b0000_1111 {
             return self;
         }
 
-        if opcode << 1 & 0b0000_0010 == 0b0000_0010 {
-            self.set_bit(2, 1, 1);
-        } else {
-            self.set_bit(2, 1, 0);
-        }
-        if opcode << 1 & 0b0000_0100 == 0b0000_0100 {
-            self.set_bit(2, 2, 1);
-        } else {
-            self.set_bit(2, 2, 0);
-        }
-        if opcode << 1 & 0b0000_1000 == 0b0000_1000 {
-            self.set_bit(2, 3, 1);
-        } else {
-            self.set_bit(2, 3, 0);
-        }
-        if opcode << 1 & 0b0001_0000 == 0b0001_0000 {
-            self.set_bit(2, 4, 1);
-        } else {
-            self.set_bit(2, 4, 0);
+        for pos in 0..4 {
+            self.set_bit(2, pos + 3, (opcode >> pos) & 1);
         }
 
         return self;
@@ -120,120 +109,121 @@ impl Header {
     // 1 if the responding server "owns" the domain queried, i.e., it's authoritative.
     // Expected value: 0.
     pub fn aa(&self) -> u8 {
-        return (self.0[2] & 0b0010_0000) >> 5;
+        return (self.0[2] & 0b0000_0100) >> 2;
     }
 
     pub fn with_aa(&mut self, aa: u8) -> &mut Self {
         if aa > 1 {
             return self;
         }
-        return self.set_bit(2, 5, aa);
+        return self.set_bit(2, 2, aa);
     }
 
     // Truncation (TC): 1 bit
     // 1 if the message is larger than 512 bytes. Always 0 in UDP responses.
     // Expected value: 0.
     pub fn tc(&self) -> u8 {
-        return (self.0[2] & 0b0100_0000) >> 6;
+        return (self.0[2] & 0b0000_0010) >> 1;
     }
 
     pub fn with_tc(&mut self, tc: u8) -> &mut Self {
         if tc > 1 {
             return self;
         }
-        return self.set_bit(2, 6, tc);
+        return self.set_bit(2, 1, tc);
     }
 
     // Recursion Desired (RD): 1 bit
     // Sender sets this to 1 if the server should recursively resolve this query, 0 otherwise.
     // Expected value: 0.
     pub fn rd(&self) -> u8 {
-        return (self.0[2] & 0b1000_0000) >> 7;
+        return self.0[2] & 0b0000_0001;
     }
 
     pub fn with_rd(&mut self, rd: u8) -> &mut Self {
         if rd > 1 {
             return self;
         }
-        return self.set_bit(2, 7, rd);
+        return self.set_bit(2, 0, rd);
     }
 
     // Recursion Available (RA): 1 bit
     // Server sets this to 1 to indicate that recursion is available.
     // Expected value: 0.
     pub fn ra(&self) -> u8 {
-        return self.0[3] & 0b0000_0001;
+        return (self.0[3] & 0b1000_0000) >> 7;
     }
 
     pub fn with_ra(&mut self, ra: u8) -> &mut Self {
         if ra > 1 {
             return self;
         }
-        return self.set_bit(3, 0, ra);
+        return self.set_bit(3, 7, ra);
     }
 
     // Reserved (Z): 3 bits
     // Used by DNSSEC queries. At inception, it was reserved for future use.
+    // The highest bit is still reserved, the other two are AD and CD (RFC 4035 3.2),
+    // see `ad` and `cd` to access them individually.
     // Expected value: 0.
     pub fn z(&self) -> u8 {
-        return (self.0[3] & 0b0000_1110) >> 1;
+        return (self.0[3] & 0b0111_0000) >> 4;
     }
 
     pub fn with_z(&mut self, z: u32) -> &mut Self {
-        if z << 1 > 0b0000_1110 {
+        if z > 0b0000_0111 {
             return self;
         }
 
-        if z << 1 & 0b0000_0010 == 0b0000_0010 {
-            self.set_bit(3, 1, 1);
-        } else {
-            self.set_bit(3, 1, 0);
-        }
-        if z << 1 & 0b0000_0100 == 0b0000_0100 {
-            self.set_bit(3, 2, 1);
-        } else {
-            self.set_bit(3, 2, 0);
-        }
-        if z << 1 & 0b0000_1000 == 0b0000_1000 {
-            self.set_bit(3, 3, 1);
-        } else {
-            self.set_bit(3, 3, 0);
+        for pos in 0..3 {
+            self.set_bit(3, pos + 4, ((z >> pos) & 1) as u8);
         }
 
         return self;
     }
 
-    // Response Code (RCODE):4 bits
-    // Response code indicating the status of the response.
-    // Expected value: 0 (no error).
-    pub fn rcode(&self) -> u8 {
-        return (self.0[3] & 0b1111_0000) >> 4;
+    // Authentic Data (AD): 1 bit
+    // Set by a validating resolver when all data in the answer has been verified.
+    // Expected value: 0.
+    pub fn ad(&self) -> u8 {
+        return (self.0[3] & 0b0010_0000) >> 5;
     }
 
-    pub fn with_rcode(&mut self, rcode: u32) -> &mut Self {
-        if rcode << 4 > 0b1111_0000 {
+    pub fn with_ad(&mut self, ad: u8) -> &mut Self {
+        if ad > 1 {
             return self;
         }
+        return self.set_bit(3, 5, ad);
+    }
 
-        if rcode << 4 & 0b0001_0000 == 0b0001_0000 {
-            self.set_bit(3, 4, 1);
-        } else {
-            self.set_bit(3, 4, 0);
-        }
-        if rcode << 4 & 0b0010_0000 == 0b0010_0000 {
-            self.set_bit(3, 5, 1);
-        } else {
-            self.set_bit(3, 5, 0);
-        }
-        if rcode << 4 & 0b0100_0000 == 0b0100_0000 {
-            self.set_bit(3, 6, 1);
-        } else {
-            self.set_bit(3, 6, 0);
+    // Checking Disabled (CD): 1 bit
+    // Set by the client to ask the resolver not to perform DNSSEC validation.
+    // Expected value: 0.
+    pub fn cd(&self) -> u8 {
+        return (self.0[3] & 0b0001_0000) >> 4;
+    }
+
+    pub fn with_cd(&mut self, cd: u8) -> &mut Self {
+        if cd > 1 {
+            return self;
         }
-        if rcode << 4 & 0b1000_0000 == 0b1000_0000 {
-            self.set_bit(3, 7, 1);
-        } else {
-            self.set_bit(3, 7, 0);
+        return self.set_bit(3, 4, cd);
+    }
+
+    // Response Code (RCODE):4 bits
+    // Response code indicating the status of the response.
+    // Only the lower 4 bits of the 12-bit RCODE are stored here, the upper 8 bits live in the
+    // OPT record. Use `DNS::rcode` / `DNS::with_rcode` to read or write the combined value.
+    // Expected value: 0 (no error).
+    pub fn rcode(&self) -> Rcode {
+        return Rcode::from((self.0[3] & 0b0000_1111) as u16);
+    }
+
+    pub fn with_rcode(&mut self, rcode: Rcode) -> &mut Self {
+        let rcode = rcode.low();
+
+        for pos in 0..4 {
+            self.set_bit(3, pos, (rcode >> pos) & 1);
         }
 
         return self;
@@ -295,11 +285,138 @@ impl Header {
         return self;
     }
 
+    // All single bit flags at once.
+    pub fn flags(&self) -> HeaderFlags {
+        return HeaderFlags {
+            qr: self.qr() == 1,
+            aa: self.aa() == 1,
+            tc: self.tc() == 1,
+            rd: self.rd() == 1,
+            ra: self.ra() == 1,
+            ad: self.ad() == 1,
+            cd: self.cd() == 1,
+        };
+    }
+
+    pub fn with_flags(&mut self, flags: HeaderFlags) -> &mut Self {
+        return self
+            .with_qr(flags.qr as u8)
+            .with_aa(flags.aa as u8)
+            .with_tc(flags.tc as u8)
+            .with_rd(flags.rd as u8)
+            .with_ra(flags.ra as u8)
+            .with_ad(flags.ad as u8)
+            .with_cd(flags.cd as u8);
+    }
+
     pub fn get_0(&self) -> [u8; 12] {
         return self.0;
     }
 }
 
+// The single bit flags of a header as one value.
+// Displays like the `flags:` line of dig, e.g. `qr aa rd ra ad`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HeaderFlags {
+    qr: bool,
+    aa: bool,
+    tc: bool,
+    rd: bool,
+    ra: bool,
+    ad: bool,
+    cd: bool,
+}
+
+impl HeaderFlags {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn qr(&self) -> bool {
+        return self.qr;
+    }
+
+    pub fn with_qr(&mut self, qr: bool) -> &mut Self {
+        self.qr = qr;
+        return self;
+    }
+
+    pub fn aa(&self) -> bool {
+        return self.aa;
+    }
+
+    pub fn with_aa(&mut self, aa: bool) -> &mut Self {
+        self.aa = aa;
+        return self;
+    }
+
+    pub fn tc(&self) -> bool {
+        return self.tc;
+    }
+
+    pub fn with_tc(&mut self, tc: bool) -> &mut Self {
+        self.tc = tc;
+        return self;
+    }
+
+    pub fn rd(&self) -> bool {
+        return self.rd;
+    }
+
+    pub fn with_rd(&mut self, rd: bool) -> &mut Self {
+        self.rd = rd;
+        return self;
+    }
+
+    pub fn ra(&self) -> bool {
+        return self.ra;
+    }
+
+    pub fn with_ra(&mut self, ra: bool) -> &mut Self {
+        self.ra = ra;
+        return self;
+    }
+
+    pub fn ad(&self) -> bool {
+        return self.ad;
+    }
+
+    pub fn with_ad(&mut self, ad: bool) -> &mut Self {
+        self.ad = ad;
+        return self;
+    }
+
+    pub fn cd(&self) -> bool {
+        return self.cd;
+    }
+
+    pub fn with_cd(&mut self, cd: bool) -> &mut Self {
+        self.cd = cd;
+        return self;
+    }
+}
+
+impl fmt::Display for HeaderFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (self.qr, "qr"),
+            (self.aa, "aa"),
+            (self.tc, "tc"),
+            (self.rd, "rd"),
+            (self.ra, "ra"),
+            (self.ad, "ad"),
+            (self.cd, "cd"),
+        ];
+        let set: Vec<&str> = names
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect();
+
+        return write!(f, "{}", set.join(" "));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -319,9 +436,9 @@ mod tests {
 
     #[test]
     pub fn test_header_qr() {
-        let mut head = Header([0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.qr());
-        let head = Header([0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0x7f, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(0, head.qr());
     }
 
@@ -339,29 +456,42 @@ mod tests {
     #[test]
     pub fn test_header_opcode() {
         let mut head = Header([0, 0, u8::MAX, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        assert_eq!(15, head.opcode());
-        head = Header([0, 0, 30, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        assert_eq!(15, head.opcode());
-        head = Header([0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        assert_eq!(1, head.opcode());
+        assert_eq!(Opcode::Unknown(15), head.opcode());
+        head = Header([0, 0, 0x78, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Opcode::Unknown(15), head.opcode());
+        head = Header([0, 0, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Opcode::IQuery, head.opcode());
+        head = Header([0, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Opcode::Notify, head.opcode());
+        head = Header([0, 0, 0x87, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Opcode::Query, head.opcode());
     }
 
     #[test]
     pub fn test_header_with_opcode() {
         let mut head = Header([0; 12]);
-        head.with_opcode(12);
-        assert_eq!(12, head.opcode());
-        head.with_opcode(99);
-        assert_eq!(12, head.opcode());
-        head.with_opcode(15);
-        assert_eq!(15, head.opcode());
+        head.with_opcode(Opcode::Update);
+        assert_eq!(Opcode::Update, head.opcode());
+        head.with_opcode(Opcode::Unknown(12));
+        assert_eq!(Opcode::Unknown(12), head.opcode());
+        head.with_opcode(Opcode::Unknown(99));
+        assert_eq!(Opcode::Unknown(12), head.opcode());
+        head.with_opcode(Opcode::Unknown(15));
+        assert_eq!(Opcode::Unknown(15), head.opcode());
+        head.with_opcode(Opcode::Query);
+        assert_eq!(Opcode::Query, head.opcode());
+
+        head.with_opcode(Opcode::Notify);
+        assert_eq!(0x20, head.0[2]);
     }
 
     #[test]
     pub fn test_header_aa() {
-        let mut head = Header([0, 0, u8::MAX, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, u8::MAX, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(1, head.aa());
+        let head = Header([0, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.aa());
-        let mut head = Header([0, 0, 64, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0xfb, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(0, head.aa());
     }
 
@@ -378,11 +508,11 @@ mod tests {
 
     #[test]
     pub fn test_header_tc() {
-        let mut head = Header([0, 0, u8::MAX, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, u8::MAX, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.tc());
-        let mut head = Header([0, 0, 64, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.tc());
-        let mut head = Header([0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(0, head.tc());
     }
 
@@ -399,11 +529,11 @@ mod tests {
 
     #[test]
     pub fn test_header_rd() {
-        let mut head = Header([0, 0, u8::MAX, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, u8::MAX, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.rd());
-        let mut head = Header([0, 0, 160, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.rd());
-        let mut head = Header([0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0xfe, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(0, head.rd());
     }
 
@@ -420,11 +550,11 @@ mod tests {
 
     #[test]
     pub fn test_header_ra() {
-        let mut head = Header([0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.ra());
-        let mut head = Header([0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0, 0xc0, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(1, head.ra());
-        let mut head = Header([0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0, 0x7f, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(0, head.ra());
     }
 
@@ -441,8 +571,10 @@ mod tests {
 
     #[test]
     pub fn test_header_z() {
-        let mut head = Header([0, 0, 0, 14, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0, 0x70, 0, 0, 0, 0, 0, 0, 0, 0]);
         assert_eq!(7, head.z());
+        let head = Header([0, 0, 0, 0x8f, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(0, head.z());
     }
 
     #[test]
@@ -454,30 +586,149 @@ mod tests {
         assert_eq!(7, head.z());
         head.with_z(8);
         assert_eq!(7, head.z());
+        assert_eq!(0x70, head.0[3]);
+    }
+
+    #[test]
+    pub fn test_header_ad() {
+        let head = Header([0, 0, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(1, head.ad());
+        assert_eq!(0, head.cd());
+        assert_eq!(2, head.z());
+    }
+
+    #[test]
+    pub fn test_header_with_ad() {
+        let mut head = Header([0; 12]);
+        head.with_ad(1);
+        assert_eq!(1, head.ad());
+        head.with_ad(2);
+        assert_eq!(1, head.ad());
+        head.with_ad(0);
+        assert_eq!(0, head.ad());
+    }
+
+    #[test]
+    pub fn test_header_cd() {
+        let head = Header([0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(1, head.cd());
+        assert_eq!(0, head.ad());
+        assert_eq!(1, head.z());
+    }
+
+    #[test]
+    pub fn test_header_with_cd() {
+        let mut head = Header([0; 12]);
+        head.with_cd(1);
+        assert_eq!(1, head.cd());
+        head.with_cd(2);
+        assert_eq!(1, head.cd());
+        head.with_cd(0);
+        assert_eq!(0, head.cd());
+    }
+
+    #[test]
+    pub fn test_header_flags() {
+        let mut head = Header([0; 12]);
+        head.with_qr(1).with_rd(1).with_ra(1).with_ad(1);
+        let flags = head.flags();
+        assert!(flags.qr());
+        assert!(!flags.aa());
+        assert!(flags.ad());
+        assert!(!flags.cd());
+
+        let mut expected = HeaderFlags::new();
+        expected
+            .with_qr(true)
+            .with_rd(true)
+            .with_ra(true)
+            .with_ad(true);
+        assert_eq!(expected, flags);
+    }
+
+    #[test]
+    pub fn test_header_with_flags() {
+        let mut head = Header([0; 12]);
+        head.with_rcode(Rcode::NXDomain).with_opcode(Opcode::Notify);
+        let mut flags = HeaderFlags::new();
+        flags.with_aa(true).with_tc(true).with_cd(true);
+        head.with_flags(flags);
+        assert_eq!(flags, head.flags());
+        assert_eq!(Rcode::NXDomain, head.rcode());
+        assert_eq!(Opcode::Notify, head.opcode());
+
+        head.with_flags(HeaderFlags::new());
+        assert_eq!(HeaderFlags::new(), head.flags());
+        assert_eq!(Rcode::NXDomain, head.rcode());
+    }
+
+    #[test]
+    pub fn test_header_flags_display() {
+        let mut flags = HeaderFlags::new();
+        assert_eq!("", flags.to_string());
+        flags
+            .with_qr(true)
+            .with_aa(true)
+            .with_rd(true)
+            .with_ra(true)
+            .with_ad(true);
+        assert_eq!("qr aa rd ra ad", flags.to_string());
+        flags.with_aa(false).with_cd(true);
+        assert_eq!("qr rd ra ad cd", flags.to_string());
     }
 
     #[test]
     pub fn test_header_rcode() {
-        let mut head = Header([0, 0, 0, 240, 0, 0, 0, 0, 0, 0, 0, 0]);
-        assert_eq!(15, head.rcode());
+        let head = Header([0, 0, 0, 0x0f, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Rcode::Unknown(15), head.rcode());
+        let head = Header([0, 0, 0, 0x83, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Rcode::NXDomain, head.rcode());
     }
 
     #[test]
     pub fn test_header_with_rcode() {
         let mut head = Header([0; 12]);
-        head.with_rcode(1);
-        assert_eq!(1, head.rcode());
-        head.with_rcode(7);
-        assert_eq!(7, head.rcode());
-        head.with_rcode(15);
-        assert_eq!(15, head.rcode());
-        head.with_rcode(16);
-        assert_eq!(15, head.rcode());
+        head.with_rcode(Rcode::FormErr);
+        assert_eq!(Rcode::FormErr, head.rcode());
+        head.with_rcode(Rcode::YXRRSet);
+        assert_eq!(Rcode::YXRRSet, head.rcode());
+        head.with_rcode(Rcode::Unknown(15));
+        assert_eq!(Rcode::Unknown(15), head.rcode());
+        // only the lower 4 bits of an extended rcode fit in the header
+        head.with_rcode(Rcode::BadCookie);
+        assert_eq!(Rcode::YXRRSet, head.rcode());
+        head.with_rcode(Rcode::BadVers);
+        assert_eq!(Rcode::NoError, head.rcode());
+    }
+
+    #[test]
+    pub fn test_header_wire_layout() {
+        // a query from `dig example.com`: rd and ad set (flags 0x0120)
+        let query = Header([0x5c, 0x1e, 0x01, 0x20, 0, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(0, query.qr());
+        assert_eq!(Opcode::Query, query.opcode());
+        assert_eq!("rd ad", query.flags().to_string());
+
+        // the answer of a public resolver: qr rd ra, NOERROR (flags 0x8180)
+        let reply = Header([0x5c, 0x1e, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 1]);
+        assert_eq!("qr rd ra", reply.flags().to_string());
+        assert_eq!(Rcode::NoError, reply.rcode());
+        // an authoritative NXDOMAIN (flags 0x8403)
+        let reply = Header([0x5c, 0x1e, 0x84, 0x03, 0, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!("qr aa", reply.flags().to_string());
+        assert_eq!(Rcode::NXDomain, reply.rcode());
+
+        let mut head = Header([0; 12]);
+        head.with_qr(1)
+            .with_rd(1)
+            .with_ra(1)
+            .with_rcode(Rcode::ServFail);
+        assert_eq!([0x81, 0x82], [head.0[2], head.0[3]]);
     }
 
     #[test]
     pub fn test_header_qdcount() {
-        let mut head = Header([0, 0, 0, 14, 2, 4, 0, 0, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0, 14, 2, 4, 0, 0, 0, 0, 0, 0]);
         assert_eq!(516, head.qdcount());
     }
 
@@ -493,7 +744,7 @@ mod tests {
 
     #[test]
     pub fn test_header_ancount() {
-        let mut head = Header([0, 0, 0, 14, 0, 0, 2, 4, 0, 0, 0, 0]);
+        let head = Header([0, 0, 0, 14, 0, 0, 2, 4, 0, 0, 0, 0]);
         assert_eq!(516, head.ancount());
     }
 
@@ -509,7 +760,7 @@ mod tests {
 
     #[test]
     pub fn test_header_nscount() {
-        let mut head = Header([0, 0, 0, 14, 0, 0, 0, 0, 2, 4, 0, 0]);
+        let head = Header([0, 0, 0, 14, 0, 0, 0, 0, 2, 4, 0, 0]);
         assert_eq!(516, head.nscount());
     }
 
@@ -525,7 +776,7 @@ mod tests {
 
     #[test]
     pub fn test_header_arcount() {
-        let mut head = Header([0, 0, 0, 14, 0, 0, 0, 0, 0, 0, 2, 4]);
+        let head = Header([0, 0, 0, 14, 0, 0, 0, 0, 0, 0, 2, 4]);
         assert_eq!(516, head.arcount());
     }
 
@@ -0,0 +1,291 @@
+use anyhow::Error;
+
+use super::name::escape_label;
+
+// ACE prefix of an A-label (RFC 5890 2.3.2.5).
+pub const ACE_PREFIX: &str = "xn--";
+
+// Bootstring parameters for Punycode (RFC 3492 5).
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+// Full stops that UTS #46 maps to `.` when splitting a name into labels.
+pub const DOTS: [char; 4] = ['.', '\u{3002}', '\u{ff0e}', '\u{ff61}'];
+
+fn adapt(delta: u32, numpoints: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / numpoints;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    return k + (BASE - TMIN + 1) * delta / (delta + SKEW);
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        return TMIN;
+    }
+    if k >= bias + TMAX {
+        return TMAX;
+    }
+    return k - bias;
+}
+
+fn encode_digit(d: u32) -> char {
+    return match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    };
+}
+
+fn decode_digit(c: char) -> Option<u32> {
+    return match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+        _ => None,
+    };
+}
+
+// Punycode encoding of a Unicode label, without the ACE prefix (RFC 3492 6.3).
+pub fn punycode_encode(input: &str) -> Result<String, Error> {
+    let overflow = || Error::msg(format!("punycode overflow encoding: {}", input));
+    let chars: Vec<u32> = input.chars().map(|c| c as u32).collect();
+
+    let mut output: String = input.chars().filter(|c| c.is_ascii()).collect();
+    let basic = output.len() as u32;
+    let mut handled = basic;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    while (handled as usize) < chars.len() {
+        let m = *chars
+            .iter()
+            .filter(|c| **c >= n)
+            .min()
+            .ok_or_else(overflow)?;
+        delta = delta
+            .checked_add((m - n).checked_mul(handled + 1).ok_or_else(overflow)?)
+            .ok_or_else(overflow)?;
+        n = m;
+        for c in &chars {
+            if *c < n {
+                delta = delta.checked_add(1).ok_or_else(overflow)?;
+            }
+            if *c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+
+    return Ok(output);
+}
+
+// Punycode decoding of a label without the ACE prefix (RFC 3492 6.2).
+pub fn punycode_decode(input: &str) -> Result<String, Error> {
+    let invalid = || Error::msg(format!("invalid punycode: {}", input));
+
+    let (basic, extended) = match input.rfind('-') {
+        Some(pos) => (&input[..pos], &input[pos + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return Err(invalid());
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.chars();
+    while !digits.as_str().is_empty() {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = digits.next().and_then(decode_digit).ok_or_else(invalid)?;
+            i = digit
+                .checked_mul(w)
+                .and_then(|v| i.checked_add(v))
+                .ok_or_else(invalid)?;
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t).ok_or_else(invalid)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len).ok_or_else(invalid)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n).ok_or_else(invalid)?);
+        i += 1;
+    }
+
+    return Ok(output.into_iter().collect());
+}
+
+// UTS #46 mapping and validity checks for a single label, nontransitional processing.
+// Characters are lowercased with the Unicode default case mapping. NFC normalization is
+// not applied, labels are expected to be typed in composed form.
+fn map_label(label: &str) -> Result<String, Error> {
+    let mapped: String = label.chars().flat_map(|c| c.to_lowercase()).collect();
+    if let Some(c) = mapped
+        .chars()
+        .find(|c| c.is_control() || c.is_whitespace() || DOTS.contains(c))
+    {
+        return Err(Error::msg(format!(
+            "disallowed character {:?} in label: {}",
+            c, label
+        )));
+    }
+    if mapped.starts_with('-') || mapped.ends_with('-') {
+        return Err(Error::msg(format!(
+            "label must not start or end with a hyphen: {}",
+            label
+        )));
+    }
+    if mapped.get(2..4) == Some("--") {
+        return Err(Error::msg(format!(
+            "hyphens in the third and fourth position: {}",
+            label
+        )));
+    }
+
+    return Ok(mapped);
+}
+
+// Convert a Unicode U-label to its A-label. ASCII-only labels are returned unchanged.
+pub fn to_ascii(label: &str) -> Result<Vec<u8>, Error> {
+    if label.is_ascii() {
+        return Ok(label.as_bytes().to_vec());
+    }
+
+    let mapped = map_label(label)?;
+    let ace = format!("{}{}", ACE_PREFIX, punycode_encode(&mapped)?);
+
+    return Ok(ace.into_bytes());
+}
+
+// Convert an A-label to its U-label for display. Labels that are not valid A-labels are
+// returned in their escaped presentation form.
+pub fn to_unicode(label: &[u8]) -> String {
+    let escaped = escape_label(label);
+    let ace = match std::str::from_utf8(label) {
+        Ok(ace) if ace.len() > ACE_PREFIX.len() && ace[..4].eq_ignore_ascii_case(ACE_PREFIX) => ace,
+        _ => return escaped,
+    };
+
+    let unicode = match punycode_decode(&ace[4..]).and_then(|u| map_label(&u)) {
+        Ok(unicode) if !unicode.is_ascii() => unicode,
+        _ => return escaped,
+    };
+    // only accept labels that survive a round trip, this rejects fake A-labels
+    match to_ascii(&unicode) {
+        Ok(back) if back.eq_ignore_ascii_case(label) => return unicode,
+        _ => return escaped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_punycode_rfc3492_samples() {
+        let samples = [
+            // (A) Arabic (Egyptian)
+            (
+                "\u{0644}\u{064A}\u{0647}\u{0645}\u{0627}\u{0628}\u{062A}\u{0643}\u{0644}\u{0645}\u{0648}\u{0634}\u{0639}\u{0631}\u{0628}\u{064A}\u{061F}",
+                "egbpdaj6bu4bxfgehfvwxn",
+            ),
+            // (B) Chinese (simplified)
+            (
+                "\u{4ED6}\u{4EEC}\u{4E3A}\u{4EC0}\u{4E48}\u{4E0D}\u{8BF4}\u{4E2D}\u{6587}",
+                "ihqwcrb4cv8a8dqg056pqjye",
+            ),
+            // (L) 3<nen>B<gumi><kinpachi><sensei>
+            (
+                "3\u{5E74}B\u{7D44}\u{91D1}\u{516B}\u{5148}\u{751F}",
+                "3B-ww4c5e180e575a65lsy2b",
+            ),
+            ("b\u{00fc}cher", "bcher-kva"),
+            ("m\u{00fc}nchen", "mnchen-3ya"),
+        ];
+        for (unicode, puny) in samples {
+            assert_eq!(puny, punycode_encode(unicode).unwrap());
+            assert_eq!(unicode, punycode_decode(puny).unwrap());
+        }
+    }
+
+    #[test]
+    pub fn test_punycode_decode_invalid() {
+        assert!(punycode_decode("abc-!").is_err());
+        assert!(punycode_decode("\u{00fc}-abc").is_err());
+        assert!(punycode_decode("99999999999").is_err());
+    }
+
+    #[test]
+    pub fn test_to_ascii() {
+        assert_eq!(
+            b"xn--mnchen-3ya".to_vec(),
+            to_ascii("m\u{00fc}nchen").unwrap()
+        );
+        // UTS #46 maps to lower case
+        assert_eq!(
+            b"xn--mnchen-3ya".to_vec(),
+            to_ascii("M\u{00dc}NCHEN").unwrap()
+        );
+        assert_eq!(
+            b"xn--wgv71a".to_vec(),
+            to_ascii("\u{65e5}\u{672c}").unwrap()
+        );
+        // ASCII labels are left alone
+        assert_eq!(b"WWW".to_vec(), to_ascii("WWW").unwrap());
+
+        assert!(to_ascii("-m\u{00fc}nchen").is_err());
+        assert!(to_ascii("m\u{00fc} nchen").is_err());
+        assert!(to_ascii("m\u{00fc}\u{3002}de").is_err());
+    }
+
+    #[test]
+    pub fn test_to_unicode() {
+        assert_eq!("m\u{00fc}nchen", to_unicode(b"xn--mnchen-3ya"));
+        assert_eq!("m\u{00fc}nchen", to_unicode(b"XN--MNCHEN-3YA"));
+        assert_eq!("example", to_unicode(b"example"));
+        // not a valid A-label, shown as is
+        assert_eq!("xn--ab!c", to_unicode(b"xn--ab!c"));
+        // decodes to plain ASCII, which is never encoded as an A-label
+        assert_eq!("xn--abc-", to_unicode(b"xn--abc-"));
+        assert_eq!("xn--", to_unicode(b"xn--"));
+        assert_eq!("\\255", to_unicode(&[0xff]));
+    }
+}
@@ -1,6 +1,13 @@
 pub mod answer;
 pub mod dns;
+pub mod edns;
 pub mod header;
+pub mod idna;
+pub mod name;
+pub mod opcode;
 pub mod question;
+pub mod rcode;
+pub mod rtype;
 
 pub use dns::DNS;
+pub use name::DomainName;
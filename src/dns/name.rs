@@ -0,0 +1,482 @@
+use anyhow::Error;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use super::idna;
+
+// RFC 1035 2.3.4 size limits.
+pub const MAX_LABEL_LENGTH: usize = 63;
+pub const MAX_NAME_LENGTH: usize = 255;
+
+// Upper bound of compression pointers followed while reading a single name,
+// protects against pointer loops in malicious packets.
+const MAX_POINTERS: usize = 64;
+
+// A domain name made of labels, the root label is implicit.
+// Labels are arbitrary octets (RFC 2181 11) and are re-encoded exactly as received.
+// Comparison and hashing ignore ASCII case (RFC 4343) while the original case is kept
+// for encoding and display.
+#[derive(Debug, Clone, Default)]
+pub struct DomainName {
+    labels: Vec<Vec<u8>>,
+}
+
+impl DomainName {
+    pub fn root() -> Self {
+        return Self { labels: vec![] };
+    }
+
+    pub fn from_labels<I, S>(labels: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Vec<u8>>,
+    {
+        let name = Self {
+            labels: labels.into_iter().map(|l| l.into()).collect(),
+        };
+        name.validate()?;
+
+        return Ok(name);
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for label in &self.labels {
+            if label.is_empty() {
+                return Err(Error::msg("empty label in domain name"));
+            }
+            if label.len() > MAX_LABEL_LENGTH {
+                return Err(Error::msg(format!(
+                    "label exceeds {} bytes: {}",
+                    MAX_LABEL_LENGTH,
+                    escape_label(label)
+                )));
+            }
+        }
+        if self.wire_length() > MAX_NAME_LENGTH {
+            return Err(Error::msg(format!(
+                "domain name exceeds {} bytes",
+                MAX_NAME_LENGTH
+            )));
+        }
+
+        return Ok(());
+    }
+
+    // Read a possibly compressed domain name (RFC 1035 4.1.4) located at `offset` of the whole
+    // message. Returns the name and the offset right after the name at its original position.
+    pub fn decode(msg: &[u8], offset: usize) -> Result<(Self, usize), Error> {
+        let pkg_err = || Error::msg("the domain name is incomplete");
+
+        let mut labels = Vec::<Vec<u8>>::new();
+        let mut pos = offset;
+        let mut end: Option<usize> = None;
+        let mut jumps = 0;
+        loop {
+            let length = *msg.get(pos).ok_or_else(pkg_err)? as usize;
+            match length & 0b1100_0000 {
+                0b1100_0000 => {
+                    let low = *msg.get(pos + 1).ok_or_else(pkg_err)? as usize;
+                    if end.is_none() {
+                        end = Some(pos + 2);
+                    }
+                    jumps += 1;
+                    if jumps > MAX_POINTERS {
+                        return Err(Error::msg("too many compression pointers in domain name"));
+                    }
+                    pos = (length & 0b0011_1111) << 8 | low;
+                }
+                0b0000_0000 => {
+                    pos += 1;
+                    if length == 0 {
+                        break;
+                    }
+                    let label = msg.get(pos..pos + length).ok_or_else(pkg_err)?;
+                    labels.push(label.to_vec());
+                    pos += length;
+                }
+                _ => return Err(Error::msg("unsupported label type in domain name")),
+            }
+        }
+
+        return Ok((Self::from_labels(labels)?, end.unwrap_or(pos)));
+    }
+
+    pub fn is_root(&self) -> bool {
+        return self.labels.is_empty();
+    }
+
+    // Number of labels, not counting the root.
+    pub fn len(&self) -> usize {
+        return self.labels.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.is_root();
+    }
+
+    // Labels from the leftmost (most specific) one to the rightmost.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &[u8]> {
+        return self.labels.iter().map(|l| l.as_slice());
+    }
+
+    // Size of the uncompressed wire form, including the root label.
+    pub fn wire_length(&self) -> usize {
+        return self.labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1;
+    }
+
+    // The name with the leftmost label removed, `None` for the root.
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            return None;
+        }
+        return Some(Self {
+            labels: self.labels[1..].to_vec(),
+        });
+    }
+
+    // True when `self` equals `other` or sits below it in the tree.
+    pub fn is_subdomain_of(&self, other: &DomainName) -> bool {
+        if other.len() > self.len() {
+            return false;
+        }
+        return self
+            .iter()
+            .rev()
+            .zip(other.iter().rev())
+            .all(|(a, b)| a.eq_ignore_ascii_case(b));
+    }
+
+    // Prepend `label` to the name.
+    pub fn child(&self, label: &[u8]) -> Result<Self, Error> {
+        let mut labels = vec![label.to_vec()];
+        labels.extend(self.labels.iter().cloned());
+        return Self::from_labels(labels);
+    }
+
+    // Presentation form with A-labels converted back to Unicode, for display to users.
+    pub fn to_unicode(&self) -> String {
+        if self.is_root() {
+            return ".".to_string();
+        }
+        let mut result = String::new();
+        for label in &self.labels {
+            result.push_str(&idna::to_unicode(label));
+            result.push('.');
+        }
+
+        return result;
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::<u8>::with_capacity(self.wire_length());
+        for label in &self.labels {
+            result.push(label.len() as u8);
+            result.extend_from_slice(label);
+        }
+        result.push(b'\x00');
+
+        return result;
+    }
+}
+
+impl PartialEq for DomainName {
+    fn eq(&self, other: &Self) -> bool {
+        return self.len() == other.len()
+            && self
+                .iter()
+                .zip(other.iter())
+                .all(|(a, b)| a.eq_ignore_ascii_case(b));
+    }
+}
+
+impl Eq for DomainName {}
+
+impl Hash for DomainName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in &self.labels {
+            state.write_u8(label.len() as u8);
+            for b in label {
+                state.write_u8(b.to_ascii_lowercase());
+            }
+        }
+        state.write_u8(0);
+    }
+}
+
+// Absolute names in master file format: labels separated by `.`, a trailing `.` is optional.
+// `\X` escapes a literal character and `\DDD` a byte given in decimal.
+// Labels typed in Unicode are converted to A-labels with UTS #46 processing, the ideographic
+// full stops are accepted as separators as well.
+impl FromStr for DomainName {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.is_empty() {
+            return Err(Error::msg("empty domain name"));
+        }
+        if text.chars().count() == 1 && text.starts_with(idna::DOTS) {
+            return Ok(Self::root());
+        }
+
+        let finish = |label: Vec<u8>, unicode: bool| -> Result<Vec<u8>, Error> {
+            if label.is_empty() {
+                return Err(Error::msg(format!("empty label in domain name: {}", text)));
+            }
+            if !unicode {
+                return Ok(label);
+            }
+            return idna::to_ascii(std::str::from_utf8(&label)?);
+        };
+
+        let mut labels = Vec::<Vec<u8>>::new();
+        let mut label = Vec::<u8>::new();
+        let mut unicode = false;
+        let mut chars = text.chars();
+        let mut utf8 = [0_u8; 4];
+        while let Some(c) = chars.next() {
+            match c {
+                c if idna::DOTS.contains(&c) => {
+                    labels.push(finish(std::mem::take(&mut label), unicode)?);
+                    unicode = false;
+                }
+                '\\' => {
+                    let next = chars
+                        .next()
+                        .ok_or_else(|| Error::msg(format!("dangling escape in: {}", text)))?;
+                    if next.is_ascii_digit() {
+                        let digits = [Some(next), chars.next(), chars.next()];
+                        let value = digits
+                            .iter()
+                            .try_fold(0_u32, |acc, d| match d {
+                                Some(d) => d.to_digit(10).map(|d| acc * 10 + d),
+                                None => None,
+                            })
+                            .filter(|v| *v <= u8::MAX as u32)
+                            .ok_or_else(|| {
+                                Error::msg(format!("invalid \\DDD escape in: {}", text))
+                            })?;
+                        label.push(value as u8);
+                    } else {
+                        label.extend_from_slice(next.encode_utf8(&mut utf8).as_bytes());
+                    }
+                }
+                c => {
+                    unicode |= !c.is_ascii();
+                    label.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                }
+            }
+        }
+        if !label.is_empty() {
+            labels.push(finish(label, unicode)?);
+        }
+
+        return Self::from_labels(labels);
+    }
+}
+
+// Presentation form of a label (RFC 4343 2.1): printable ASCII is kept, `.` and the other
+// master file special characters are escaped with `\`, everything else becomes `\DDD`.
+pub fn escape_label(label: &[u8]) -> String {
+    let mut result = String::with_capacity(label.len());
+    for b in label {
+        match b {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                result.push('\\');
+                result.push(*b as char);
+            }
+            0x21..=0x7e => result.push(*b as char),
+            _ => result.push_str(&format!("\\{:03}", b)),
+        }
+    }
+
+    return result;
+}
+
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return write!(f, ".");
+        }
+        for label in &self.labels {
+            write!(f, "{}.", escape_label(label))?;
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn labels(name: &DomainName) -> Vec<&[u8]> {
+        return name.iter().collect();
+    }
+
+    #[test]
+    pub fn test_name_decode() {
+        let msg = vec![
+            0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+        ];
+        let (name, next) = DomainName::decode(&msg, 0).unwrap();
+        assert_eq!(vec![b"google".as_ref(), b"com"], labels(&name));
+        assert_eq!(12, next);
+        assert_eq!(msg, name.encode());
+    }
+
+    #[test]
+    pub fn test_name_decode_compressed() {
+        let msg = vec![
+            // google com
+            0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+            // www + pointer to offset 0
+            0x03, 0x77, 0x77, 0x77, 0xc0, 0x00,
+        ];
+        let (name, next) = DomainName::decode(&msg, 12).unwrap();
+        assert_eq!("www.google.com.", name.to_string());
+        assert_eq!(18, next);
+    }
+
+    #[test]
+    pub fn test_name_decode_invalid() {
+        // pointer loop
+        assert!(DomainName::decode(&[0xc0, 0x00], 0).is_err());
+        // truncated label
+        assert!(DomainName::decode(&[0x03, 0x77, 0x77], 0).is_err());
+        // missing root label
+        assert!(DomainName::decode(&[0x01, 0x77], 0).is_err());
+        // reserved label type
+        assert!(DomainName::decode(&[0x40, 0x00], 0).is_err());
+
+        // longer than 255 bytes once decompressed
+        let mut msg = Vec::<u8>::new();
+        for _ in 0..5 {
+            msg.push(60);
+            msg.extend_from_slice(&[b'a'; 60]);
+        }
+        msg.push(0);
+        assert!(DomainName::decode(&msg, 0).is_err());
+    }
+
+    #[test]
+    pub fn test_name_binary_labels() {
+        let msg = vec![0x03, 0xff, 0x00, 0x2e, 0x02, 0x20, 0x41, 0x00];
+        let (name, _) = DomainName::decode(&msg, 0).unwrap();
+        assert_eq!(vec![&[0xff_u8, 0x00, 0x2e][..], b" A"], labels(&name));
+        assert_eq!(msg, name.encode());
+        assert_eq!("\\255\\000\\..\\032A.", name.to_string());
+
+        // the presentation form parses back to the same octets
+        let parsed: DomainName = name.to_string().parse().unwrap();
+        assert_eq!(msg, parsed.encode());
+
+        // case folding only applies to ASCII letters
+        let upper = DomainName::from_labels([vec![0xc4_u8]]).unwrap();
+        let lower = DomainName::from_labels([vec![0xe4_u8]]).unwrap();
+        assert_ne!(upper, lower);
+    }
+
+    #[test]
+    pub fn test_name_limits() {
+        assert!(DomainName::from_labels(["a".repeat(63)]).is_ok());
+        assert!(DomainName::from_labels(["a".repeat(64)]).is_err());
+        assert!(DomainName::from_labels([""]).is_err());
+
+        // 4 * (63 + 1) + 1 = 257
+        let labels = vec!["a".repeat(63); 4];
+        assert!(DomainName::from_labels(labels).is_err());
+        // 3 * (63 + 1) + (61 + 1) + 1 = 255
+        let mut labels = vec!["a".repeat(63); 3];
+        labels.push("a".repeat(61));
+        assert_eq!(255, DomainName::from_labels(labels).unwrap().wire_length());
+    }
+
+    #[test]
+    pub fn test_name_case_insensitive() {
+        let a: DomainName = "WWW.Example.COM".parse().unwrap();
+        let b: DomainName = "www.example.com.".parse().unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, "www.example.org".parse::<DomainName>().unwrap());
+
+        let mut set = HashSet::new();
+        set.insert(a.clone());
+        assert!(set.contains(&b));
+
+        // original case is kept
+        assert_eq!("WWW.Example.COM.", a.to_string());
+        assert_eq!(b"\x03WWW\x07Example\x03COM\x00".to_vec(), a.encode());
+    }
+
+    #[test]
+    pub fn test_name_parse() {
+        assert!(DomainName::from_str(".").unwrap().is_root());
+        assert_eq!(2, DomainName::from_str("google.com").unwrap().len());
+        assert!(DomainName::from_str("").is_err());
+        assert!(DomainName::from_str("a..b").is_err());
+        assert!(DomainName::from_str(".com").is_err());
+        assert!(DomainName::from_str("com\\").is_err());
+
+        let name = DomainName::from_str("a\\.b.c\\092d.\\065x").unwrap();
+        assert_eq!(vec![&b"a.b"[..], b"c\\d", b"Ax"], labels(&name));
+        assert_eq!("a\\.b.c\\\\d.Ax.", name.to_string());
+
+        let name = DomainName::from_str("\\000\\255x.com").unwrap();
+        assert_eq!(vec![&[0_u8, 255, b'x'][..], b"com"], labels(&name));
+
+        assert!(DomainName::from_str("a\\65").is_err());
+        assert!(DomainName::from_str("a\\6x5").is_err());
+        assert!(DomainName::from_str("a\\256").is_err());
+    }
+
+    #[test]
+    pub fn test_name_parse_unicode() {
+        let name = DomainName::from_str("www.M\u{00fc}nchen.de").unwrap();
+        assert_eq!("www.xn--mnchen-3ya.de.", name.to_string());
+        assert_eq!("www.m\u{00fc}nchen.de.", name.to_unicode());
+        assert!(name.encode().is_ascii());
+
+        let name =
+            DomainName::from_str("\u{4f8b}\u{3048}\u{3002}\u{30c6}\u{30b9}\u{30c8}").unwrap();
+        assert_eq!("xn--r8jz45g.xn--zckzah.", name.to_string());
+        assert_eq!(
+            name,
+            DomainName::from_str("XN--R8JZ45G.xn--zckzah.").unwrap()
+        );
+        assert_eq!(
+            "\u{4f8b}\u{3048}.\u{30c6}\u{30b9}\u{30c8}.",
+            name.to_unicode()
+        );
+
+        assert!(DomainName::from_str("-m\u{00fc}nchen.de").is_err());
+        assert_eq!(".", DomainName::root().to_unicode());
+        // escaped octets are not treated as Unicode text
+        let name = DomainName::from_str("\\195\\188.de").unwrap();
+        assert_eq!("\\195\\188.de.", name.to_unicode());
+    }
+
+    #[test]
+    pub fn test_name_parent() {
+        let name = DomainName::from_str("www.example.com").unwrap();
+        let parent = name.parent().unwrap();
+        assert_eq!("example.com.", parent.to_string());
+        assert_eq!("com.", parent.parent().unwrap().to_string());
+        assert!(parent.parent().unwrap().parent().unwrap().is_root());
+        assert!(DomainName::root().parent().is_none());
+
+        assert_eq!(name, parent.child(b"www").unwrap());
+    }
+
+    #[test]
+    pub fn test_name_is_subdomain_of() {
+        let name = DomainName::from_str("www.Example.com").unwrap();
+        assert!(name.is_subdomain_of(&"example.COM".parse().unwrap()));
+        assert!(name.is_subdomain_of(&name));
+        assert!(name.is_subdomain_of(&DomainName::root()));
+        assert!(!name.is_subdomain_of(&"ample.com".parse().unwrap()));
+        assert!(!name.is_subdomain_of(&"a.www.example.com".parse().unwrap()));
+    }
+}
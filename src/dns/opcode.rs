@@ -0,0 +1,83 @@
+use std::fmt;
+
+// Operation Code (OPCODE): 4 bits
+// Registered values from RFC 1035, RFC 1996 (NOTIFY), RFC 2136 (UPDATE) and RFC 8490 (DSO).
+// Anything else is kept as `Unknown` so it survives a decode/encode round trip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Query,
+    IQuery,
+    Status,
+    Notify,
+    Update,
+    Dso,
+    Unknown(u8),
+}
+
+impl From<u8> for Opcode {
+    fn from(value: u8) -> Self {
+        return match value {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            6 => Opcode::Dso,
+            v => Opcode::Unknown(v),
+        };
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        return match opcode {
+            Opcode::Query => 0,
+            Opcode::IQuery => 1,
+            Opcode::Status => 2,
+            Opcode::Notify => 4,
+            Opcode::Update => 5,
+            Opcode::Dso => 6,
+            Opcode::Unknown(v) => v,
+        };
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Opcode::Query => write!(f, "QUERY"),
+            Opcode::IQuery => write!(f, "IQUERY"),
+            Opcode::Status => write!(f, "STATUS"),
+            Opcode::Notify => write!(f, "NOTIFY"),
+            Opcode::Update => write!(f, "UPDATE"),
+            Opcode::Dso => write!(f, "DSO"),
+            Opcode::Unknown(v) => write!(f, "OPCODE{}", v),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_opcode_from_u8() {
+        assert_eq!(Opcode::Query, Opcode::from(0));
+        assert_eq!(Opcode::Notify, Opcode::from(4));
+        assert_eq!(Opcode::Dso, Opcode::from(6));
+        assert_eq!(Opcode::Unknown(3), Opcode::from(3));
+    }
+
+    #[test]
+    pub fn test_opcode_round_trip() {
+        for v in 0..16_u8 {
+            assert_eq!(v, u8::from(Opcode::from(v)));
+        }
+    }
+
+    #[test]
+    pub fn test_opcode_display() {
+        assert_eq!("UPDATE", Opcode::Update.to_string());
+        assert_eq!("OPCODE9", Opcode::Unknown(9).to_string());
+    }
+}
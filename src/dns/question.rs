@@ -1,70 +1,44 @@
 use anyhow::Error;
-use nom::AsChar;
 
-#[derive(Debug)]
+use super::name::DomainName;
+
+#[derive(Debug, Clone)]
 pub struct Question {
     length: usize,
-    names: Vec<String>,
+    name: DomainName,
     typ: u16,
     class: u16,
 }
 
 impl Question {
-    pub fn new(raw: &mut [u8]) -> Result<Self, Error> {
-        let pkg_err = Err(Error::msg("the question package not incomplete"));
-        if raw.len() == 0 {
-            return pkg_err;
-        }
-
-        let mut ques = Question {
-            names: vec![],
-            typ: 0,
-            class: 0,
-            length: 0,
-        };
-
-        let mut domain_length = 0;
-        // parse domain name
-        let mut iter = raw.as_ref().into_iter();
-        let mut start = 0_usize;
-        loop {
-            let u = iter.next().unwrap();
-            start += 1;
-            domain_length += 1;
-            if u.as_char().eq(&'\x00') {
-                break;
-            }
-
-            let mut length = *u as usize;
-            domain_length += length;
-
-            if start + length >= raw.len() {
-                return pkg_err;
-            }
-            ques.names
-                .extend(String::from_utf8(raw[start..start + length].to_vec()));
-            while length > 0 {
-                iter.next();
-                start += 1;
-                length -= 1;
-            }
+    // Decode the question located at `offset` of the whole message, following compression
+    // pointers. `length` is the number of bytes the question occupies at `offset`.
+    pub fn decode(msg: &[u8], offset: usize) -> Result<Self, Error> {
+        let (name, pos) = DomainName::decode(msg, offset)?;
+        if pos + 4 > msg.len() {
+            return Err(Error::msg("the question package not incomplete"));
         }
 
-        if domain_length + 4 > raw.len() {
-            return pkg_err;
-        }
-        // parse typ
-        ques.typ = u16::from_be_bytes(raw[domain_length..domain_length + 2].try_into()?);
-        // parse class
-        ques.class = u16::from_be_bytes(raw[domain_length + 2..domain_length + 4].try_into()?);
-        // length
-        ques.length = domain_length + 4;
+        return Ok(Question {
+            length: pos + 4 - offset,
+            name,
+            typ: u16::from_be_bytes(msg[pos..pos + 2].try_into()?),
+            class: u16::from_be_bytes(msg[pos + 2..pos + 4].try_into()?),
+        });
+    }
 
-        return Ok(ques);
+    // A question built locally, `length` is its encoded size.
+    pub fn from_parts(name: DomainName, typ: u16, class: u16) -> Self {
+        return Question {
+            length: name.wire_length() + 4,
+            name,
+            typ,
+            class,
+        };
     }
 
-    pub fn names(&self) -> &Vec<String> {
-        return &self.names;
+    pub fn name(&self) -> &DomainName {
+        return &self.name;
     }
 
     pub fn length(&self) -> usize {
@@ -83,13 +57,7 @@ impl Question {
         let mut result = Vec::<u8>::new();
 
         // encode domain names
-        for name in &self.names {
-            result.push(name.len() as u8);
-            for v in name.as_bytes() {
-                result.push(*v);
-            }
-        }
-        result.push(b'\x00');
+        result.extend_from_slice(&self.name.encode());
 
         // encode typ
         for v in self.typ.to_be_bytes() {
@@ -104,24 +72,62 @@ impl Question {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct Questions(Vec<Question>);
+
+impl Questions {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    pub fn extend(&mut self, ques: Question) {
+        self.0.push(ques);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.0.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.0.is_empty();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Question> {
+        return self.0.iter();
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::<u8>::new();
+
+        for ques in &self.0 {
+            result.extend_from_slice(&ques.encode());
+        }
+
+        return result;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    pub fn test_question_new() {
+    pub fn test_question_decode_uncompressed() {
         // correct
-        let mut ques = Question::new(&mut vec![
-            // google com
-            0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
-            // type & class
-            0x11, 0x22, 0x33, 0x44,
-        ]);
-        assert_eq!(true, ques.as_ref().is_ok());
-        assert_eq!(2, ques.as_ref().unwrap().names().len());
+        let mut ques = Question::decode(
+            &[
+                // google com
+                0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+                // type & class
+                0x11, 0x22, 0x33, 0x44,
+            ],
+            0,
+        );
+        assert!(ques.as_ref().is_ok());
+        assert_eq!(2, ques.as_ref().unwrap().name().len());
         assert_eq!(16, ques.as_ref().unwrap().length());
-        assert_eq!("google", ques.as_ref().unwrap().names().get(0).unwrap());
-        assert_eq!("com", ques.as_ref().unwrap().names().get(1).unwrap());
+        let labels: Vec<&[u8]> = ques.as_ref().unwrap().name().iter().collect();
+        assert_eq!(vec![b"google".as_ref(), b"com".as_ref()], labels);
 
         // incorrect
         let mut raw = vec![
@@ -130,19 +136,50 @@ mod tests {
             // type & class
             0x11, 0x22, 0x33,
         ];
-        while raw.len() != 0 {
-            ques = Question::new(&mut raw);
-            assert_eq!(true, ques.is_err());
+        while !raw.is_empty() {
+            ques = Question::decode(&raw, 0);
+            assert!(ques.is_err());
             raw.pop();
         }
     }
 
+    #[test]
+    pub fn test_question_binary_label() {
+        let raw = vec![
+            // \xff\x00. com
+            0x02, 0xff, 0x00, 0x03, 0x63, 0x6f, 0x6d, 0x00, // type & class
+            0x00, 0x01, 0x00, 0x01,
+        ];
+        let ques = Question::decode(&raw, 0).unwrap();
+        assert_eq!(2, ques.name().len());
+        assert_eq!(&[0xff_u8, 0x00], ques.name().iter().next().unwrap());
+        assert_eq!("\\255\\000.com.", ques.name().to_string());
+        assert_eq!(raw, ques.encode());
+    }
+
+    #[test]
+    pub fn test_question_decode() {
+        let msg = vec![
+            // google com
+            0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+            // www + pointer to offset 0, type & class
+            0x03, 0x77, 0x77, 0x77, 0xc0, 0x00, 0x00, 0x01, 0x00, 0x01,
+        ];
+        let ques = Question::decode(&msg, 12).unwrap();
+        assert_eq!("www.google.com.", ques.name().to_string());
+        assert_eq!(10, ques.length());
+        assert_eq!(1, ques.typ());
+        assert_eq!(1, ques.class());
+
+        assert!(Question::decode(&msg[..msg.len() - 1], 12).is_err());
+    }
+
     #[test]
     pub fn test_question_encode() {
         // correct
         let ques = Question {
             length: 16,
-            names: vec!["google".to_string(), "com".to_string()],
+            name: DomainName::from_labels(["google", "com"]).unwrap(),
             typ: 4386,
             class: 13124,
         };
@@ -0,0 +1,177 @@
+use std::fmt;
+
+// Response Code (RCODE): 12 bits
+// The lower 4 bits live in the header, the upper 8 bits in the OPT record (RFC 6891 6.1.3).
+// Values above 15 therefore need an OPT record to be put on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rcode {
+    NoError,
+    FormErr,
+    ServFail,
+    NXDomain,
+    NotImp,
+    Refused,
+    YXDomain,
+    YXRRSet,
+    NXRRSet,
+    NotAuth,
+    NotZone,
+    DSOTypeNI,
+    BadVers,
+    BadKey,
+    BadTime,
+    BadMode,
+    BadName,
+    BadAlg,
+    BadTrunc,
+    BadCookie,
+    Unknown(u16),
+}
+
+impl Rcode {
+    // TSIG (RFC 8945) reuses 16 for BADSIG, the meaning depends on where the code is found.
+    pub const BADSIG: Rcode = Rcode::BadVers;
+
+    // Combine the 4 header bits with the 8 extended bits from the OPT record.
+    pub fn from_parts(low: u8, high: u8) -> Self {
+        return Rcode::from((high as u16) << 4 | (low & 0x0F) as u16);
+    }
+
+    // The 4 bits carried in the header.
+    pub fn low(&self) -> u8 {
+        return (u16::from(*self) & 0x0F) as u8;
+    }
+
+    // The 8 bits carried in the OPT record TTL field.
+    pub fn high(&self) -> u8 {
+        return (u16::from(*self) >> 4 & 0xFF) as u8;
+    }
+
+    pub fn is_extended(&self) -> bool {
+        return self.high() != 0;
+    }
+}
+
+impl From<u16> for Rcode {
+    fn from(value: u16) -> Self {
+        return match value {
+            0 => Rcode::NoError,
+            1 => Rcode::FormErr,
+            2 => Rcode::ServFail,
+            3 => Rcode::NXDomain,
+            4 => Rcode::NotImp,
+            5 => Rcode::Refused,
+            6 => Rcode::YXDomain,
+            7 => Rcode::YXRRSet,
+            8 => Rcode::NXRRSet,
+            9 => Rcode::NotAuth,
+            10 => Rcode::NotZone,
+            11 => Rcode::DSOTypeNI,
+            16 => Rcode::BadVers,
+            17 => Rcode::BadKey,
+            18 => Rcode::BadTime,
+            19 => Rcode::BadMode,
+            20 => Rcode::BadName,
+            21 => Rcode::BadAlg,
+            22 => Rcode::BadTrunc,
+            23 => Rcode::BadCookie,
+            v => Rcode::Unknown(v & 0x0FFF),
+        };
+    }
+}
+
+impl From<Rcode> for u16 {
+    fn from(rcode: Rcode) -> Self {
+        return match rcode {
+            Rcode::NoError => 0,
+            Rcode::FormErr => 1,
+            Rcode::ServFail => 2,
+            Rcode::NXDomain => 3,
+            Rcode::NotImp => 4,
+            Rcode::Refused => 5,
+            Rcode::YXDomain => 6,
+            Rcode::YXRRSet => 7,
+            Rcode::NXRRSet => 8,
+            Rcode::NotAuth => 9,
+            Rcode::NotZone => 10,
+            Rcode::DSOTypeNI => 11,
+            Rcode::BadVers => 16,
+            Rcode::BadKey => 17,
+            Rcode::BadTime => 18,
+            Rcode::BadMode => 19,
+            Rcode::BadName => 20,
+            Rcode::BadAlg => 21,
+            Rcode::BadTrunc => 22,
+            Rcode::BadCookie => 23,
+            Rcode::Unknown(v) => v & 0x0FFF,
+        };
+    }
+}
+
+impl fmt::Display for Rcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rcode::NoError => "NOERROR",
+            Rcode::FormErr => "FORMERR",
+            Rcode::ServFail => "SERVFAIL",
+            Rcode::NXDomain => "NXDOMAIN",
+            Rcode::NotImp => "NOTIMP",
+            Rcode::Refused => "REFUSED",
+            Rcode::YXDomain => "YXDOMAIN",
+            Rcode::YXRRSet => "YXRRSET",
+            Rcode::NXRRSet => "NXRRSET",
+            Rcode::NotAuth => "NOTAUTH",
+            Rcode::NotZone => "NOTZONE",
+            Rcode::DSOTypeNI => "DSOTYPENI",
+            Rcode::BadVers => "BADVERS",
+            Rcode::BadKey => "BADKEY",
+            Rcode::BadTime => "BADTIME",
+            Rcode::BadMode => "BADMODE",
+            Rcode::BadName => "BADNAME",
+            Rcode::BadAlg => "BADALG",
+            Rcode::BadTrunc => "BADTRUNC",
+            Rcode::BadCookie => "BADCOOKIE",
+            Rcode::Unknown(v) => return write!(f, "RCODE{}", v),
+        };
+        return write!(f, "{}", name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_rcode_from_parts() {
+        assert_eq!(Rcode::NXDomain, Rcode::from_parts(3, 0));
+        assert_eq!(Rcode::BadVers, Rcode::from_parts(0, 1));
+        assert_eq!(Rcode::BadCookie, Rcode::from_parts(7, 1));
+        assert_eq!(Rcode::Unknown(0xFFF), Rcode::from_parts(0xFF, 0xFF));
+    }
+
+    #[test]
+    pub fn test_rcode_split() {
+        assert_eq!(7, Rcode::BadCookie.low());
+        assert_eq!(1, Rcode::BadCookie.high());
+        assert!(Rcode::BadCookie.is_extended());
+        assert_eq!(5, Rcode::Refused.low());
+        assert_eq!(0, Rcode::Refused.high());
+        assert!(!Rcode::Refused.is_extended());
+    }
+
+    #[test]
+    pub fn test_rcode_round_trip() {
+        for v in 0..4096_u16 {
+            let rcode = Rcode::from(v);
+            assert_eq!(v, u16::from(rcode));
+            assert_eq!(rcode, Rcode::from_parts(rcode.low(), rcode.high()));
+        }
+    }
+
+    #[test]
+    pub fn test_rcode_display() {
+        assert_eq!("BADCOOKIE", Rcode::BadCookie.to_string());
+        assert_eq!(Rcode::BadVers, Rcode::BADSIG);
+        assert_eq!("RCODE12", Rcode::Unknown(12).to_string());
+    }
+}
@@ -0,0 +1,14 @@
+// Resource record TYPE values used by the server (RFC 1035 3.2.2, RFC 3596, RFC 6891).
+pub const A: u16 = 1;
+pub const NS: u16 = 2;
+pub const CNAME: u16 = 5;
+pub const SOA: u16 = 6;
+pub const PTR: u16 = 12;
+pub const MX: u16 = 15;
+pub const TXT: u16 = 16;
+pub const AAAA: u16 = 28;
+pub const OPT: u16 = 41;
+pub const ANY: u16 = 255;
+
+// CLASS values (RFC 1035 3.2.4).
+pub const CLASS_IN: u16 = 1;
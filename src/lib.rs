@@ -0,0 +1,15 @@
+// The codebase prefers explicit `return` statements and keeps the `DNS`
+// message type in `dns::dns`, so these lints are silenced crate-wide.
+#![allow(
+    clippy::needless_return,
+    clippy::upper_case_acronyms,
+    clippy::module_inception
+)]
+
+pub mod cache;
+pub mod config;
+pub mod dns;
+pub mod log;
+pub mod resolver;
+pub mod server;
+pub mod zone;
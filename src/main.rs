@@ -1,34 +1,319 @@
-// Uncomment this block to pass the first stage
-// use std::net::UdpSocket;
-mod dns;
+use anyhow::Error;
+use dns_starter_rust::{
+    cache::Cache,
+    config::{Config, Upstream},
+    info, log,
+    resolver::{cached::Cached, forward::Forwarder, recursive::Recursor, Resolver},
+    server::{self, listener::Listener, pool::WorkerPool, tcp, udp, Context, Transport},
+    warn,
+    zone::{Catalog, Zone},
+};
 
-use dns::DNS;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
-use std::net::UdpSocket;
+const USAGE: &str =
+    "Usage: dns-starter-rust [--config FILE] [--check-config] [--listen [udp:|tcp:]ADDR]...
+                         [--resolver ADDR]... [--recursive]
 
-const READ_LENGTH: usize = 1024;
+Options:
+  --config FILE   Read the configuration from a TOML file
+  --check-config  Validate the configuration and exit
+  --listen SPEC   Address to serve, repeatable, replaces the listeners of the file.
+                  `udp:` or `tcp:` limits it to one transport, IPv6 addresses go in
+                  brackets: --listen udp:[::]:53
+                  Default: 127.0.0.1:2053 over UDP and TCP
+  --resolver ADDR Forward queries to this upstream, repeatable, replaces the
+                  upstreams of the file. The port defaults to 53
+  --recursive     Resolve queries from the root servers instead of forwarding";
+
+// How often the query and cache counters are logged.
+const REPORT_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Default)]
+struct Args {
+    config: Option<PathBuf>,
+    check_config: bool,
+    listeners: Vec<Listener>,
+    resolvers: Vec<SocketAddr>,
+    recursive: bool,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, Error> {
+    let mut parsed = Args::default();
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| Error::msg("--config needs a file"))?;
+                parsed.config = Some(PathBuf::from(path));
+            }
+            "--check-config" => parsed.check_config = true,
+            "--listen" => {
+                let spec = args
+                    .next()
+                    .ok_or_else(|| Error::msg("--listen needs an address"))?;
+                parsed.listeners.extend(Listener::parse(&spec)?);
+            }
+            "--resolver" => {
+                let addr = args
+                    .next()
+                    .ok_or_else(|| Error::msg("--resolver needs an address"))?;
+                parsed.resolvers.push(addr.parse::<Upstream>()?.addr());
+            }
+            "--recursive" => parsed.recursive = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            _ => return Err(Error::msg(format!("unknown argument: {}", arg))),
+        }
+    }
+    if parsed.recursive && !parsed.resolvers.is_empty() {
+        return Err(Error::msg("--recursive and --resolver exclude each other"));
+    }
+
+    Ok(parsed)
+}
 
 fn main() {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
 
-    // Uncomment this block to pass the first stage
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053").expect("Failed to bind to address");
-    let mut buf = [0; READ_LENGTH];
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+    let mut config = match &args.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            eprintln!("Invalid configuration: {}", e);
+            exit(1);
+        }),
+        None => Config::new(),
+    };
+    if !args.listeners.is_empty() {
+        config.with_listeners(args.listeners);
+    }
+    if !args.resolvers.is_empty() {
+        config.with_forwarders(args.resolvers).with_recursion(false);
+    }
+    if args.recursive {
+        config.with_forwarders(vec![]).with_recursion(true);
+    }
+    // zone files are part of the configuration, a broken one fails the check too
+    let loaded: Vec<Zone> = config
+        .zones()
+        .iter()
+        .map(|zone| {
+            Zone::load(zone.name().clone(), zone.file()).unwrap_or_else(|e| {
+                eprintln!("Invalid zone {}: {}", zone.name(), e);
+                exit(1);
+            })
+        })
+        .collect();
+    if args.check_config {
+        for warning in config.warnings() {
+            eprintln!("Warning: {}", warning);
+        }
+        println!("Configuration OK");
+        return;
+    }
 
-    loop {
-        match udp_socket.recv_from(&mut buf) {
-            Ok((size, source)) => {
-                let mydns = DNS::from(&buf[0..size]);
+    log::set_level(config.log_level());
+    for warning in config.warnings() {
+        warn!("{}", warning);
+    }
+    let mut zones = Catalog::new();
+    for zone in loaded {
+        info!("Serving zone {} with {} names", zone.origin(), zone.len());
+        zones.with_zone(zone);
+    }
+
+    let limits = config.limits();
+    let mut ctx = Context::new();
+    ctx.with_acl(config.acl().clone()).with_zones(zones);
+    let cache = Arc::new({
+        let mut cache = Cache::new(config.cache_max_memory());
+        cache
+            .with_shards(config.cache_shards())
+            .with_min_ttl(config.cache_min_ttl())
+            .with_max_ttl(config.cache_max_ttl())
+            .with_max_negative_ttl(config.cache_max_negative_ttl())
+            .with_stale_window(config.cache_stale_window())
+            .with_prefetch(config.cache_prefetch());
+        cache
+    });
+    // the cache of a resolver is worth keeping across restarts
+    let cache_file = config
+        .cache_file()
+        .filter(|_| config.cache_max_memory() > 0)
+        .map(Path::to_path_buf);
+    if let Some(file) = &cache_file {
+        // there is nothing to load on the first start
+        if file.exists() {
+            match cache.load(file) {
+                Ok(count) => info!("Loaded {} cache entries from {}", count, file.display()),
+                Err(e) => warn!("Not loading the cache from {}: {}", file.display(), e),
+            }
+        }
+    }
+    // the counters are reported and the cache saved on the way out
+    if let Err(e) = server::catch_termination() {
+        warn!("Cannot catch termination signals: {}", e);
+    }
+    let resolver: Option<Arc<dyn Resolver>> = if !config.forwarders().is_empty() {
+        let mut forwarder = Forwarder::new(config.forwarders().to_vec());
+        forwarder
+            .with_timeout(config.forward_timeout())
+            .with_retries(config.forward_retries())
+            .with_coalesce_timeout(config.coalesce_timeout())
+            .with_max_waiters(config.max_waiters());
+        Some(Arc::new(forwarder))
+    } else if config.recursion() {
+        let mut recursor = Recursor::new(config.root_hints().to_vec());
+        recursor.with_timeout(config.recursion_timeout());
+        if config.cache_max_memory() > 0 {
+            recursor.with_cache(cache.clone());
+        }
+        Some(Arc::new(recursor))
+    } else {
+        None
+    };
+    match resolver {
+        Some(resolver) if config.cache_max_memory() > 0 => {
+            let mut cached = Cached::new(cache.clone(), resolver);
+            cached.with_stale_answer_timeout(config.cache_stale_answer_timeout());
+            ctx.with_resolver(Arc::new(cached));
+        }
+        Some(resolver) => {
+            ctx.with_resolver(resolver);
+        }
+        None => {}
+    }
+    let ctx = Arc::new(ctx);
+    // all listeners share the workers, so a flood on one of them slows down the others
+    // instead of growing without bound
+    let pool = Arc::new(WorkerPool::new(limits.workers(), limits.queue_size()));
 
-                udp_socket
-                    .send_to(&mydns.encode().to_vec(), source)
-                    .expect("Failed to send response");
+    // bind everything before serving so a bad address fails at startup
+    let mut loops = Vec::<thread::JoinHandle<()>>::new();
+    for listener in config.listeners() {
+        match listener.transport() {
+            Transport::Udp => {
+                let sockets = udp::bind(listener.addr(), limits.udp()).unwrap_or_else(|e| {
+                    eprintln!("Failed to bind {}: {}", listener, e);
+                    exit(1);
+                });
+                // one receive loop per socket
+                for socket in sockets {
+                    let ctx = ctx.clone();
+                    let pool = pool.clone();
+                    let batch_size = limits.udp().batch_size();
+                    loops.push(thread::spawn(move || {
+                        udp::serve_batched(Arc::new(socket), ctx, &pool, batch_size)
+                    }));
+                }
             }
-            Err(e) => {
-                eprintln!("Error receiving data: {}", e);
-                break;
+            Transport::Tcp => {
+                let tcp_listener = tcp::bind(listener.addr()).unwrap_or_else(|e| {
+                    eprintln!("Failed to bind {}: {}", listener, e);
+                    exit(1);
+                });
+                let ctx = ctx.clone();
+                let pool = pool.clone();
+                let opts = *limits.tcp();
+                loops.push(thread::spawn(move || {
+                    tcp::serve(&tcp_listener, ctx, opts, pool)
+                }));
             }
         }
+        println!("Listening on {}", listener);
+    }
+
+    run(
+        &loops,
+        &ctx,
+        &cache,
+        cache_file.as_deref(),
+        config.cache_save_interval(),
+    );
+}
+
+// Wait for the serve loops, reporting the counters every `REPORT_INTERVAL`. With a cache
+// file, the cache is saved every `interval` (unless zero) as well. On SIGINT or SIGTERM,
+// or once every loop has ended, the counters are reported and the cache saved a last time.
+fn run(
+    loops: &[thread::JoinHandle<()>],
+    ctx: &Context,
+    cache: &Cache,
+    file: Option<&Path>,
+    interval: Duration,
+) {
+    let mut last_report = Instant::now();
+    let mut last_save = Instant::now();
+    while !loops.iter().all(|serve_loop| serve_loop.is_finished()) {
+        thread::sleep(Duration::from_millis(200));
+        if server::terminating() {
+            shut_down(ctx, cache, file);
+            exit(0);
+        }
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            report(ctx, cache);
+            last_report = Instant::now();
+        }
+        if let Some(file) = file.filter(|_| !interval.is_zero()) {
+            if last_save.elapsed() >= interval {
+                save(cache, file);
+                last_save = Instant::now();
+            }
+        }
+    }
+    shut_down(ctx, cache, file);
+}
+
+fn shut_down(ctx: &Context, cache: &Cache, file: Option<&Path>) {
+    report(ctx, cache);
+    if let Some(file) = file {
+        save(cache, file);
+    }
+}
+
+fn report(ctx: &Context, cache: &Cache) {
+    let stats = ctx.stats();
+    info!(
+        "Queries: {} received, {} answered, {} FORMERR, {} refused, {} dropped, {} receive and {} send errors",
+        stats.received(),
+        stats.answered(),
+        stats.formerr(),
+        stats.refused(),
+        stats.dropped(),
+        stats.recv_errors(),
+        stats.send_errors()
+    );
+    info!(
+        "Cache: {} entries in {} bytes, {} hits, {} misses, {} evictions",
+        cache.len(),
+        cache.memory(),
+        cache.hits(),
+        cache.misses(),
+        cache.evictions()
+    );
+}
+
+fn save(cache: &Cache, file: &Path) {
+    match cache.save(file) {
+        Ok(count) => info!("Saved {} cache entries to {}", count, file.display()),
+        Err(e) => warn!("Failed to save the cache to {}: {}", file.display(), e),
     }
 }
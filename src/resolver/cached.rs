@@ -0,0 +1,408 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{merge_replies, Resolver};
+use crate::{
+    cache::Cache,
+    dns::{
+        edns::{Edns, EDE_STALE_ANSWER},
+        question::Question,
+        rcode::Rcode,
+        DomainName, DNS,
+    },
+};
+
+// RFC 8767 5 suggests answering stale after 1.8 seconds.
+pub const DEFAULT_STALE_ANSWER_TIMEOUT: Duration = Duration::from_millis(1800);
+// Refreshes running in the background at once, each one holds a thread.
+pub const DEFAULT_MAX_REFRESHING: usize = 64;
+
+// Name, type and class of a question being refreshed in the background.
+type Refreshing = Mutex<HashSet<(DomainName, u16, u16)>>;
+
+// Answers from the cache when it can and asks `inner` otherwise, caching its replies.
+// When `inner` fails or takes longer than `stale_answer_timeout` and the cache still holds
+// expired data for the question, that data is answered instead (RFC 8767) while `inner`
+// goes on in the background to refresh the cache. Answers close to their expiry are
+// refreshed in the background as well, at most one refresh per question at a time and
+// `max_refreshing` in all. Beyond that a client without fresh data waits for `inner`.
+#[derive(Debug)]
+pub struct Cached {
+    cache: Arc<Cache>,
+    inner: Arc<dyn Resolver>,
+    stale_answer_timeout: Duration,
+    max_refreshing: usize,
+    refreshing: Arc<Refreshing>,
+}
+
+impl Cached {
+    pub fn new(cache: Arc<Cache>, inner: Arc<dyn Resolver>) -> Self {
+        return Cached {
+            cache,
+            inner,
+            stale_answer_timeout: DEFAULT_STALE_ANSWER_TIMEOUT,
+            max_refreshing: DEFAULT_MAX_REFRESHING,
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+        };
+    }
+
+    pub fn cache(&self) -> &Arc<Cache> {
+        return &self.cache;
+    }
+
+    // Time given to `inner` before stale data is answered.
+    pub fn stale_answer_timeout(&self) -> Duration {
+        return self.stale_answer_timeout;
+    }
+
+    pub fn with_stale_answer_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.stale_answer_timeout = timeout;
+        return self;
+    }
+
+    pub fn max_refreshing(&self) -> usize {
+        return self.max_refreshing;
+    }
+
+    pub fn with_max_refreshing(&mut self, max: usize) -> &mut Self {
+        self.max_refreshing = max;
+        return self;
+    }
+
+    fn resolve_question(&self, ques: &Question, query: &DNS) -> DNS {
+        if let Some((reply, expiring)) = self.cache.answer_prefetch(ques) {
+            if expiring {
+                // popular names are refreshed before they expire, this client does not wait
+                self.refresh_in_background(ques, &single_query(ques, query));
+            }
+            return reply;
+        }
+
+        let single = single_query(ques, query);
+        let stale = match self.cache.answer_stale(ques) {
+            Some(stale) => stale,
+            None => return refresh(&self.cache, self.inner.as_ref(), &single),
+        };
+        let receiver = match self.refresh_in_background(ques, &single) {
+            Refresh::Started(receiver) => receiver,
+            // another client already waits for the same data
+            Refresh::Running => return mark_stale(stale, query),
+            Refresh::Full => {
+                let reply = refresh(&self.cache, self.inner.as_ref(), &single);
+                if resolved(&reply) {
+                    return reply;
+                }
+                return mark_stale(stale, query);
+            }
+        };
+        match receiver.recv_timeout(self.stale_answer_timeout) {
+            Ok(reply) if resolved(&reply) => return reply,
+            _ => return mark_stale(stale, query),
+        }
+    }
+
+    // Ask `inner` on another thread and cache its reply, which is sent to the receiver as
+    // well.
+    fn refresh_in_background(&self, ques: &Question, single: &DNS) -> Refresh {
+        let key = (ques.name().clone(), ques.typ(), ques.class());
+        let mut refreshing = self.refreshing.lock().unwrap();
+        if refreshing.contains(&key) {
+            return Refresh::Running;
+        }
+        if refreshing.len() >= self.max_refreshing {
+            return Refresh::Full;
+        }
+        refreshing.insert(key.clone());
+        drop(refreshing);
+
+        let (sender, receiver) = mpsc::channel();
+        let cache = self.cache.clone();
+        let inner = self.inner.clone();
+        let refreshing = self.refreshing.clone();
+        let single = single.clone();
+        thread::spawn(move || {
+            let reply = refresh(&cache, inner.as_ref(), &single);
+            refreshing.lock().unwrap().remove(&key);
+            // nobody listens when the cached data was answered already
+            let _ = sender.send(reply);
+        });
+        return Refresh::Started(receiver);
+    }
+}
+
+// What `refresh_in_background` did.
+enum Refresh {
+    Started(Receiver<DNS>),
+    // a refresh of the question is running already
+    Running,
+    // `max_refreshing` refreshes are running, none was started
+    Full,
+}
+
+// The query the inner resolver sees for `ques` alone.
+fn single_query(ques: &Question, query: &DNS) -> DNS {
+    let mut single = DNS::new();
+    single
+        .head_mut()
+        .with_id(query.head().id())
+        .with_opcode(query.head().opcode())
+        .with_rd(query.head().rd())
+        .with_cd(query.head().cd());
+    single.with_question(ques.clone());
+    if let Some(edns) = query.edns() {
+        let mut own = Edns::new();
+        own.with_dnssec_ok(edns.dnssec_ok());
+        single.with_edns(Some(own));
+    }
+    return single;
+}
+
+// Ask `inner` and cache what it replies.
+fn refresh(cache: &Cache, inner: &dyn Resolver, single: &DNS) -> DNS {
+    let reply = inner.resolve(single);
+    if resolved(&reply) {
+        // whoever answered is trusted for every name
+        cache.store(&reply, &DomainName::root());
+    }
+    return reply;
+}
+
+fn resolved(reply: &DNS) -> bool {
+    return matches!(reply.rcode(), Rcode::NoError | Rcode::NXDomain);
+}
+
+// Flag a stale reply with an Extended DNS Error for clients that speak EDNS.
+fn mark_stale(mut stale: DNS, query: &DNS) -> DNS {
+    if query.edns().is_some() {
+        let mut edns = Edns::new();
+        edns.with_extended_error(EDE_STALE_ANSWER, "");
+        stale.with_edns(Some(edns));
+    }
+    return stale;
+}
+
+impl Resolver for Cached {
+    fn resolve(&self, query: &DNS) -> DNS {
+        return merge_replies(query, |ques| Ok(self.resolve_question(ques, query)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::STALE_TTL,
+        dns::{answer::ResourceRecord, edns::OPTION_EDE, rtype},
+    };
+    use std::{
+        net::Ipv4Addr,
+        sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    };
+
+    // Answers every A query with one record, counting the queries.
+    #[derive(Debug, Default)]
+    struct Counting(AtomicUsize);
+
+    impl Resolver for Counting {
+        fn resolve(&self, query: &DNS) -> DNS {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            let mut resp = DNS::response_for(query);
+            let ques = query.questions().iter().next().unwrap();
+            let mut rr = ResourceRecord::new();
+            rr.with_domain_name(ques.name().clone())
+                .with_type(rtype::A)
+                .with_class(rtype::CLASS_IN)
+                .with_ttl(300)
+                .with_rdata(Ipv4Addr::new(192, 0, 2, 1));
+            resp.with_answer(rr);
+            return resp;
+        }
+    }
+
+    fn query(id: u16, names: &[&str]) -> DNS {
+        let mut query = DNS::new();
+        query.head_mut().with_id(id).with_rd(1);
+        for name in names {
+            query.with_question(Question::from_parts(
+                name.parse().unwrap(),
+                rtype::A,
+                rtype::CLASS_IN,
+            ));
+        }
+        return query;
+    }
+
+    #[test]
+    pub fn test_cached_resolve() {
+        let inner = Arc::new(Counting::default());
+        let cached = Cached::new(Arc::new(Cache::new(1 << 20)), inner.clone());
+
+        let resp = cached.resolve(&query(1, &["www.example."]));
+        assert_eq!(1, resp.head().id());
+        assert_eq!(1, resp.answers().len());
+        assert_eq!(1, inner.0.load(Ordering::SeqCst));
+
+        // only the question that is not cached yet goes to the inner resolver
+        let resp = cached.resolve(&query(2, &["WWW.example.", "mail.example."]));
+        assert_eq!(2, resp.head().id());
+        assert_eq!(1, resp.head().ra());
+        assert_eq!(2, resp.answers().len());
+        assert!(resp.answers().iter().all(|rr| rr.ttl() <= 300));
+        assert_eq!(2, inner.0.load(Ordering::SeqCst));
+        assert_eq!(2, cached.cache().len());
+    }
+
+    // Answers like `Counting` with a TTL, delay and failure chosen by the test.
+    #[derive(Debug, Default)]
+    struct Flapping {
+        queries: AtomicUsize,
+        ttl: AtomicU32,
+        delay: AtomicU64,
+        failing: AtomicBool,
+    }
+
+    impl Resolver for Flapping {
+        fn resolve(&self, query: &DNS) -> DNS {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(self.delay.load(Ordering::SeqCst)));
+            let mut resp = DNS::response_for(query);
+            if self.failing.load(Ordering::SeqCst) {
+                resp.with_rcode(Rcode::ServFail);
+                return resp;
+            }
+            let mut rr = ResourceRecord::new();
+            rr.with_domain_name(query.questions().iter().next().unwrap().name().clone())
+                .with_type(rtype::A)
+                .with_class(rtype::CLASS_IN)
+                .with_ttl(self.ttl.load(Ordering::SeqCst))
+                .with_rdata(Ipv4Addr::new(192, 0, 2, 1));
+            resp.with_answer(rr);
+            return resp;
+        }
+    }
+
+    #[test]
+    pub fn test_cached_prefetch() {
+        let inner = Arc::new(Flapping::default());
+        inner.ttl.store(2, Ordering::SeqCst);
+        let mut cache = Cache::new(1 << 20);
+        cache.with_prefetch(60);
+        let cached = Cached::new(Arc::new(cache), inner.clone());
+        let query = query(1, &["www.example."]);
+
+        cached.resolve(&query);
+        assert_eq!(1, inner.queries.load(Ordering::SeqCst));
+        thread::sleep(Duration::from_millis(1100));
+
+        // the cached answer is served while one refresh runs
+        inner.delay.store(200, Ordering::SeqCst);
+        inner.ttl.store(300, Ordering::SeqCst);
+        assert!(
+            cached
+                .resolve(&query)
+                .answers()
+                .iter()
+                .next()
+                .unwrap()
+                .ttl()
+                <= 1
+        );
+        assert!(
+            cached
+                .resolve(&query)
+                .answers()
+                .iter()
+                .next()
+                .unwrap()
+                .ttl()
+                <= 1
+        );
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(2, inner.queries.load(Ordering::SeqCst));
+        assert!(
+            cached
+                .resolve(&query)
+                .answers()
+                .iter()
+                .next()
+                .unwrap()
+                .ttl()
+                > 200
+        );
+        assert_eq!(2, inner.queries.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_cached_serve_stale() {
+        let inner = Arc::new(Flapping::default());
+        inner.ttl.store(1, Ordering::SeqCst);
+        let mut cached = Cached::new(Arc::new(Cache::new(1 << 20)), inner.clone());
+        cached.with_stale_answer_timeout(Duration::from_millis(100));
+        let mut query = query(1, &["www.example."]);
+        query.with_edns(Some(Edns::new()));
+        let stale_ede = (OPTION_EDE, EDE_STALE_ANSWER.to_be_bytes().to_vec());
+
+        assert_eq!(1, cached.resolve(&query).answers().len());
+        thread::sleep(Duration::from_millis(1100));
+
+        // the upstream fails
+        inner.failing.store(true, Ordering::SeqCst);
+        let resp = cached.resolve(&query);
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(STALE_TTL, resp.answers().iter().next().unwrap().ttl());
+        assert!(resp.edns().unwrap().options().contains(&stale_ede));
+        assert_eq!(2, inner.queries.load(Ordering::SeqCst));
+        let mut plain = query.clone();
+        plain.with_edns(None);
+        assert!(cached.resolve(&plain).edns().is_none());
+
+        // the upstream is slow, the refresh goes on in the background
+        inner.failing.store(false, Ordering::SeqCst);
+        inner.delay.store(300, Ordering::SeqCst);
+        inner.ttl.store(300, Ordering::SeqCst);
+        let queries = inner.queries.load(Ordering::SeqCst);
+        let resp = cached.resolve(&query);
+        assert_eq!(STALE_TTL, resp.answers().iter().next().unwrap().ttl());
+        // a refresh is running already, so nobody waits for another one
+        let resp = cached.resolve(&query);
+        assert_eq!(STALE_TTL, resp.answers().iter().next().unwrap().ttl());
+        assert_eq!(queries + 1, inner.queries.load(Ordering::SeqCst));
+
+        thread::sleep(Duration::from_millis(400));
+        let resp = cached.resolve(&query);
+        assert!(resp.answers().iter().next().unwrap().ttl() > STALE_TTL);
+        assert!(resp.edns().unwrap().options().is_empty());
+        assert_eq!(queries + 1, inner.queries.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_cached_max_refreshing() {
+        let inner = Arc::new(Flapping::default());
+        inner.ttl.store(1, Ordering::SeqCst);
+        let mut cached = Cached::new(Arc::new(Cache::new(1 << 20)), inner.clone());
+        cached
+            .with_stale_answer_timeout(Duration::from_millis(100))
+            .with_max_refreshing(1);
+        let one = query(1, &["one.example."]);
+        let two = query(2, &["two.example."]);
+        cached.resolve(&one);
+        cached.resolve(&two);
+        thread::sleep(Duration::from_millis(1100));
+
+        inner.delay.store(300, Ordering::SeqCst);
+        inner.ttl.store(300, Ordering::SeqCst);
+        let resp = cached.resolve(&one);
+        assert_eq!(STALE_TTL, resp.answers().iter().next().unwrap().ttl());
+        // the only background refresh is taken, this client waits for the fresh answer
+        let resp = cached.resolve(&two);
+        assert!(resp.answers().iter().next().unwrap().ttl() > STALE_TTL);
+        assert_eq!(4, inner.queries.load(Ordering::SeqCst));
+    }
+}
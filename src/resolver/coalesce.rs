@@ -0,0 +1,238 @@
+use anyhow::Error;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_WAITERS: usize = 1024;
+
+// Runs one lookup per key at a time. Whoever asks for a key while its lookup is running
+// waits for that lookup and gets a copy of its result.
+#[derive(Debug)]
+pub struct Coalescer<K, V> {
+    calls: Mutex<HashMap<K, Arc<Call<V>>>>,
+    timeout: Duration,
+    max_waiters: usize,
+}
+
+// A running lookup. `result` is set once the lookup is done.
+#[derive(Debug)]
+struct Call<V> {
+    state: Mutex<State<V>>,
+    done: Condvar,
+}
+
+#[derive(Debug)]
+struct State<V> {
+    result: Option<Result<V, String>>,
+    waiters: usize,
+}
+
+impl<K, V> Coalescer<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        return Coalescer {
+            calls: Mutex::new(HashMap::new()),
+            timeout: DEFAULT_TIMEOUT,
+            max_waiters: DEFAULT_MAX_WAITERS,
+        };
+    }
+
+    // How long a waiter waits for the running lookup.
+    pub fn timeout(&self) -> Duration {
+        return self.timeout;
+    }
+
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    // Waiters for one lookup beyond which further callers fail, 0 runs every lookup on its
+    // own.
+    pub fn max_waiters(&self) -> usize {
+        return self.max_waiters;
+    }
+
+    pub fn with_max_waiters(&mut self, max_waiters: usize) -> &mut Self {
+        self.max_waiters = max_waiters;
+        return self;
+    }
+
+    // Number of keys with a lookup running.
+    pub fn len(&self) -> usize {
+        return self.calls.lock().unwrap().len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // The result of `lookup`, or of the lookup of `key` that is running already.
+    pub fn run<F>(&self, key: K, lookup: F) -> Result<V, Error>
+    where
+        F: FnOnce() -> Result<V, Error>,
+    {
+        if self.max_waiters == 0 {
+            return lookup();
+        }
+
+        let mut calls = self.calls.lock().unwrap();
+        if let Some(call) = calls.get(&key).cloned() {
+            drop(calls);
+            return self.wait(&call);
+        }
+        let call = Arc::new(Call {
+            state: Mutex::new(State {
+                result: None,
+                waiters: 0,
+            }),
+            done: Condvar::new(),
+        });
+        calls.insert(key.clone(), call.clone());
+        drop(calls);
+
+        // the waiters are released even when the lookup panics
+        let mut leader = Leader {
+            coalescer: self,
+            key,
+            call,
+            result: Err("the lookup did not finish".to_string()),
+        };
+        let result = lookup();
+        leader.result = match &result {
+            Ok(value) => Ok(value.clone()),
+            Err(e) => Err(e.to_string()),
+        };
+        return result;
+    }
+
+    fn wait(&self, call: &Call<V>) -> Result<V, Error> {
+        let mut state = call.state.lock().unwrap();
+        if state.waiters >= self.max_waiters {
+            return Err(Error::msg("too many queries wait for the same lookup"));
+        }
+        state.waiters += 1;
+        let (mut state, _) = call
+            .done
+            .wait_timeout_while(state, self.timeout, |state| state.result.is_none())
+            .unwrap();
+        state.waiters -= 1;
+        match &state.result {
+            Some(Ok(value)) => return Ok(value.clone()),
+            Some(Err(e)) => return Err(Error::msg(e.clone())),
+            None => return Err(Error::msg("timed out waiting for the same lookup")),
+        }
+    }
+}
+
+impl<K, V> Default for Coalescer<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// Hands the result of a lookup to its waiters and forgets the lookup when dropped.
+struct Leader<'a, K: Hash + Eq, V> {
+    coalescer: &'a Coalescer<K, V>,
+    key: K,
+    call: Arc<Call<V>>,
+    result: Result<V, String>,
+}
+
+impl<K: Hash + Eq, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        self.coalescer.calls.lock().unwrap().remove(&self.key);
+        let result = std::mem::replace(&mut self.result, Err(String::new()));
+        self.call.state.lock().unwrap().result = Some(result);
+        self.call.done.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    #[test]
+    pub fn test_coalesce() {
+        let coalescer = Arc::new(Coalescer::<u16, u16>::new());
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let coalescer = coalescer.clone();
+                let lookups = lookups.clone();
+                thread::spawn(move || {
+                    return coalescer.run(1, || {
+                        lookups.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(200));
+                        return Ok(42);
+                    });
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(42, thread.join().unwrap().unwrap());
+        }
+        assert_eq!(1, lookups.load(Ordering::SeqCst));
+        assert!(coalescer.is_empty());
+
+        // errors reach the waiters too, and nothing is remembered afterwards
+        let failing = {
+            let coalescer = coalescer.clone();
+            thread::spawn(move || {
+                return coalescer.run(2, || {
+                    thread::sleep(Duration::from_millis(200));
+                    return Err(Error::msg("upstream down"));
+                });
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        let err = coalescer.run(2, || Ok(0)).unwrap_err();
+        assert_eq!("upstream down", err.to_string());
+        assert!(failing.join().unwrap().is_err());
+        assert_eq!(7, coalescer.run(2, || Ok(7)).unwrap());
+    }
+
+    #[test]
+    pub fn test_coalesce_limits() {
+        let mut coalescer = Coalescer::<u16, u16>::new();
+        coalescer
+            .with_timeout(Duration::from_millis(100))
+            .with_max_waiters(1);
+        let coalescer = Arc::new(coalescer);
+        let slow = {
+            let coalescer = coalescer.clone();
+            thread::spawn(move || {
+                return coalescer.run(1, || {
+                    thread::sleep(Duration::from_millis(300));
+                    return Ok(1);
+                });
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+
+        let waiter = {
+            let coalescer = coalescer.clone();
+            thread::spawn(move || coalescer.run(1, || Ok(2)))
+        };
+        thread::sleep(Duration::from_millis(10));
+        // one waiter at most, and it gives up before the lookup is done
+        assert!(coalescer.run(1, || Ok(3)).is_err());
+        assert!(waiter.join().unwrap().is_err());
+        assert_eq!(1, slow.join().unwrap().unwrap());
+    }
+}
@@ -0,0 +1,356 @@
+use anyhow::Error;
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use super::{coalesce::Coalescer, exchange, merge_replies, Resolver};
+use crate::{
+    debug,
+    dns::{edns::Edns, question::Question, DomainName, DNS},
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_RETRIES: usize = 2;
+
+// Sends the queries to upstream resolvers. Each question goes out as a query of its own,
+// many resolvers refuse QDCOUNT > 1, and the replies are merged into one response.
+// Identical questions asked at the same time share one upstream query.
+#[derive(Debug)]
+pub struct Forwarder {
+    upstreams: Vec<SocketAddr>,
+    timeout: Duration,
+    retries: usize,
+    // round robin start of the next query
+    next: AtomicUsize,
+    // upstream queries running, by name, type, class, DO and CD bit
+    inflight: Coalescer<(DomainName, u16, u16, bool, bool), DNS>,
+}
+
+impl Forwarder {
+    pub fn new(upstreams: Vec<SocketAddr>) -> Self {
+        return Forwarder {
+            upstreams,
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            next: AtomicUsize::new(0),
+            inflight: Coalescer::new(),
+        };
+    }
+
+    pub fn upstreams(&self) -> &[SocketAddr] {
+        return &self.upstreams;
+    }
+
+    // How long to wait for the reply of one upstream.
+    pub fn timeout(&self) -> Duration {
+        return self.timeout;
+    }
+
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    // Attempts after the first one, each goes to the next upstream.
+    pub fn retries(&self) -> usize {
+        return self.retries;
+    }
+
+    pub fn with_retries(&mut self, retries: usize) -> &mut Self {
+        self.retries = retries;
+        return self;
+    }
+
+    // How long a query waits for the identical upstream query that is running already.
+    pub fn coalesce_timeout(&self) -> Duration {
+        return self.inflight.timeout();
+    }
+
+    pub fn with_coalesce_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.inflight.with_timeout(timeout);
+        return self;
+    }
+
+    // Queries that may wait for one upstream query, further ones fail. 0 sends every query
+    // upstream.
+    pub fn max_waiters(&self) -> usize {
+        return self.inflight.max_waiters();
+    }
+
+    pub fn with_max_waiters(&mut self, max_waiters: usize) -> &mut Self {
+        self.inflight.with_max_waiters(max_waiters);
+        return self;
+    }
+
+    // Resolve one question, trying the upstreams in turn until one replies.
+    fn forward(&self, ques: &Question, query: &DNS) -> Result<DNS, Error> {
+        if self.upstreams.is_empty() {
+            return Err(Error::msg("no upstream resolvers"));
+        }
+
+        let mut upstream_query = DNS::new();
+        upstream_query
+            .head_mut()
+            .with_id(rand::random())
+            .with_rd(1)
+            .with_cd(query.head().cd());
+        upstream_query.with_question(ques.clone());
+        // always advertise our buffer size, it saves most TCP retries
+        let mut edns = Edns::new();
+        edns.with_dnssec_ok(query.edns().is_some_and(|e| e.dnssec_ok()));
+        upstream_query.with_edns(Some(edns));
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut last_err = Error::msg("no attempt made");
+        for attempt in 0..=self.retries {
+            let upstream = self.upstreams[(start + attempt) % self.upstreams.len()];
+            match exchange(upstream, &upstream_query, self.timeout) {
+                Ok(reply) => return Ok(reply),
+                Err(e) => {
+                    debug!("Upstream {} failed for {}: {}", upstream, ques.name(), e);
+                    last_err = e;
+                }
+            }
+        }
+
+        return Err(last_err);
+    }
+}
+
+impl Resolver for Forwarder {
+    fn resolve(&self, query: &DNS) -> DNS {
+        let dnssec_ok = query.edns().is_some_and(|e| e.dnssec_ok());
+        let checking_disabled = query.head().cd() == 1;
+        return merge_replies(query, |ques| {
+            let key = (
+                ques.name().clone(),
+                ques.typ(),
+                ques.class(),
+                dnssec_ok,
+                checking_disabled,
+            );
+            return self.inflight.run(key, || self.forward(ques, query));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dns::{answer::ResourceRecord, rcode::Rcode, rtype, DomainName},
+        server::tcp::{read_frame, write_frame},
+    };
+    use std::{
+        net::{Ipv4Addr, TcpListener, UdpSocket},
+        sync::{atomic::AtomicUsize, Arc},
+        thread,
+    };
+
+    // A local upstream answering every query with the packets `reply` returns for it.
+    // Stops after a second without queries.
+    fn stub<F>(reply: F) -> (SocketAddr, Arc<AtomicUsize>)
+    where
+        F: Fn(&DNS, usize) -> Vec<Vec<u8>> + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let addr = socket.local_addr().unwrap();
+        let count = Arc::new(AtomicUsize::new(0));
+        let received = count.clone();
+        thread::spawn(move || {
+            let mut buf = [0_u8; 512];
+            while let Ok((len, source)) = socket.recv_from(&mut buf) {
+                let query = DNS::decode(&buf[..len]).unwrap();
+                assert_eq!(1, query.questions().len());
+                let n = received.fetch_add(1, Ordering::SeqCst);
+                for packet in reply(&query, n) {
+                    socket.send_to(&packet, source).unwrap();
+                }
+            }
+        });
+        return (addr, count);
+    }
+
+    fn answer(query: &DNS, ip: Ipv4Addr) -> DNS {
+        let mut resp = DNS::response_for(query);
+        let mut rr = ResourceRecord::new();
+        rr.with_domain_name(query.questions().iter().next().unwrap().name().clone())
+            .with_type(rtype::A)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(60)
+            .with_rdata(ip);
+        resp.with_answer(rr);
+        return resp;
+    }
+
+    fn query(names: &[&str]) -> DNS {
+        let mut query = DNS::new();
+        query.head_mut().with_id(4321).with_rd(1);
+        for name in names {
+            let name: DomainName = name.parse().unwrap();
+            query.with_question(Question::from_parts(name, rtype::A, rtype::CLASS_IN));
+        }
+        return query;
+    }
+
+    fn forwarder(upstreams: Vec<SocketAddr>) -> Forwarder {
+        let mut forwarder = Forwarder::new(upstreams);
+        forwarder
+            .with_timeout(Duration::from_millis(200))
+            .with_retries(1);
+        return forwarder;
+    }
+
+    #[test]
+    pub fn test_forward_merges_questions() {
+        let (addr, count) =
+            stub(|query, n| vec![answer(query, Ipv4Addr::new(192, 0, 2, n as u8)).encode()]);
+
+        let resp = forwarder(vec![addr]).resolve(&query(&["a.example.", "b.example."]));
+        assert_eq!(4321, resp.head().id());
+        assert_eq!(1, resp.head().qr());
+        assert_eq!(1, resp.head().ra());
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(2, resp.questions().len());
+        let answers: Vec<String> = resp
+            .answers()
+            .iter()
+            .map(|rr| rr.name().to_string())
+            .collect();
+        assert_eq!(vec!["a.example.", "b.example."], answers);
+        assert_eq!(2, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_forward_ignores_mismatched_replies() {
+        let (addr, _) = stub(|q, _| {
+            let mut wrong_id = answer(q, Ipv4Addr::new(192, 0, 2, 66));
+            wrong_id.head_mut().with_id(q.head().id().wrapping_add(1));
+            let mut wrong_question = query(&["other.example."]);
+            wrong_question.head_mut().with_id(q.head().id());
+            let wrong_question = answer(&wrong_question, Ipv4Addr::new(192, 0, 2, 66));
+            let mut nxdomain = DNS::response_for(q);
+            nxdomain.with_rcode(Rcode::NXDomain);
+            return vec![
+                wrong_id.encode(),
+                wrong_question.encode(),
+                nxdomain.encode(),
+            ];
+        });
+
+        let resp = forwarder(vec![addr]).resolve(&query(&["missing.example."]));
+        assert_eq!(Rcode::NXDomain, resp.rcode());
+        assert!(resp.answers().is_empty());
+    }
+
+    #[test]
+    pub fn test_forward_retries() {
+        // the first query is lost, the retry goes to the second upstream
+        let (silent, silent_count) = stub(|_, _| vec![]);
+        let (addr, count) =
+            stub(|query, _| vec![answer(query, Ipv4Addr::new(192, 0, 2, 1)).encode()]);
+
+        let resp = forwarder(vec![silent, addr]).resolve(&query(&["a.example."]));
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(1, resp.answers().len());
+        assert_eq!(1, silent_count.load(Ordering::SeqCst));
+        assert_eq!(1, count.load(Ordering::SeqCst));
+
+        let (silent, silent_count) = stub(|_, _| vec![]);
+        let resp = forwarder(vec![silent]).resolve(&query(&["a.example."]));
+        assert_eq!(4321, resp.head().id());
+        assert_eq!(Rcode::ServFail, resp.rcode());
+        assert_eq!(2, silent_count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_forward_truncated_over_tcp() {
+        let (addr, _) = stub(|query, _| {
+            let mut truncated = DNS::response_for(query);
+            truncated.head_mut().with_tc(1);
+            return vec![truncated.encode()];
+        });
+        let listener = TcpListener::bind(addr).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let query = DNS::decode(&read_frame(&mut stream).unwrap().unwrap()).unwrap();
+            write_frame(
+                &mut stream,
+                &answer(&query, Ipv4Addr::new(192, 0, 2, 7)).encode(),
+            )
+            .unwrap();
+        });
+
+        let resp = forwarder(vec![addr]).resolve(&query(&["big.example."]));
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(0, resp.head().tc());
+        assert_eq!(
+            &[192, 0, 2, 7],
+            resp.answers().iter().next().unwrap().rdata()
+        );
+    }
+
+    #[test]
+    pub fn test_forward_coalesces_identical_queries() {
+        let (addr, count) = stub(|query, _| {
+            thread::sleep(Duration::from_millis(100));
+            return vec![answer(query, Ipv4Addr::new(192, 0, 2, 1)).encode()];
+        });
+        let mut forwarder = Forwarder::new(vec![addr]);
+        forwarder.with_timeout(Duration::from_secs(1));
+        let forwarder = Arc::new(forwarder);
+
+        let clients: Vec<_> = (0..20)
+            .map(|_| {
+                let forwarder = forwarder.clone();
+                thread::spawn(move || forwarder.resolve(&query(&["www.example."])))
+            })
+            .collect();
+        for client in clients {
+            let resp = client.join().unwrap();
+            assert_eq!(4321, resp.head().id());
+            assert_eq!(1, resp.answers().len());
+        }
+        assert_eq!(1, count.load(Ordering::SeqCst));
+
+        // the DO bit asks for different data
+        let mut dnssec = query(&["www.example."]);
+        let mut edns = Edns::new();
+        edns.with_dnssec_ok(true);
+        dnssec.with_edns(Some(edns));
+        forwarder.resolve(&dnssec);
+        assert_eq!(2, count.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_forward_real_wire_reply() {
+        let (addr, _) = stub(|query, _| {
+            // a plain recursive query: qr 0, opcode QUERY, rd 1
+            assert_eq!(0x01, query.encode()[2]);
+            // the reply of 8.8.8.8 to `dig example.com A`, with the ID of this query
+            let mut raw = vec![
+                0x00, 0x00, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            ];
+            raw[..2].copy_from_slice(&query.head().id().to_be_bytes());
+            raw.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+            raw.extend_from_slice(&[
+                0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x04, 0x5d, 0xb8,
+                0xd7, 0x0e,
+            ]);
+            return vec![raw];
+        });
+
+        let resp = forwarder(vec![addr]).resolve(&query(&["example.com."]));
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(1, resp.head().ra());
+        assert_eq!(
+            &[93, 184, 215, 14],
+            resp.answers().iter().next().unwrap().rdata()
+        );
+    }
+}
@@ -0,0 +1,165 @@
+pub mod cached;
+pub mod coalesce;
+pub mod forward;
+pub mod recursive;
+
+use anyhow::Error;
+use std::{
+    fmt::Debug,
+    io::ErrorKind,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    debug,
+    dns::{answer::ResourceRecord, edns::OPTION_EDE, question::Question, rcode::Rcode, DNS},
+    server::tcp::{read_frame, write_frame},
+};
+
+// Largest reply a server can send over UDP.
+const RECV_LENGTH: usize = 65535;
+
+// Answers the queries the server has no local data for.
+pub trait Resolver: Send + Sync + Debug {
+    // The full reply to `query`, with the ID and question section of the query.
+    fn resolve(&self, query: &DNS) -> DNS;
+}
+
+// Send `query` to `server` over UDP and wait for its reply, repeating the query over TCP
+// when the reply is truncated.
+pub(crate) fn exchange(server: SocketAddr, query: &DNS, timeout: Duration) -> Result<DNS, Error> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.send_to(&query.encode(), server)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0_u8; RECV_LENGTH];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(Error::msg("timed out"));
+        }
+        socket.set_read_timeout(Some(left))?;
+        let (len, source) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(Error::msg("timed out"));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // anything but the reply to this query is ignored, it may be spoofed
+        if source != server {
+            continue;
+        }
+        let reply = match DNS::decode(&buf[..len]) {
+            Ok(reply) if is_reply_to(query, &reply) => reply,
+            _ => continue,
+        };
+        if reply.head().tc() == 1 {
+            return exchange_tcp(server, query, timeout);
+        }
+        return Ok(reply);
+    }
+}
+
+fn exchange_tcp(server: SocketAddr, query: &DNS, timeout: Duration) -> Result<DNS, Error> {
+    let mut stream = TcpStream::connect_timeout(&server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write_frame(&mut stream, &query.encode())?;
+
+    let raw = read_frame(&mut stream)?.ok_or_else(|| Error::msg("connection closed"))?;
+    let reply = DNS::decode(&raw)?;
+    if !is_reply_to(query, &reply) {
+        return Err(Error::msg("mismatched reply over TCP"));
+    }
+
+    return Ok(reply);
+}
+
+// A reply matches when it is a response with the ID and question of the query.
+fn is_reply_to(query: &DNS, reply: &DNS) -> bool {
+    if reply.head().qr() != 1 || reply.head().id() != query.head().id() {
+        return false;
+    }
+    let mut asked = query.questions().iter();
+    let mut answered = reply.questions().iter();
+    return match (asked.next(), answered.next(), answered.next()) {
+        (Some(q), Some(a), None) => {
+            q.name() == a.name() && q.typ() == a.typ() && q.class() == a.class()
+        }
+        _ => false,
+    };
+}
+
+// Build the reply to `query` from one reply per question. The sections are merged, the
+// first rcode other than NOERROR is the rcode of the response and SERVFAIL is returned
+// when a question could not be answered at all.
+pub(crate) fn merge_replies<F>(query: &DNS, mut resolve: F) -> DNS
+where
+    F: FnMut(&Question) -> Result<DNS, Error>,
+{
+    let mut resp = DNS::response_for(query);
+    if resp.rcode() != Rcode::NoError {
+        return resp;
+    }
+    resp.head_mut().with_ra(1);
+
+    let mut rcode = Rcode::NoError;
+    for ques in query.questions().iter() {
+        let reply = match resolve(ques) {
+            Ok(reply) => reply,
+            Err(e) => {
+                debug!("Resolving {} failed: {}", ques.name(), e);
+                let mut resp = DNS::response_for(query);
+                resp.head_mut().with_ra(1);
+                resp.with_rcode(Rcode::ServFail);
+                return resp;
+            }
+        };
+        if rcode == Rcode::NoError {
+            rcode = reply.rcode();
+        }
+        for rr in reply.answers().iter() {
+            if !contains(resp.answers().iter(), rr) {
+                resp.with_answer(rr.clone());
+            }
+        }
+        for rr in reply.authorities().iter() {
+            if !contains(resp.authorities().iter(), rr) {
+                resp.with_authority(rr.clone());
+            }
+        }
+        for rr in reply.additionals().iter() {
+            if !contains(resp.additionals().iter(), rr) {
+                resp.with_additional(rr.clone());
+            }
+        }
+        // extended errors, such as a stale answer, go to clients that sent an OPT record
+        if let (Some(own), Some(edns)) = (resp.edns(), reply.edns()) {
+            let mut own = own.clone();
+            for (code, data) in edns.options() {
+                if *code == OPTION_EDE && !own.options().contains(&(*code, data.clone())) {
+                    own.with_option(*code, data.clone());
+                }
+            }
+            resp.with_edns(Some(own));
+        }
+    }
+    resp.with_rcode(rcode);
+
+    return resp;
+}
+
+// Questions for names sharing a zone bring the same authority and glue records.
+fn contains<'a>(
+    mut records: impl Iterator<Item = &'a ResourceRecord>,
+    rr: &ResourceRecord,
+) -> bool {
+    let encoded = rr.encode();
+    return records.any(|r| r.encode() == encoded);
+}
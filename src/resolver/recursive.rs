@@ -0,0 +1,773 @@
+use anyhow::Error;
+use rand::seq::SliceRandom;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use super::{exchange, merge_replies, Resolver};
+use crate::{
+    cache::{Cache, Trust},
+    config::DNS_PORT,
+    debug,
+    dns::{
+        answer::ResourceRecord, edns::Edns, question::Question, rcode::Rcode, rtype, DomainName,
+        DNS,
+    },
+};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1500);
+// Queries sent to name servers for one question, including glue and CNAME lookups.
+pub const DEFAULT_MAX_QUERIES: usize = 64;
+// Nested lookups: name server addresses and CNAME targets in other zones.
+pub const DEFAULT_MAX_DEPTH: usize = 6;
+// Length of a CNAME chain.
+const MAX_CNAMES: usize = 8;
+
+// The root servers (https://www.iana.org/domains/root/files).
+const ROOT_HINTS: [IpAddr; 26] = [
+    IpAddr::V4(Ipv4Addr::new(198, 41, 0, 4)),
+    IpAddr::V4(Ipv4Addr::new(170, 247, 170, 2)),
+    IpAddr::V4(Ipv4Addr::new(192, 33, 4, 12)),
+    IpAddr::V4(Ipv4Addr::new(199, 7, 91, 13)),
+    IpAddr::V4(Ipv4Addr::new(192, 203, 230, 10)),
+    IpAddr::V4(Ipv4Addr::new(192, 5, 5, 241)),
+    IpAddr::V4(Ipv4Addr::new(192, 112, 36, 4)),
+    IpAddr::V4(Ipv4Addr::new(198, 97, 190, 53)),
+    IpAddr::V4(Ipv4Addr::new(192, 36, 148, 17)),
+    IpAddr::V4(Ipv4Addr::new(192, 58, 128, 30)),
+    IpAddr::V4(Ipv4Addr::new(193, 0, 14, 129)),
+    IpAddr::V4(Ipv4Addr::new(199, 7, 83, 42)),
+    IpAddr::V4(Ipv4Addr::new(202, 12, 27, 33)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30)),
+    IpAddr::V6(Ipv6Addr::new(0x2801, 0x1b8, 0x10, 0, 0, 0, 0, 0xb)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x2, 0, 0, 0, 0, 0xc)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x2d, 0, 0, 0, 0, 0xd)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0xa8, 0, 0, 0, 0, 0xe)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x2f, 0, 0, 0, 0, 0xf)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x12, 0, 0, 0, 0, 0xd0d)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x1, 0, 0, 0, 0, 0x53)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x7fe, 0, 0, 0, 0, 0, 0x53)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x503, 0xc27, 0, 0, 0, 0x2, 0x30)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x7fd, 0, 0, 0, 0, 0, 0x1)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x500, 0x9f, 0, 0, 0, 0, 0x42)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0xdc3, 0, 0, 0, 0, 0, 0x35)),
+];
+
+// Resolves queries itself, starting at the root servers and following the referrals
+// down to the authoritative servers of the name.
+#[derive(Debug, Clone)]
+pub struct Recursor {
+    hints: Vec<SocketAddr>,
+    port: u16,
+    timeout: Duration,
+    max_queries: usize,
+    max_depth: usize,
+    cache: Option<Arc<Cache>>,
+}
+
+// The outcome of one question, `answers` includes the CNAMEs that led to the name.
+struct Resolution {
+    rcode: Rcode,
+    answers: Vec<ResourceRecord>,
+    authorities: Vec<ResourceRecord>,
+}
+
+impl Recursor {
+    // Start at the servers in `hints`, usually the root servers of `root_hints`.
+    pub fn new(hints: Vec<SocketAddr>) -> Self {
+        return Recursor {
+            hints,
+            port: DNS_PORT,
+            timeout: DEFAULT_TIMEOUT,
+            max_queries: DEFAULT_MAX_QUERIES,
+            max_depth: DEFAULT_MAX_DEPTH,
+            cache: None,
+        };
+    }
+
+    pub fn root_hints() -> Vec<SocketAddr> {
+        return ROOT_HINTS
+            .iter()
+            .map(|ip| SocketAddr::new(*ip, DNS_PORT))
+            .collect();
+    }
+
+    pub fn hints(&self) -> &[SocketAddr] {
+        return &self.hints;
+    }
+
+    // Port of the name servers learned from referrals.
+    pub fn port(&self) -> u16 {
+        return self.port;
+    }
+
+    pub fn with_port(&mut self, port: u16) -> &mut Self {
+        self.port = port;
+        return self;
+    }
+
+    // How long to wait for the reply of one name server.
+    pub fn timeout(&self) -> Duration {
+        return self.timeout;
+    }
+
+    pub fn with_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    pub fn max_queries(&self) -> usize {
+        return self.max_queries;
+    }
+
+    pub fn with_max_queries(&mut self, max_queries: usize) -> &mut Self {
+        self.max_queries = max_queries;
+        return self;
+    }
+
+    pub fn max_depth(&self) -> usize {
+        return self.max_depth;
+    }
+
+    pub fn with_max_depth(&mut self, max_depth: usize) -> &mut Self {
+        self.max_depth = max_depth;
+        return self;
+    }
+
+    // Remember the replies of name servers, later queries start at the closest zone cut
+    // with cached name server addresses instead of the hints.
+    pub fn with_cache(&mut self, cache: Arc<Cache>) -> &mut Self {
+        self.cache = Some(cache);
+        return self;
+    }
+
+    // Resolve one question into a reply of its own, `merge_replies` builds the response.
+    fn resolve_question(&self, ques: &Question) -> Result<DNS, Error> {
+        let mut sent = 0;
+        let res = self.resolve_name(ques.name(), ques.typ(), ques.class(), &mut sent, 0)?;
+
+        let mut reply = DNS::new();
+        reply.head_mut().with_qr(1);
+        reply.with_rcode(res.rcode);
+        for rr in res.answers {
+            reply.with_answer(rr);
+        }
+        for rr in res.authorities {
+            reply.with_authority(rr);
+        }
+        return Ok(reply);
+    }
+
+    // Walk down from the hints to the servers of `qname`. CNAMEs are followed, restarting
+    // at the hints when the target is not in the same reply.
+    fn resolve_name(
+        &self,
+        qname: &DomainName,
+        qtype: u16,
+        qclass: u16,
+        sent: &mut usize,
+        depth: usize,
+    ) -> Result<Resolution, Error> {
+        if depth > self.max_depth {
+            return Err(Error::msg("too many nested lookups"));
+        }
+
+        let mut chain = Vec::<ResourceRecord>::new();
+        let mut name = qname.clone();
+        let (mut zone, mut servers) = self.closest_servers(&name, qclass);
+        loop {
+            let reply = self.ask(&servers, &zone, &name, qtype, qclass, sent)?;
+            if reply.rcode() == Rcode::NXDomain {
+                return Ok(Resolution {
+                    rcode: Rcode::NXDomain,
+                    answers: chain,
+                    authorities: soa_of(&reply, &zone),
+                });
+            }
+
+            // only records from the zone the server was asked about are believed
+            let answers: Vec<&ResourceRecord> = reply
+                .answers()
+                .iter()
+                .filter(|rr| rr.name().is_subdomain_of(&zone) && rr.class() == qclass)
+                .collect();
+            let mut followed = false;
+            loop {
+                let matching: Vec<ResourceRecord> = answers
+                    .iter()
+                    .filter(|rr| rr.name() == &name && (rr.typ() == qtype || qtype == rtype::ANY))
+                    .map(|rr| (*rr).clone())
+                    .collect();
+                if !matching.is_empty() {
+                    chain.extend(matching);
+                    return Ok(Resolution {
+                        rcode: Rcode::NoError,
+                        answers: chain,
+                        authorities: vec![],
+                    });
+                }
+                let cname = match answers
+                    .iter()
+                    .find(|rr| rr.name() == &name && rr.typ() == rtype::CNAME)
+                {
+                    Some(cname) => cname,
+                    None => break,
+                };
+                if chain.len() >= MAX_CNAMES {
+                    return Err(Error::msg(format!("CNAME chain of {} is too long", qname)));
+                }
+                name = DomainName::decode(cname.rdata(), 0)?.0;
+                chain.push((*cname).clone());
+                followed = true;
+            }
+            if followed {
+                (zone, servers) = self.closest_servers(&name, qclass);
+                continue;
+            }
+
+            match referral(&reply, &zone, &name) {
+                Some((cut, ns_names)) => {
+                    servers = self.addresses(&ns_names, &reply, &zone, qclass, sent, depth)?;
+                    debug!("{} is delegated to {:?}", cut, servers);
+                    zone = cut;
+                }
+                None if reply.authorities().iter().any(|rr| rr.typ() == rtype::NS)
+                    && reply.head().aa() == 0 =>
+                {
+                    return Err(Error::msg(format!("lame referral for {}", name)));
+                }
+                // no data for the type
+                None => {
+                    return Ok(Resolution {
+                        rcode: Rcode::NoError,
+                        answers: chain,
+                        authorities: soa_of(&reply, &zone),
+                    });
+                }
+            }
+        }
+    }
+
+    // The deepest zone above `name` with cached name server addresses, the root and the
+    // hints when there is none.
+    fn closest_servers(&self, name: &DomainName, qclass: u16) -> (DomainName, Vec<SocketAddr>) {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return (DomainName::root(), self.hints.clone()),
+        };
+
+        let mut zone = Some(name.clone());
+        while let Some(cut) = zone.filter(|cut| !cut.is_root()) {
+            let ns_names = cache
+                .get(&cut, rtype::NS, qclass, Trust::Additional)
+                .unwrap_or_default();
+            let mut addrs = Vec::<SocketAddr>::new();
+            for ns in ns_names
+                .iter()
+                .filter_map(|rr| DomainName::decode(rr.rdata(), 0).ok())
+            {
+                for typ in [rtype::A, rtype::AAAA] {
+                    let glue = cache.get(&ns.0, typ, qclass, Trust::Additional);
+                    addrs.extend(
+                        glue.unwrap_or_default()
+                            .iter()
+                            .filter_map(address)
+                            .map(|ip| SocketAddr::new(ip, self.port)),
+                    );
+                }
+            }
+            if !addrs.is_empty() {
+                return (cut, addrs);
+            }
+            zone = cut.parent();
+        }
+
+        return (DomainName::root(), self.hints.clone());
+    }
+
+    // Send the question to one server of `zone` after the other until one gives a usable
+    // reply.
+    fn ask(
+        &self,
+        servers: &[SocketAddr],
+        zone: &DomainName,
+        name: &DomainName,
+        qtype: u16,
+        qclass: u16,
+        sent: &mut usize,
+    ) -> Result<DNS, Error> {
+        let mut query = DNS::new();
+        query.head_mut().with_id(rand::random());
+        query.with_question(Question::from_parts(name.clone(), qtype, qclass));
+        query.with_edns(Some(Edns::new()));
+
+        let mut servers = servers.to_vec();
+        servers.shuffle(&mut rand::thread_rng());
+        for server in servers {
+            if *sent >= self.max_queries {
+                return Err(Error::msg(format!(
+                    "gave up on {} after {} queries",
+                    name, sent
+                )));
+            }
+            *sent += 1;
+            match exchange(server, &query, self.timeout) {
+                Ok(reply) if matches!(reply.rcode(), Rcode::NoError | Rcode::NXDomain) => {
+                    if let Some(cache) = &self.cache {
+                        cache.store(&reply, zone);
+                    }
+                    return Ok(reply);
+                }
+                Ok(reply) => debug!("{} answered {} for {}", server, reply.rcode(), name),
+                Err(e) => debug!("{} failed for {}: {}", server, name, e),
+            }
+        }
+
+        return Err(Error::msg(format!("no name server answered for {}", name)));
+    }
+
+    // Addresses of the name servers of a referral, taken from the glue when it is there
+    // and looked up otherwise. Glue outside the zone that sent it is not trusted.
+    fn addresses(
+        &self,
+        ns_names: &[DomainName],
+        reply: &DNS,
+        zone: &DomainName,
+        qclass: u16,
+        sent: &mut usize,
+        depth: usize,
+    ) -> Result<Vec<SocketAddr>, Error> {
+        let mut addrs = Vec::<SocketAddr>::new();
+        for rr in reply.additionals().iter() {
+            if !ns_names.contains(rr.name()) || !rr.name().is_subdomain_of(zone) {
+                continue;
+            }
+            if let Some(ip) = address(rr) {
+                addrs.push(SocketAddr::new(ip, self.port));
+            }
+        }
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+
+        for ns in ns_names {
+            // name servers reachable over IPv6 only have no A record
+            for typ in [rtype::A, rtype::AAAA] {
+                match self.resolve_name(ns, typ, qclass, sent, depth + 1) {
+                    Ok(res) => addrs.extend(
+                        res.answers
+                            .iter()
+                            .filter_map(address)
+                            .map(|ip| SocketAddr::new(ip, self.port)),
+                    ),
+                    Err(e) => debug!("No address for name server {}: {}", ns, e),
+                }
+            }
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+        }
+
+        return Err(Error::msg("no address for any of the name servers"));
+    }
+}
+
+impl Resolver for Recursor {
+    fn resolve(&self, query: &DNS) -> DNS {
+        return merge_replies(query, |ques| self.resolve_question(ques));
+    }
+}
+
+// The zone cut and name server names of a referral towards `name`. Only referrals that
+// lead further down from `zone` count, anything else would loop.
+fn referral(
+    reply: &DNS,
+    zone: &DomainName,
+    name: &DomainName,
+) -> Option<(DomainName, Vec<DomainName>)> {
+    let cut = reply
+        .authorities()
+        .iter()
+        .find(|rr| rr.typ() == rtype::NS)?
+        .name()
+        .clone();
+    if cut == *zone || !cut.is_subdomain_of(zone) || !name.is_subdomain_of(&cut) {
+        return None;
+    }
+
+    let ns_names: Vec<DomainName> = reply
+        .authorities()
+        .iter()
+        .filter(|rr| rr.typ() == rtype::NS && rr.name() == &cut)
+        .filter_map(|rr| DomainName::decode(rr.rdata(), 0).ok())
+        .map(|(ns, _)| ns)
+        .collect();
+    if ns_names.is_empty() {
+        return None;
+    }
+    return Some((cut, ns_names));
+}
+
+// The SOA record of a negative reply, needed by the client to cache it.
+fn soa_of(reply: &DNS, zone: &DomainName) -> Vec<ResourceRecord> {
+    return reply
+        .authorities()
+        .iter()
+        .filter(|rr| rr.typ() == rtype::SOA && rr.name().is_subdomain_of(zone))
+        .cloned()
+        .collect();
+}
+
+fn address(rr: &ResourceRecord) -> Option<IpAddr> {
+    return match (rr.typ(), rr.rdata().len()) {
+        (rtype::A, 4) => Some(IpAddr::from(<[u8; 4]>::try_from(rr.rdata()).ok()?)),
+        (rtype::AAAA, 16) => Some(IpAddr::from(<[u8; 16]>::try_from(rr.rdata()).ok()?)),
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, thread};
+
+    fn name(s: &str) -> DomainName {
+        return s.parse().unwrap();
+    }
+
+    fn record(owner: &str, typ: u16, rdata: Vec<u8>) -> ResourceRecord {
+        let mut rr = ResourceRecord::new();
+        rr.with_domain_name(name(owner))
+            .with_type(typ)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(300)
+            .with_raw_rdata(rdata);
+        return rr;
+    }
+
+    fn a(owner: &str, ip: [u8; 4]) -> ResourceRecord {
+        return record(owner, rtype::A, ip.to_vec());
+    }
+
+    fn aaaa(owner: &str, ip: Ipv6Addr) -> ResourceRecord {
+        return record(owner, rtype::AAAA, ip.octets().to_vec());
+    }
+
+    fn ns(owner: &str, target: &str) -> ResourceRecord {
+        return record(owner, rtype::NS, name(target).encode());
+    }
+
+    fn cname(owner: &str, target: &str) -> ResourceRecord {
+        return record(owner, rtype::CNAME, name(target).encode());
+    }
+
+    fn soa(apex: &str) -> ResourceRecord {
+        let mut rdata = name(apex).child(b"ns").unwrap().encode();
+        rdata.extend(name(apex).child(b"hostmaster").unwrap().encode());
+        rdata.extend([
+            0, 0, 0, 1, 0, 0, 14, 16, 0, 0, 3, 132, 0, 9, 58, 128, 0, 0, 1, 44,
+        ]);
+        return record(apex, rtype::SOA, rdata);
+    }
+
+    // A minimal authoritative server for the zone at `apex` holding `records`: referrals
+    // for delegated names, answers with CNAMEs, NODATA and NXDOMAIN with the SOA.
+    fn serve(addr: SocketAddr, apex: &str, records: Vec<ResourceRecord>) {
+        let socket = UdpSocket::bind(addr).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        let apex = name(apex);
+        thread::spawn(move || {
+            let mut buf = [0_u8; 512];
+            while let Ok((len, source)) = socket.recv_from(&mut buf) {
+                let query = DNS::decode(&buf[..len]).unwrap();
+                let resp = respond(&query, &apex, &records);
+                socket.send_to(&resp.encode(), source).unwrap();
+            }
+        });
+    }
+
+    fn respond(query: &DNS, apex: &DomainName, records: &[ResourceRecord]) -> DNS {
+        let ques = query.questions().iter().next().unwrap();
+        let qname = ques.name();
+        let mut resp = DNS::response_for(query);
+
+        let cut = records.iter().find(|rr| {
+            rr.typ() == rtype::NS && rr.name() != apex && qname.is_subdomain_of(rr.name())
+        });
+        if let Some(cut) = cut {
+            for rr in records
+                .iter()
+                .filter(|rr| rr.typ() == rtype::NS && rr.name() == cut.name())
+            {
+                resp.with_authority(rr.clone());
+                let target = DomainName::decode(rr.rdata(), 0).unwrap().0;
+                for glue in records
+                    .iter()
+                    .filter(|g| g.typ() == rtype::A && g.name() == &target)
+                {
+                    resp.with_additional(glue.clone());
+                }
+            }
+            return resp;
+        }
+
+        resp.head_mut().with_aa(1);
+        let mut owner = qname.clone();
+        for _ in 0..MAX_CNAMES {
+            let at_owner: Vec<&ResourceRecord> =
+                records.iter().filter(|rr| rr.name() == &owner).collect();
+            if at_owner.is_empty() {
+                resp.with_rcode(Rcode::NXDomain);
+                break;
+            }
+            let matching: Vec<&&ResourceRecord> = at_owner
+                .iter()
+                .filter(|rr| rr.typ() == ques.typ())
+                .collect();
+            if !matching.is_empty() {
+                for rr in matching {
+                    resp.with_answer((*rr).clone());
+                }
+                return resp;
+            }
+            match at_owner.iter().find(|rr| rr.typ() == rtype::CNAME) {
+                Some(rr) => {
+                    resp.with_answer((*rr).clone());
+                    owner = DomainName::decode(rr.rdata(), 0).unwrap().0;
+                    if !owner.is_subdomain_of(apex) {
+                        return resp;
+                    }
+                }
+                None => break,
+            }
+        }
+        if !resp.answers().is_empty() {
+            return resp;
+        }
+        resp.with_authority(
+            records
+                .iter()
+                .find(|rr| rr.typ() == rtype::SOA)
+                .unwrap()
+                .clone(),
+        );
+        return resp;
+    }
+
+    // root, com, example.com, net with example.net, glueless.com, whose name server has
+    // no glue, and v6only.com, whose name server has an IPv6 address only.
+    fn hierarchy() -> Recursor {
+        let probe = UdpSocket::bind("127.0.1.1:0").unwrap();
+        let root = probe.local_addr().unwrap();
+        drop(probe);
+        let port = root.port();
+        let at = |last: u8| SocketAddr::from(([127, 0, 1, last], port));
+
+        serve(
+            root,
+            ".",
+            vec![
+                soa("."),
+                ns("com.", "ns.com."),
+                a("ns.com.", [127, 0, 1, 2]),
+                ns("net.", "ns.net."),
+                a("ns.net.", [127, 0, 1, 4]),
+            ],
+        );
+        serve(
+            at(2),
+            "com.",
+            vec![
+                soa("com."),
+                ns("example.com.", "ns1.example.com."),
+                a("ns1.example.com.", [127, 0, 1, 3]),
+                ns("glueless.com.", "ns.example.net."),
+                ns("v6only.com.", "ns6.example.net."),
+            ],
+        );
+        serve(
+            at(3),
+            "example.com.",
+            vec![
+                soa("example.com."),
+                a("www.example.com.", [192, 0, 2, 1]),
+                cname("alias.example.com.", "www.example.net."),
+                cname("loop1.example.com.", "loop2.example.com."),
+                cname("loop2.example.com.", "loop1.example.com."),
+            ],
+        );
+        serve(
+            at(4),
+            "net.",
+            vec![
+                soa("net."),
+                a("www.example.net.", [192, 0, 2, 2]),
+                a("ns.example.net.", [127, 0, 1, 5]),
+                aaaa("ns6.example.net.", Ipv6Addr::LOCALHOST),
+            ],
+        );
+        serve(
+            at(5),
+            "glueless.com.",
+            vec![soa("glueless.com."), a("www.glueless.com.", [192, 0, 2, 3])],
+        );
+        serve(
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+            "v6only.com.",
+            vec![soa("v6only.com."), a("www.v6only.com.", [192, 0, 2, 4])],
+        );
+
+        let mut recursor = Recursor::new(vec![root]);
+        recursor
+            .with_port(port)
+            .with_timeout(Duration::from_millis(500));
+        return recursor;
+    }
+
+    fn query(qname: &str, qtype: u16) -> DNS {
+        let mut query = DNS::new();
+        query.head_mut().with_id(77).with_rd(1);
+        query.with_question(Question::from_parts(name(qname), qtype, rtype::CLASS_IN));
+        return query;
+    }
+
+    fn rdata(resp: &DNS) -> Vec<Vec<u8>> {
+        return resp
+            .answers()
+            .iter()
+            .map(|rr| rr.rdata().to_vec())
+            .collect();
+    }
+
+    #[test]
+    pub fn test_recursive_resolve() {
+        let recursor = hierarchy();
+
+        // referrals with glue
+        let resp = recursor.resolve(&query("www.example.com.", rtype::A));
+        assert_eq!(77, resp.head().id());
+        assert_eq!(1, resp.head().ra());
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(vec![vec![192, 0, 2, 1]], rdata(&resp));
+
+        // a CNAME into another zone
+        let resp = recursor.resolve(&query("alias.example.com.", rtype::A));
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(2, resp.answers().len());
+        assert_eq!(rtype::CNAME, resp.answers().iter().next().unwrap().typ());
+        assert_eq!(vec![192, 0, 2, 2], rdata(&resp)[1]);
+
+        // the name server address is looked up first
+        let resp = recursor.resolve(&query("www.glueless.com.", rtype::A));
+        assert_eq!(vec![vec![192, 0, 2, 3]], rdata(&resp));
+        // and its IPv6 address when it has no IPv4 one
+        let resp = recursor.resolve(&query("www.v6only.com.", rtype::A));
+        assert_eq!(vec![vec![192, 0, 2, 4]], rdata(&resp));
+    }
+
+    #[test]
+    pub fn test_recursive_real_wire_referral() {
+        let recursor = hierarchy();
+        let port = recursor.port();
+
+        // A root server answering like a.root-servers.net does: flags 0x8000 (qr only),
+        // compressed names, a glue record and an OPT record. The glue points at the local
+        // com server.
+        let socket = UdpSocket::bind(("127.0.1.6", port)).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        thread::spawn(move || {
+            let mut buf = [0_u8; 512];
+            while let Ok((_, source)) = socket.recv_from(&mut buf) {
+                let mut reply = vec![
+                    // id, qr, NOERROR, qdcount 1, nscount 1, arcount 2
+                    buf[0], buf[1], 0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x02,
+                ];
+                // www example com, type A, class IN
+                reply.extend_from_slice(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+                reply.extend_from_slice(&[
+                    // com (offset 24), NS, IN, ttl 172800, ns (offset 45) + com
+                    0xc0, 0x18, 0x00, 0x02, 0x00, 0x01, 0x00, 0x02, 0xa3, 0x00, 0x00, 0x05, 0x02,
+                    0x6e, 0x73, 0xc0, 0x18, // ns com, A, IN, ttl 172800, 127.0.1.2
+                    0xc0, 0x2d, 0x00, 0x01, 0x00, 0x01, 0x00, 0x02, 0xa3, 0x00, 0x00, 0x04, 0x7f,
+                    0x00, 0x01, 0x02, // OPT, payload 4096
+                    0x00, 0x00, 0x29, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                ]);
+                socket.send_to(&reply, source).unwrap();
+            }
+        });
+
+        let mut recursor = Recursor::new(vec![SocketAddr::from(([127, 0, 1, 6], port))]);
+        recursor
+            .with_port(port)
+            .with_timeout(Duration::from_millis(500));
+        let resp = recursor.resolve(&query("www.example.com.", rtype::A));
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(vec![vec![192, 0, 2, 1]], rdata(&resp));
+    }
+
+    #[test]
+    pub fn test_recursive_negative() {
+        let recursor = hierarchy();
+
+        let resp = recursor.resolve(&query("missing.example.com.", rtype::A));
+        assert_eq!(Rcode::NXDomain, resp.rcode());
+        assert!(resp.answers().is_empty());
+        assert_eq!(rtype::SOA, resp.authorities().iter().next().unwrap().typ());
+
+        let resp = recursor.resolve(&query("www.example.com.", rtype::AAAA));
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert!(resp.answers().is_empty());
+        assert_eq!(rtype::SOA, resp.authorities().iter().next().unwrap().typ());
+    }
+
+    #[test]
+    pub fn test_recursive_limits() {
+        let mut recursor = hierarchy();
+
+        let resp = recursor.resolve(&query("loop1.example.com.", rtype::A));
+        assert_eq!(Rcode::ServFail, resp.rcode());
+
+        // root, com and example.com are needed
+        recursor.with_max_queries(2);
+        let resp = recursor.resolve(&query("www.example.com.", rtype::A));
+        assert_eq!(Rcode::ServFail, resp.rcode());
+        recursor.with_max_queries(3);
+        let resp = recursor.resolve(&query("www.example.com.", rtype::A));
+        assert_eq!(Rcode::NoError, resp.rcode());
+
+        // the glueless name server needs a lookup of its own
+        recursor
+            .with_max_queries(DEFAULT_MAX_QUERIES)
+            .with_max_depth(0);
+        let resp = recursor.resolve(&query("www.glueless.com.", rtype::A));
+        assert_eq!(Rcode::ServFail, resp.rcode());
+    }
+
+    #[test]
+    pub fn test_recursive_cache() {
+        let mut recursor = hierarchy();
+        let cache = Arc::new(Cache::new(1 << 20));
+        recursor.with_cache(cache.clone());
+
+        let resp = recursor.resolve(&query("www.example.com.", rtype::A));
+        assert_eq!(vec![vec![192, 0, 2, 1]], rdata(&resp));
+        let www = cache.get(
+            &name("www.example.com."),
+            rtype::A,
+            rtype::CLASS_IN,
+            Trust::AuthAnswer,
+        );
+        assert!(www.is_some());
+
+        // the delegation to example.com is cached, its server is asked directly
+        recursor.with_max_queries(1);
+        let resp = recursor.resolve(&query("missing.example.com.", rtype::A));
+        assert_eq!(Rcode::NXDomain, resp.rcode());
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Error;
+use std::{fmt::Display, net::IpAddr, str::FromStr};
+
+// An address prefix such as `10.0.0.0/8` or `2001:db8::/32`. A bare address is a /32 or
+// /128.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self, Error> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(Error::msg(format!(
+                "prefix length {} is larger than {}",
+                prefix, max
+            )));
+        }
+        return Ok(Network { addr, prefix });
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        return self.addr;
+    }
+
+    pub fn prefix(&self) -> u8 {
+        return self.prefix;
+    }
+
+    // IPv4-mapped IPv6 addresses match IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, ip) = match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (net.to_bits() as u128, ip.to_bits() as u128),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (net.to_bits(), ip.to_bits()),
+            _ => return false,
+        };
+        let bits = if self.addr.is_ipv4() { 32 } else { 128 };
+        let shift = bits - self.prefix as u32;
+        if shift >= 128 {
+            return true;
+        }
+        return net >> shift == ip >> shift;
+    }
+}
+
+impl FromStr for Network {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |e: &dyn Display| Error::msg(format!("invalid network {:?}: {}", s, e));
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|e| invalid(&e))?;
+        let prefix = match prefix {
+            Some(prefix) => u8::from_str(prefix).map_err(|e| invalid(&e))?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+        return Network::new(addr, prefix).map_err(|e| invalid(&e));
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}/{}", self.addr, self.prefix);
+    }
+}
+
+// Which clients may query the server. Denied networks win over allowed ones, an empty
+// allow list allows everybody.
+#[derive(Debug, Clone, Default)]
+pub struct Acl {
+    allow: Vec<Network>,
+    deny: Vec<Network>,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn allow(&self) -> &[Network] {
+        return &self.allow;
+    }
+
+    pub fn deny(&self) -> &[Network] {
+        return &self.deny;
+    }
+
+    pub fn with_allow(&mut self, net: Network) -> &mut Self {
+        self.allow.push(net);
+        return self;
+    }
+
+    pub fn with_deny(&mut self, net: Network) -> &mut Self {
+        self.deny.push(net);
+        return self;
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+        return self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        return s.parse().unwrap();
+    }
+
+    #[test]
+    pub fn test_network() {
+        let net: Network = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(ip("10.1.255.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(net.contains(ip("::ffff:10.1.0.1")));
+        assert!(!net.contains(ip("::1")));
+        assert_eq!("10.1.0.0/16", net.to_string());
+
+        assert!("0.0.0.0/0"
+            .parse::<Network>()
+            .unwrap()
+            .contains(ip("8.8.8.8")));
+        assert!("::/0"
+            .parse::<Network>()
+            .unwrap()
+            .contains(ip("2001:db8::1")));
+        let host: Network = "2001:db8::1".parse().unwrap();
+        assert_eq!(128, host.prefix());
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+
+        assert!("10.0.0.0/33".parse::<Network>().is_err());
+        assert!("10.0.0/8".parse::<Network>().is_err());
+        assert!("10.0.0.0/x".parse::<Network>().is_err());
+    }
+
+    #[test]
+    pub fn test_acl() {
+        let mut acl = Acl::new();
+        assert!(acl.allows(ip("192.0.2.1")));
+
+        acl.with_allow("127.0.0.0/8".parse().unwrap())
+            .with_allow("::1".parse().unwrap())
+            .with_deny("127.0.0.2".parse().unwrap());
+        assert!(acl.allows(ip("127.0.0.1")));
+        assert!(acl.allows(ip("::1")));
+        assert!(!acl.allows(ip("127.0.0.2")));
+        assert!(!acl.allows(ip("192.0.2.1")));
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::Error;
+use std::{fmt::Display, net::SocketAddr, str::FromStr};
+
+use super::Transport;
+
+// Address the server listens on when nothing is configured.
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:2053";
+
+// One address and the transport served on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Listener {
+    addr: SocketAddr,
+    transport: Transport,
+}
+
+impl Listener {
+    pub fn new(addr: SocketAddr, transport: Transport) -> Self {
+        return Listener { addr, transport };
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    pub fn transport(&self) -> Transport {
+        return self.transport;
+    }
+
+    // Parse `udp:ADDR`, `tcp:ADDR` or a bare `ADDR` which stands for both transports.
+    // IPv6 addresses are written in brackets, e.g. `udp:[::]:53`.
+    pub fn parse(spec: &str) -> Result<Vec<Listener>, Error> {
+        let (transports, addr) = match spec.split_once(':') {
+            Some(("udp", addr)) => (vec![Transport::Udp], addr),
+            Some(("tcp", addr)) => (vec![Transport::Tcp], addr),
+            _ => (vec![Transport::Udp, Transport::Tcp], spec),
+        };
+        let addr = SocketAddr::from_str(addr)
+            .map_err(|e| Error::msg(format!("invalid listen address {:?}: {}", spec, e)))?;
+
+        return Ok(transports
+            .into_iter()
+            .map(|transport| Listener::new(addr, transport))
+            .collect());
+    }
+
+    // UDP and TCP on `DEFAULT_LISTEN_ADDR`.
+    pub fn defaults() -> Vec<Listener> {
+        return Listener::parse(DEFAULT_LISTEN_ADDR).expect("valid default address");
+    }
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let transport = match self.transport {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+        };
+        return write!(f, "{}:{}", transport, self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_listener_parse() {
+        let listeners = Listener::parse("udp:[::]:53").unwrap();
+        assert_eq!(1, listeners.len());
+        assert_eq!(Transport::Udp, listeners[0].transport());
+        assert_eq!(
+            "[::]:53".parse::<SocketAddr>().unwrap(),
+            listeners[0].addr()
+        );
+        assert_eq!("udp:[::]:53", listeners[0].to_string());
+
+        let listeners = Listener::parse("tcp:0.0.0.0:2053").unwrap();
+        assert_eq!(vec!["tcp:0.0.0.0:2053"], to_strings(&listeners));
+
+        // no transport means both
+        let listeners = Listener::parse("[fe80::1%2]:53").unwrap();
+        assert_eq!(
+            vec!["udp:[fe80::1%2]:53", "tcp:[fe80::1%2]:53"],
+            to_strings(&listeners)
+        );
+        assert_eq!(
+            vec!["udp:127.0.0.1:2053", "tcp:127.0.0.1:2053"],
+            to_strings(&Listener::defaults())
+        );
+
+        assert!(Listener::parse("udp:127.0.0.1").is_err());
+        assert!(Listener::parse("sctp:127.0.0.1:53").is_err());
+        assert!(Listener::parse("::1:53").is_err());
+    }
+
+    fn to_strings(listeners: &[Listener]) -> Vec<String> {
+        return listeners.iter().map(|l| l.to_string()).collect();
+    }
+}
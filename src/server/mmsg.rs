@@ -0,0 +1,222 @@
+// Batched datagram I/O with recvmmsg/sendmmsg, one syscall for many packets.
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    os::raw::c_void,
+};
+
+use super::sys::{self, Control, Iovec, Mmsghdr, PktInfo, SockaddrStorage};
+
+// An outgoing datagram: payload, destination and the local address to send it from.
+pub type Datagram = (Vec<u8>, SocketAddr, Option<PktInfo>);
+
+// Preallocated buffers for up to `batch_size` datagrams, reused by every `recv`.
+pub struct RecvBatch {
+    packet_size: usize,
+    bufs: Vec<u8>,
+    addrs: Vec<SockaddrStorage>,
+    controls: Vec<Control>,
+    iovecs: Vec<Iovec>,
+    headers: Vec<Mmsghdr>,
+    received: usize,
+}
+
+impl RecvBatch {
+    pub fn new(batch_size: usize, packet_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        return RecvBatch {
+            packet_size,
+            bufs: vec![0; batch_size * packet_size],
+            addrs: vec![SockaddrStorage::zeroed(); batch_size],
+            controls: vec![Control::default(); batch_size],
+            iovecs: Vec::with_capacity(batch_size),
+            headers: Vec::with_capacity(batch_size),
+            received: 0,
+        };
+    }
+
+    // Block until at least one datagram arrives, then take whatever else is queued up to
+    // the capacity. Returns the number of datagrams received.
+    pub fn recv(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.prepare();
+        self.received = sys::recv_mmsg(socket, &mut self.headers, sys::MSG_WAITFORONE)?;
+        return Ok(self.received);
+    }
+
+    // Like `recv` but with recvmsg into the first slot only, for kernels without
+    // recvmmsg. The packet info is kept all the same.
+    pub fn recv_one(&mut self, socket: &UdpSocket) -> io::Result<usize> {
+        self.prepare();
+        let len = sys::recv_msg(socket, &mut self.headers[0].hdr)?;
+        self.headers[0].len = len as u32;
+        self.received = 1;
+        return Ok(self.received);
+    }
+
+    fn prepare(&mut self) {
+        // the headers point into our own buffers, rebuild them so they are never stale
+        self.iovecs.clear();
+        for buf in self.bufs.chunks_exact_mut(self.packet_size) {
+            self.iovecs.push(Iovec {
+                base: buf.as_mut_ptr() as *mut c_void,
+                len: buf.len(),
+            });
+        }
+        self.headers.clear();
+        let namelen = std::mem::size_of::<SockaddrStorage>() as u32;
+        let iter = self.addrs.iter_mut().zip(self.iovecs.iter_mut());
+        for ((addr, iov), control) in iter.zip(self.controls.iter_mut()) {
+            let len = control.len();
+            self.headers
+                .push(Mmsghdr::new(addr, namelen, iov, Some((control, len))));
+        }
+        self.received = 0;
+    }
+
+    // Datagram `i` of the last `recv`, its source and, when the socket asked for it, the
+    // address it was sent to.
+    pub fn packet(&self, i: usize) -> Option<(&[u8], SocketAddr, Option<PktInfo>)> {
+        if i >= self.received {
+            return None;
+        }
+        let start = i * self.packet_size;
+        let len = (self.headers[i].len as usize).min(self.packet_size);
+        let source = sys::from_storage(&self.addrs[i])?;
+        let pktinfo = self.controls[i].pktinfo(self.headers[i].hdr.controllen);
+
+        return Some((&self.bufs[start..start + len], source, pktinfo));
+    }
+}
+
+// Send the datagrams in one call. Returns how many went out, which is fewer than all
+// when the kernel stops early; an error means the first datagram failed.
+pub fn send_batch(socket: &UdpSocket, packets: &[Datagram]) -> io::Result<usize> {
+    if packets.is_empty() {
+        return Ok(0);
+    }
+
+    let mut addrs: Vec<(SockaddrStorage, u32)> = packets
+        .iter()
+        .map(|(_, dest, _)| sys::to_storage(dest))
+        .collect();
+    let mut controls: Vec<Option<(Control, usize)>> = packets
+        .iter()
+        .map(|(_, _, pktinfo)| pktinfo.as_ref().map(Control::with_pktinfo))
+        .collect();
+    let mut iovecs: Vec<Iovec> = packets
+        .iter()
+        .map(|(data, _, _)| Iovec {
+            base: data.as_ptr() as *mut c_void,
+            len: data.len(),
+        })
+        .collect();
+    let mut headers: Vec<Mmsghdr> = addrs
+        .iter_mut()
+        .zip(iovecs.iter_mut())
+        .zip(controls.iter_mut())
+        .map(|(((addr, len), iov), control)| {
+            let control = control.as_mut().map(|(control, len)| (control, *len));
+            Mmsghdr::new(addr, *len, iov, control)
+        })
+        .collect();
+
+    return sys::send_mmsg(socket, &mut headers);
+}
+
+// Send one datagram with sendmsg, from the local address of its packet info like
+// `send_batch` does. Returns the number of bytes sent.
+pub fn send_one(socket: &UdpSocket, packet: &Datagram) -> io::Result<usize> {
+    let (data, dest, pktinfo) = packet;
+    let (mut addr, addrlen) = sys::to_storage(dest);
+    let mut control = pktinfo.as_ref().map(Control::with_pktinfo);
+    let mut iovec = Iovec {
+        base: data.as_ptr() as *mut c_void,
+        len: data.len(),
+    };
+    let control = control.as_mut().map(|(control, len)| (control, *len));
+    let header = Mmsghdr::new(&mut addr, addrlen, &mut iovec, control);
+
+    return sys::send_msg(socket, &header.hdr);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::IpAddr, time::Duration};
+
+    #[test]
+    pub fn test_recv_and_send_batch() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        for data in [&b"one"[..], b"two", b"three"] {
+            client.send_to(data, addr).unwrap();
+        }
+        // loopback delivers synchronously, all three are queued already
+        let mut batch = RecvBatch::new(8, 4);
+        assert_eq!(3, batch.recv(&server).unwrap());
+        let client_addr = client.local_addr().unwrap();
+        assert_eq!(Some((&b"one"[..], client_addr, None)), batch.packet(0));
+        // cut to the packet size
+        assert_eq!(Some((&b"thre"[..], client_addr, None)), batch.packet(2));
+        assert_eq!(None, batch.packet(3));
+
+        let packets: Vec<Datagram> = (0..3_u8)
+            .map(|i| (vec![i; i as usize + 1], client_addr, None))
+            .collect();
+        assert_eq!(3, send_batch(&server, &packets).unwrap());
+        let mut buf = [0; 8];
+        for i in 0..3_u8 {
+            let size = client.recv(&mut buf).unwrap();
+            assert_eq!(vec![i; i as usize + 1], buf[..size].to_vec());
+        }
+        assert_eq!(0, send_batch(&server, &[]).unwrap());
+    }
+
+    #[test]
+    pub fn test_pktinfo_on_wildcard_socket() {
+        let server = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        sys::enable_pktinfo(&server, &addr).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // sent to 127.0.0.2, the reply must come from there and not from 127.0.0.1
+        client
+            .send_to(b"query", ("127.0.0.2", addr.port()))
+            .unwrap();
+        let mut batch = RecvBatch::new(1, 16);
+        assert_eq!(1, batch.recv(&server).unwrap());
+        let (_, source, pktinfo) = batch.packet(0).unwrap();
+        let pktinfo = pktinfo.unwrap();
+        assert_eq!("127.0.0.2".parse::<IpAddr>().unwrap(), pktinfo.local);
+
+        send_batch(&server, &[(b"reply".to_vec(), source, Some(pktinfo))]).unwrap();
+        let mut buf = [0; 16];
+        let (size, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(b"reply", &buf[..size]);
+        assert_eq!(SocketAddr::from(([127, 0, 0, 2], addr.port())), from);
+
+        // the same for a datagram received and sent on its own
+        client
+            .send_to(b"query", ("127.0.0.2", addr.port()))
+            .unwrap();
+        assert_eq!(1, batch.recv_one(&server).unwrap());
+        let (data, source, pktinfo) = batch.packet(0).unwrap();
+        assert_eq!(b"query", data);
+        let pktinfo = pktinfo.unwrap();
+        assert_eq!("127.0.0.2".parse::<IpAddr>().unwrap(), pktinfo.local);
+
+        let reply = (b"again".to_vec(), source, Some(pktinfo));
+        assert_eq!(5, send_one(&server, &reply).unwrap());
+        let (size, from) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(b"again", &buf[..size]);
+        assert_eq!(SocketAddr::from(([127, 0, 0, 2], addr.port())), from);
+    }
+}
@@ -0,0 +1,391 @@
+pub mod acl;
+pub mod listener;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+))]
+mod mmsg;
+pub mod pool;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+))]
+mod sys;
+pub mod tcp;
+pub mod udp;
+
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    dns::{dns::UDP_MAX_SIZE, rcode::Rcode, DNS},
+    resolver::Resolver,
+    zone::Catalog,
+};
+use acl::Acl;
+
+// Counters shared by all the listeners.
+#[derive(Debug, Default)]
+pub struct Stats {
+    received: AtomicU64,
+    answered: AtomicU64,
+    formerr: AtomicU64,
+    refused: AtomicU64,
+    dropped: AtomicU64,
+    recv_errors: AtomicU64,
+    send_errors: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn received(&self) -> u64 {
+        return self.received.load(Ordering::Relaxed);
+    }
+
+    pub fn answered(&self) -> u64 {
+        return self.answered.load(Ordering::Relaxed);
+    }
+
+    // Malformed queries answered with FORMERR.
+    pub fn formerr(&self) -> u64 {
+        return self.formerr.load(Ordering::Relaxed);
+    }
+
+    // Queries from clients the ACL does not allow.
+    pub fn refused(&self) -> u64 {
+        return self.refused.load(Ordering::Relaxed);
+    }
+
+    // Packets that were not answered at all.
+    pub fn dropped(&self) -> u64 {
+        return self.dropped.load(Ordering::Relaxed);
+    }
+
+    pub fn recv_errors(&self) -> u64 {
+        return self.recv_errors.load(Ordering::Relaxed);
+    }
+
+    pub fn send_errors(&self) -> u64 {
+        return self.send_errors.load(Ordering::Relaxed);
+    }
+
+    pub(crate) fn inc_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn inc_recv_errors(&self) {
+        self.recv_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn inc_send_errors(&self) {
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// State shared by all the listeners.
+#[derive(Debug, Default)]
+pub struct Context {
+    stats: Stats,
+    acl: Acl,
+    resolver: Option<Arc<dyn Resolver>>,
+    zones: Catalog,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    pub fn stats(&self) -> &Stats {
+        return &self.stats;
+    }
+
+    pub fn acl(&self) -> &Acl {
+        return &self.acl;
+    }
+
+    pub fn with_acl(&mut self, acl: Acl) -> &mut Self {
+        self.acl = acl;
+        return self;
+    }
+
+    // Queries are passed to `resolver` instead of being answered with an empty response.
+    pub fn with_resolver(&mut self, resolver: Arc<dyn Resolver>) -> &mut Self {
+        self.resolver = Some(resolver);
+        return self;
+    }
+
+    // Queries about names in `zones` are answered authoritatively from them.
+    pub fn with_zones(&mut self, zones: Catalog) -> &mut Self {
+        self.zones = zones;
+        return self;
+    }
+
+    // Answer a packet from `source`: clients the ACL rejects get REFUSED, everybody else
+    // is served from the zones, by the resolver, or by `process` without either.
+    pub fn handle(&self, raw: &[u8], transport: Transport, source: IpAddr) -> Option<Vec<u8>> {
+        if !self.acl.allows(source) {
+            return refuse(raw, &self.stats);
+        }
+        return process_with(raw, transport, &self.stats, |query| {
+            if let Some(mut resp) = self.zones.answer(query) {
+                if self.resolver.is_some() {
+                    resp.head_mut().with_ra(1);
+                }
+                return resp;
+            }
+            return match &self.resolver {
+                Some(resolver) => resolver.resolve(query),
+                None => DNS::response_for(query),
+            };
+        });
+    }
+}
+
+// The transport a query arrived on, decides the size budget of the reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl Transport {
+    fn limit_for(&self, query: &DNS) -> usize {
+        return match self {
+            Transport::Udp => DNS::udp_limit_for(query),
+            Transport::Tcp => u16::MAX as usize,
+        };
+    }
+}
+
+// Turn one received packet into the bytes of the reply, if any. Over UDP the reply is
+// truncated to the size budget of the query.
+// Never panics on malformed input: queries that fail strict decoding get a FORMERR when
+// their ID is readable, anything else (including responses sent to us) is dropped.
+pub fn process(raw: &[u8], transport: Transport, stats: &Stats) -> Option<Vec<u8>> {
+    return process_with(raw, transport, stats, DNS::response_for);
+}
+
+// `process` with the reply to well-formed queries built by `answer`.
+pub fn process_with<F>(
+    raw: &[u8],
+    transport: Transport,
+    stats: &Stats,
+    answer: F,
+) -> Option<Vec<u8>>
+where
+    F: Fn(&DNS) -> DNS,
+{
+    stats.received.fetch_add(1, Ordering::Relaxed);
+
+    let (resp, limit) = match DNS::decode_strict(raw) {
+        Ok(query) if query.head().qr() == 1 => (None, UDP_MAX_SIZE),
+        Ok(query) => (Some(answer(&query)), transport.limit_for(&query)),
+        Err(_) => {
+            let formerr = DNS::formerr_for(raw);
+            if formerr.is_some() {
+                stats.formerr.fetch_add(1, Ordering::Relaxed);
+            }
+            (formerr, UDP_MAX_SIZE)
+        }
+    };
+
+    match resp {
+        Some(resp) => {
+            stats.answered.fetch_add(1, Ordering::Relaxed);
+            return Some(resp.encode_with_limit(limit));
+        }
+        None => {
+            stats.inc_dropped();
+            return None;
+        }
+    }
+}
+
+// REFUSED for a well-formed query, anything else is dropped. Nothing beyond the header
+// and question is looked at.
+fn refuse(raw: &[u8], stats: &Stats) -> Option<Vec<u8>> {
+    stats.received.fetch_add(1, Ordering::Relaxed);
+
+    match DNS::decode_strict(raw) {
+        Ok(query) if query.head().qr() == 0 => {
+            stats.refused.fetch_add(1, Ordering::Relaxed);
+            let mut resp = DNS::response_for(&query);
+            resp.with_rcode(Rcode::Refused);
+            return Some(resp.encode_with_limit(UDP_MAX_SIZE));
+        }
+        _ => {
+            stats.inc_dropped();
+            return None;
+        }
+    }
+}
+
+// Catch SIGINT and SIGTERM so the process can clean up before exiting, see `terminating`.
+// Signals are not caught on other systems.
+pub fn catch_termination() -> std::io::Result<()> {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    ))]
+    return sys::catch_termination();
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    )))]
+    return Ok(());
+}
+
+// Whether SIGINT or SIGTERM arrived since `catch_termination`.
+pub fn terminating() -> bool {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    ))]
+    return sys::terminating();
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    )))]
+    return false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUERY: [u8; 28] = [
+        // id 1234, rd, qdcount 1
+        0x04, 0xd2, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // google com, type A, class IN
+        0x06, 0x67, 0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x01, 0x00,
+        0x01,
+    ];
+
+    #[test]
+    pub fn test_process_query() {
+        let stats = Stats::new();
+        let resp = DNS::decode(&process(&QUERY, Transport::Udp, &stats).unwrap()).unwrap();
+        assert_eq!(1234, resp.head().id());
+        assert_eq!(1, resp.head().qr());
+        assert_eq!(1, resp.questions().len());
+        assert_eq!(1, stats.received());
+        assert_eq!(1, stats.answered());
+    }
+
+    #[test]
+    pub fn test_process_malformed() {
+        let stats = Stats::new();
+        // every prefix of a query must be handled without panicking
+        for len in 0..QUERY.len() {
+            if let Some(raw) = process(&QUERY[..len], Transport::Udp, &stats) {
+                let resp = DNS::decode(&raw).unwrap();
+                assert_eq!(1234, resp.head().id());
+                assert_eq!(Rcode::FormErr, resp.rcode());
+            }
+        }
+        assert_eq!(QUERY.len() as u64, stats.received());
+        // len 0 and 1 have no readable ID
+        assert_eq!(2, stats.dropped());
+        assert_eq!(QUERY.len() as u64 - 2, stats.formerr());
+
+        // a pointer loop in the question
+        let mut raw = QUERY[..12].to_vec();
+        raw.extend_from_slice(&[0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01]);
+        let resp = DNS::decode(&process(&raw, Transport::Udp, &stats).unwrap()).unwrap();
+        assert_eq!(Rcode::FormErr, resp.rcode());
+    }
+
+    #[test]
+    pub fn test_process_response_dropped() {
+        let stats = Stats::new();
+        let mut raw = QUERY.to_vec();
+        raw[2] |= 0x80;
+        assert!(process(&raw, Transport::Udp, &stats).is_none());
+        assert!(process(&raw[..5], Transport::Udp, &stats).is_none());
+        assert_eq!(2, stats.dropped());
+    }
+
+    #[test]
+    pub fn test_handle_acl() {
+        let mut ctx = Context::new();
+        let mut acl = Acl::new();
+        acl.with_allow("127.0.0.0/8".parse().unwrap());
+        ctx.with_acl(acl);
+
+        let allowed = ctx.handle(&QUERY, Transport::Udp, "127.0.0.1".parse().unwrap());
+        assert_eq!(
+            Rcode::NoError,
+            DNS::decode(&allowed.unwrap()).unwrap().rcode()
+        );
+
+        let source = "192.0.2.1".parse().unwrap();
+        let refused = DNS::decode(&ctx.handle(&QUERY, Transport::Udp, source).unwrap()).unwrap();
+        assert_eq!(1234, refused.head().id());
+        assert_eq!(Rcode::Refused, refused.rcode());
+        assert_eq!(1, refused.questions().len());
+        // garbage from a refused client is not answered at all
+        assert!(ctx.handle(&QUERY[..5], Transport::Udp, source).is_none());
+        assert_eq!(1, ctx.stats().refused());
+        assert_eq!(1, ctx.stats().dropped());
+        assert_eq!(3, ctx.stats().received());
+    }
+
+    #[derive(Debug)]
+    struct Failing;
+
+    impl Resolver for Failing {
+        fn resolve(&self, query: &DNS) -> DNS {
+            let mut resp = DNS::response_for(query);
+            resp.with_rcode(Rcode::ServFail);
+            return resp;
+        }
+    }
+
+    #[test]
+    pub fn test_handle_resolver() {
+        let mut ctx = Context::new();
+        ctx.with_resolver(Arc::new(Failing));
+
+        let source = "127.0.0.1".parse().unwrap();
+        let resp = DNS::decode(&ctx.handle(&QUERY, Transport::Udp, source).unwrap()).unwrap();
+        assert_eq!(1234, resp.head().id());
+        assert_eq!(Rcode::ServFail, resp.rcode());
+        // malformed queries never reach the resolver
+        let resp = DNS::decode(&ctx.handle(&QUERY[..20], Transport::Udp, source).unwrap()).unwrap();
+        assert_eq!(Rcode::FormErr, resp.rcode());
+    }
+
+    #[test]
+    pub fn test_handle_zones() {
+        let origin: crate::dns::DomainName = "google.com.".parse().unwrap();
+        let text = "$TTL 60\n@ SOA ns1 host 1 2 3 4 5\n@ NS ns1\n@ A 192.0.2.1";
+        let records = crate::zone::parser::parse(text, &origin).unwrap();
+        let mut zones = Catalog::new();
+        zones.with_zone(crate::zone::Zone::new(origin, records).unwrap());
+        let mut ctx = Context::new();
+        ctx.with_resolver(Arc::new(Failing)).with_zones(zones);
+
+        let source = "127.0.0.1".parse().unwrap();
+        let resp = DNS::decode(&ctx.handle(&QUERY, Transport::Udp, source).unwrap()).unwrap();
+        assert_eq!(1234, resp.head().id());
+        assert_eq!(1, resp.head().aa());
+        assert_eq!(1, resp.head().ra());
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(1, resp.answers().len());
+    }
+}
@@ -0,0 +1,183 @@
+use anyhow::Error;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+// Queued jobs across all the listeners before receiving blocks.
+pub const DEFAULT_QUEUE_SIZE: usize = 1024;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// Fixed set of worker threads fed through a bounded queue. When the queue is full
+// `submit` blocks the caller, which pushes back on the receiving loops.
+pub struct WorkerPool {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    waits: AtomicU64,
+}
+
+impl WorkerPool {
+    pub fn new(workers: usize, queue_size: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_size.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..workers.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("dns-worker-{}", i))
+                    .spawn(move || work(&receiver))
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+
+        return Self {
+            sender: Some(sender),
+            workers,
+            waits: AtomicU64::new(0),
+        };
+    }
+
+    // One worker per available core.
+    pub fn with_default_size(queue_size: usize) -> Self {
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        return Self::new(workers, queue_size);
+    }
+
+    pub fn size(&self) -> usize {
+        return self.workers.len();
+    }
+
+    // Number of submissions that found the queue full and had to wait.
+    pub fn waits(&self) -> u64 {
+        return self.waits.load(Ordering::Relaxed);
+    }
+
+    // Queue `job`, blocking while the queue is full.
+    pub fn submit<F>(&self, job: F) -> Result<(), Error>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self
+            .sender
+            .as_ref()
+            .ok_or_else(|| Error::msg("the worker pool is shut down"))?;
+        match sender.try_send(Box::new(job)) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(job)) => {
+                self.waits.fetch_add(1, Ordering::Relaxed);
+                return sender
+                    .send(job)
+                    .map_err(|_| Error::msg("the worker pool is shut down"));
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                return Err(Error::msg("the worker pool is shut down"));
+            }
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    // Let the workers drain the queue and wait for them.
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = {
+            let receiver = receiver.lock().unwrap_or_else(|e| e.into_inner());
+            receiver.recv()
+        };
+        match job {
+            // a panicking job must not take the worker down with it
+            Ok(job) => {
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    crate::error!("A worker job panicked");
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{atomic::AtomicUsize, Barrier},
+        time::Duration,
+    };
+
+    #[test]
+    pub fn test_pool_runs_jobs_concurrently() {
+        let pool = WorkerPool::new(4, 16);
+        assert_eq!(4, pool.size());
+
+        // all four jobs must be running at the same time to pass the barrier
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = barrier.clone();
+            pool.submit(move || {
+                barrier.wait();
+            })
+            .unwrap();
+        }
+        barrier.wait();
+    }
+
+    #[test]
+    pub fn test_pool_drains_on_drop() {
+        let done = Arc::new(AtomicUsize::new(0));
+        {
+            let pool = WorkerPool::new(2, 4);
+            for _ in 0..50 {
+                let done = done.clone();
+                pool.submit(move || {
+                    done.fetch_add(1, Ordering::SeqCst);
+                })
+                .unwrap();
+            }
+        }
+        assert_eq!(50, done.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_pool_backpressure() {
+        let pool = WorkerPool::new(1, 1);
+        let (release, wait) = mpsc::channel::<()>();
+        pool.submit(move || {
+            let _ = wait.recv_timeout(Duration::from_secs(5));
+        })
+        .unwrap();
+        // the worker is busy; one job fits in the queue, the next one has to wait
+        thread::sleep(Duration::from_millis(50));
+        pool.submit(|| {}).unwrap();
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            release.send(()).unwrap();
+        });
+        pool.submit(|| {}).unwrap();
+        releaser.join().unwrap();
+        assert_eq!(1, pool.waits());
+    }
+
+    #[test]
+    pub fn test_pool_survives_panic() {
+        let pool = WorkerPool::new(1, 4);
+        pool.submit(|| panic!("job failure")).unwrap();
+        let (tx, rx) = mpsc::channel();
+        pool.submit(move || tx.send(1).unwrap()).unwrap();
+        assert_eq!(1, rx.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+}
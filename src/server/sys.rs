@@ -0,0 +1,469 @@
+// Raw Linux socket calls that std does not expose. Only the handful of constants and
+// structs this crate needs are declared, with the values of the glibc ABI on x86_64 and
+// aarch64, the module is only built for those targets.
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, SocketAddrV6, TcpListener, UdpSocket},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        raw::{c_int, c_void},
+    },
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+pub const AF_INET: c_int = 2;
+pub const AF_INET6: c_int = 10;
+pub const SOCK_STREAM: c_int = 1;
+pub const SOCK_DGRAM: c_int = 2;
+pub const SOCK_CLOEXEC: c_int = 0o2000000;
+
+pub const SOL_SOCKET: c_int = 1;
+pub const SO_REUSEADDR: c_int = 2;
+pub const SO_SNDBUF: c_int = 7;
+pub const SO_RCVBUF: c_int = 8;
+pub const SO_REUSEPORT: c_int = 15;
+
+pub const IPPROTO_IP: c_int = 0;
+pub const IP_PKTINFO: c_int = 8;
+pub const IPPROTO_IPV6: c_int = 41;
+pub const IPV6_V6ONLY: c_int = 26;
+pub const IPV6_RECVPKTINFO: c_int = 49;
+pub const IPV6_PKTINFO: c_int = 50;
+
+pub const SIGINT: c_int = 2;
+pub const SIGTERM: c_int = 15;
+const SIG_ERR: usize = usize::MAX;
+
+pub const MSG_WAITFORONE: c_int = 0x10000;
+pub const ENOSYS: i32 = 38;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SockaddrIn {
+    pub family: u16,
+    pub port: [u8; 2],
+    pub addr: [u8; 4],
+    pub zero: [u8; 8],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SockaddrIn6 {
+    pub family: u16,
+    pub port: [u8; 2],
+    pub flowinfo: u32,
+    pub addr: [u8; 16],
+    pub scope_id: u32,
+}
+
+// Large enough for either address family, as filled in by the kernel.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub union SockaddrStorage {
+    pub v4: SockaddrIn,
+    pub v6: SockaddrIn6,
+}
+
+impl SockaddrStorage {
+    pub fn zeroed() -> Self {
+        return SockaddrStorage {
+            v6: SockaddrIn6 {
+                family: 0,
+                port: [0; 2],
+                flowinfo: 0,
+                addr: [0; 16],
+                scope_id: 0,
+            },
+        };
+    }
+}
+
+#[repr(C)]
+pub struct Iovec {
+    pub base: *mut c_void,
+    pub len: usize,
+}
+
+#[repr(C)]
+pub struct Msghdr {
+    pub name: *mut c_void,
+    pub namelen: u32,
+    pub iov: *mut Iovec,
+    pub iovlen: usize,
+    pub control: *mut c_void,
+    pub controllen: usize,
+    pub flags: c_int,
+}
+
+#[repr(C)]
+pub struct Mmsghdr {
+    pub hdr: Msghdr,
+    pub len: u32,
+}
+
+impl Mmsghdr {
+    pub fn new(
+        name: *mut SockaddrStorage,
+        namelen: u32,
+        iov: *mut Iovec,
+        control: Option<(&mut Control, usize)>,
+    ) -> Self {
+        let (control, controllen) = match control {
+            Some((control, len)) => (control.0.as_mut_ptr() as *mut c_void, len),
+            None => (std::ptr::null_mut(), 0),
+        };
+        return Mmsghdr {
+            hdr: Msghdr {
+                name: name as *mut c_void,
+                namelen,
+                iov,
+                iovlen: 1,
+                control,
+                controllen,
+                flags: 0,
+            },
+            len: 0,
+        };
+    }
+}
+
+// Ancillary data buffer, aligned for cmsghdr. Large enough for one packet info message.
+#[derive(Clone, Copy, Default)]
+pub struct Control([u64; 8]);
+
+// Header of one ancillary message, the data follows aligned to `usize`.
+const CMSG_HDR_LEN: usize = std::mem::size_of::<usize>() + 2 * std::mem::size_of::<c_int>();
+
+fn cmsg_align(len: usize) -> usize {
+    let align = std::mem::size_of::<usize>();
+    return (len + align - 1) & !(align - 1);
+}
+
+impl Control {
+    pub fn len(&self) -> usize {
+        return std::mem::size_of::<[u64; 8]>();
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        return self.0.iter().flat_map(|w| w.to_ne_bytes()).collect();
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let mut control = Control::default();
+        for (word, chunk) in control.0.iter_mut().zip(bytes.chunks(8)) {
+            let mut buf = [0_u8; 8];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_ne_bytes(buf);
+        }
+        return control;
+    }
+
+    // The packet info among the first `len` bytes the kernel filled in.
+    pub fn pktinfo(&self, len: usize) -> Option<PktInfo> {
+        let bytes = self.bytes();
+        let bytes = &bytes[..len.min(bytes.len())];
+        let int = std::mem::size_of::<c_int>();
+        let mut offset = 0;
+        while offset + CMSG_HDR_LEN <= bytes.len() {
+            let word = std::mem::size_of::<usize>();
+            let cmsg_len = usize::from_ne_bytes(bytes[offset..offset + word].try_into().ok()?);
+            let level =
+                c_int::from_ne_bytes(bytes[offset + word..offset + word + int].try_into().ok()?);
+            let typ = c_int::from_ne_bytes(
+                bytes[offset + word + int..offset + CMSG_HDR_LEN]
+                    .try_into()
+                    .ok()?,
+            );
+            if cmsg_len < CMSG_HDR_LEN || offset + cmsg_len > bytes.len() {
+                return None;
+            }
+            let data = &bytes[offset + cmsg_align(CMSG_HDR_LEN)..offset + cmsg_len];
+            match (level, typ) {
+                // struct in_pktinfo { int ifindex; in_addr spec_dst; in_addr addr; }, where
+                // spec_dst is the local address to answer from
+                (IPPROTO_IP, IP_PKTINFO) if data.len() >= 12 => {
+                    let addr: [u8; 4] = data[4..8].try_into().ok()?;
+                    return Some(PktInfo {
+                        local: IpAddr::from(addr),
+                        ifindex: c_int::from_ne_bytes(data[0..4].try_into().ok()?) as u32,
+                    });
+                }
+                // struct in6_pktinfo { in6_addr addr; unsigned int ifindex; }
+                (IPPROTO_IPV6, IPV6_PKTINFO) if data.len() >= 20 => {
+                    let addr: [u8; 16] = data[0..16].try_into().ok()?;
+                    return Some(PktInfo {
+                        local: IpAddr::from(addr),
+                        ifindex: u32::from_ne_bytes(data[16..20].try_into().ok()?),
+                    });
+                }
+                _ => {}
+            }
+            offset += cmsg_align(cmsg_len);
+        }
+        return None;
+    }
+
+    // A control message selecting the source address of an outgoing packet, with its
+    // length.
+    pub fn with_pktinfo(info: &PktInfo) -> (Self, usize) {
+        let (level, typ, data) = match info.local {
+            IpAddr::V4(local) => {
+                let mut data = vec![0_u8; 12];
+                data[4..8].copy_from_slice(&local.octets());
+                (IPPROTO_IP, IP_PKTINFO, data)
+            }
+            IpAddr::V6(local) => {
+                let mut data = local.octets().to_vec();
+                data.extend_from_slice(&info.ifindex.to_ne_bytes());
+                (IPPROTO_IPV6, IPV6_PKTINFO, data)
+            }
+        };
+        let cmsg_len = cmsg_align(CMSG_HDR_LEN) + data.len();
+        let mut bytes = cmsg_len.to_ne_bytes().to_vec();
+        bytes.extend_from_slice(&level.to_ne_bytes());
+        bytes.extend_from_slice(&typ.to_ne_bytes());
+        bytes.resize(cmsg_align(CMSG_HDR_LEN), 0);
+        bytes.extend_from_slice(&data);
+        bytes.resize(cmsg_align(cmsg_len), 0);
+
+        return (Control::from_bytes(&bytes), bytes.len());
+    }
+}
+
+// Destination address and interface of a received packet, used as the source of the
+// reply on wildcard sockets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PktInfo {
+    pub local: IpAddr,
+    pub ifindex: u32,
+}
+
+extern "C" {
+    fn socket(domain: c_int, typ: c_int, protocol: c_int) -> c_int;
+    fn bind(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+    fn listen(fd: c_int, backlog: c_int) -> c_int;
+    fn setsockopt(fd: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+    fn getsockopt(fd: c_int, level: c_int, name: c_int, value: *mut c_void, len: *mut u32)
+        -> c_int;
+    fn recvmmsg(
+        fd: c_int,
+        msgs: *mut Mmsghdr,
+        vlen: u32,
+        flags: c_int,
+        timeout: *mut c_void,
+    ) -> c_int;
+    fn sendmmsg(fd: c_int, msgs: *mut Mmsghdr, vlen: u32, flags: c_int) -> c_int;
+    fn recvmsg(fd: c_int, msg: *mut Msghdr, flags: c_int) -> isize;
+    fn sendmsg(fd: c_int, msg: *const Msghdr, flags: c_int) -> isize;
+    fn signal(signum: c_int, handler: usize) -> usize;
+}
+
+// Set by `on_terminate` once SIGINT or SIGTERM arrived.
+static TERMINATING: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_terminate(_: c_int) {
+    TERMINATING.store(true, Ordering::SeqCst);
+}
+
+// Catch SIGINT and SIGTERM instead of dying, `terminating` tells when one arrived.
+pub fn catch_termination() -> io::Result<()> {
+    for signum in [SIGINT, SIGTERM] {
+        let handler = on_terminate as extern "C" fn(c_int) as usize;
+        // the handler only stores to an atomic, which is async-signal-safe
+        if unsafe { signal(signum, handler) } == SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    return Ok(());
+}
+
+pub fn terminating() -> bool {
+    return TERMINATING.load(Ordering::SeqCst);
+}
+
+fn check(ret: c_int) -> io::Result<c_int> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(ret);
+}
+
+pub fn to_storage(addr: &SocketAddr) -> (SockaddrStorage, u32) {
+    match addr {
+        SocketAddr::V4(v4) => {
+            let sa = SockaddrIn {
+                family: AF_INET as u16,
+                port: v4.port().to_be_bytes(),
+                addr: v4.ip().octets(),
+                zero: [0; 8],
+            };
+            return (
+                SockaddrStorage { v4: sa },
+                std::mem::size_of::<SockaddrIn>() as u32,
+            );
+        }
+        SocketAddr::V6(v6) => {
+            let sa = SockaddrIn6 {
+                family: AF_INET6 as u16,
+                port: v6.port().to_be_bytes(),
+                flowinfo: v6.flowinfo().to_be(),
+                addr: v6.ip().octets(),
+                scope_id: v6.scope_id(),
+            };
+            return (
+                SockaddrStorage { v6: sa },
+                std::mem::size_of::<SockaddrIn6>() as u32,
+            );
+        }
+    }
+}
+
+pub fn from_storage(storage: &SockaddrStorage) -> Option<SocketAddr> {
+    // both variants start with the family and every bit pattern is valid for them
+    let family = unsafe { storage.v4.family } as c_int;
+    match family {
+        AF_INET => {
+            let sa = unsafe { storage.v4 };
+            return Some(SocketAddr::from((sa.addr, u16::from_be_bytes(sa.port))));
+        }
+        AF_INET6 => {
+            let sa = unsafe { storage.v6 };
+            return Some(SocketAddr::V6(SocketAddrV6::new(
+                sa.addr.into(),
+                u16::from_be_bytes(sa.port),
+                u32::from_be(sa.flowinfo),
+                sa.scope_id,
+            )));
+        }
+        _ => return None,
+    }
+}
+
+pub fn set_option(
+    socket: &impl AsRawFd,
+    level: c_int,
+    name: c_int,
+    value: c_int,
+) -> io::Result<()> {
+    let len = std::mem::size_of::<c_int>() as u32;
+    let value = &value as *const c_int as *const c_void;
+    check(unsafe { setsockopt(socket.as_raw_fd(), level, name, value, len) })?;
+    return Ok(());
+}
+
+pub fn get_option(socket: &impl AsRawFd, level: c_int, name: c_int) -> io::Result<c_int> {
+    let mut value: c_int = 0;
+    let mut len = std::mem::size_of::<c_int>() as u32;
+    let ptr = &mut value as *mut c_int as *mut c_void;
+    check(unsafe { getsockopt(socket.as_raw_fd(), level, name, ptr, &mut len) })?;
+    return Ok(value);
+}
+
+fn domain(addr: &SocketAddr) -> c_int {
+    return match addr {
+        SocketAddr::V4(_) => AF_INET,
+        SocketAddr::V6(_) => AF_INET6,
+    };
+}
+
+// A UDP socket that is not bound yet, so options that must precede bind can be set.
+// IPv6 sockets only take IPv6 traffic so `[::]` and `0.0.0.0` can be bound side by side.
+pub fn udp_socket(addr: &SocketAddr) -> io::Result<UdpSocket> {
+    let fd = check(unsafe { socket(domain(addr), SOCK_DGRAM | SOCK_CLOEXEC, 0) })?;
+    // the fd is fresh and owned by nobody else, the UdpSocket closes it on drop
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    if addr.is_ipv6() {
+        set_option(&socket, IPPROTO_IPV6, IPV6_V6ONLY, 1)?;
+    }
+    return Ok(socket);
+}
+
+// Bound and listening TCP socket, IPv6 only for IPv6 addresses like `udp_socket`.
+pub fn tcp_listener(addr: &SocketAddr) -> io::Result<TcpListener> {
+    let fd = check(unsafe { socket(domain(addr), SOCK_STREAM | SOCK_CLOEXEC, 0) })?;
+    // the fd is fresh and owned by nobody else, the TcpListener closes it on drop
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    set_option(&listener, SOL_SOCKET, SO_REUSEADDR, 1)?;
+    if addr.is_ipv6() {
+        set_option(&listener, IPPROTO_IPV6, IPV6_V6ONLY, 1)?;
+    }
+    bind_to(&listener, addr)?;
+    check(unsafe { listen(listener.as_raw_fd(), 128) })?;
+    return Ok(listener);
+}
+
+// Ask for the destination address of every received packet.
+pub fn enable_pktinfo(socket: &UdpSocket, addr: &SocketAddr) -> io::Result<()> {
+    return match addr {
+        SocketAddr::V4(_) => set_option(socket, IPPROTO_IP, IP_PKTINFO, 1),
+        SocketAddr::V6(_) => set_option(socket, IPPROTO_IPV6, IPV6_RECVPKTINFO, 1),
+    };
+}
+
+pub fn bind_to(socket: &impl AsRawFd, addr: &SocketAddr) -> io::Result<()> {
+    let (storage, len) = to_storage(addr);
+    let ptr = &storage as *const SockaddrStorage as *const c_void;
+    check(unsafe { bind(socket.as_raw_fd(), ptr, len) })?;
+    return Ok(());
+}
+
+// The headers must point at live buffers for the duration of the call.
+pub fn recv_mmsg(socket: &impl AsRawFd, msgs: &mut [Mmsghdr], flags: c_int) -> io::Result<usize> {
+    let vlen = msgs.len().min(u32::MAX as usize) as u32;
+    let ptr = msgs.as_mut_ptr();
+    let count =
+        check(unsafe { recvmmsg(socket.as_raw_fd(), ptr, vlen, flags, std::ptr::null_mut()) })?;
+    return Ok(count as usize);
+}
+
+// The headers must point at live buffers for the duration of the call.
+pub fn send_mmsg(socket: &impl AsRawFd, msgs: &mut [Mmsghdr]) -> io::Result<usize> {
+    let vlen = msgs.len().min(u32::MAX as usize) as u32;
+    let count = check(unsafe { sendmmsg(socket.as_raw_fd(), msgs.as_mut_ptr(), vlen, 0) })?;
+    return Ok(count as usize);
+}
+
+// The header must point at live buffers for the duration of the call.
+pub fn recv_msg(socket: &impl AsRawFd, msg: &mut Msghdr) -> io::Result<usize> {
+    let received = unsafe { recvmsg(socket.as_raw_fd(), msg, 0) };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(received as usize);
+}
+
+// The header must point at live buffers for the duration of the call.
+pub fn send_msg(socket: &impl AsRawFd, msg: &Msghdr) -> io::Result<usize> {
+    let sent = unsafe { sendmsg(socket.as_raw_fd(), msg, 0) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(sent as usize);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    pub fn test_pktinfo_round_trip() {
+        for info in [
+            PktInfo {
+                local: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                ifindex: 0,
+            },
+            PktInfo {
+                local: IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+                ifindex: 3,
+            },
+        ] {
+            let (control, len) = Control::with_pktinfo(&info);
+            assert!(len <= control.len());
+            assert_eq!(Some(info), control.pktinfo(len));
+            // cut off inside the data
+            assert_eq!(None, control.pktinfo(CMSG_HDR_LEN + 4));
+        }
+        assert_eq!(None, Control::default().pktinfo(64));
+    }
+}
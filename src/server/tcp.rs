@@ -0,0 +1,404 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{pool::WorkerPool, Context, Transport};
+
+// Limits of the TCP listener.
+#[derive(Debug, Clone, Copy)]
+pub struct TcpOptions {
+    idle_timeout: Duration,
+    max_connections: usize,
+    max_pipelined: usize,
+}
+
+impl TcpOptions {
+    pub fn new() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(10),
+            max_connections: 128,
+            max_pipelined: 16,
+        }
+    }
+
+    // A connection without any complete query for this long is closed (RFC 7766 6.2.3),
+    // so is one whose client does not take an answer for this long.
+    pub fn idle_timeout(&self) -> Duration {
+        return self.idle_timeout;
+    }
+
+    pub fn with_idle_timeout(&mut self, idle_timeout: Duration) -> &mut Self {
+        self.idle_timeout = idle_timeout;
+        return self;
+    }
+
+    // Connections above this number are closed right after accept.
+    pub fn max_connections(&self) -> usize {
+        return self.max_connections;
+    }
+
+    pub fn with_max_connections(&mut self, max_connections: usize) -> &mut Self {
+        self.max_connections = max_connections;
+        return self;
+    }
+
+    // Queries of one connection processed at the same time, further queries wait.
+    pub fn max_pipelined(&self) -> usize {
+        return self.max_pipelined;
+    }
+
+    pub fn with_max_pipelined(&mut self, max_pipelined: usize) -> &mut Self {
+        self.max_pipelined = max_pipelined.max(1);
+        return self;
+    }
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// Bind a listening socket. On Linux IPv6 addresses only take IPv6 connections, so the
+// IPv4 and IPv6 wildcards can both be bound.
+pub fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    ))]
+    return super::sys::tcp_listener(&addr);
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    )))]
+    return TcpListener::bind(addr);
+}
+
+// Accept connections until the listener fails for good. Every connection gets its own
+// reading thread, queries are framed with a two-byte length (RFC 1035 4.2.2) and answered
+// on the worker pool.
+pub fn serve(listener: &TcpListener, ctx: Arc<Context>, opts: TcpOptions, pool: Arc<WorkerPool>) {
+    let active = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                ctx.stats().inc_recv_errors();
+                crate::warn!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        if active.fetch_add(1, Ordering::SeqCst) >= opts.max_connections {
+            active.fetch_sub(1, Ordering::SeqCst);
+            ctx.stats().inc_dropped();
+            let _ = stream.shutdown(Shutdown::Both);
+            continue;
+        }
+
+        let ctx = ctx.clone();
+        let active = active.clone();
+        let pool = pool.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve_connection(stream, &ctx, opts, &pool) {
+                crate::debug!("Error serving connection: {}", e);
+            }
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+// Read framed queries from one connection. Every query is handed to the worker pool so
+// answers go out as soon as they are ready, possibly out of order (RFC 7766 6.2.1.1).
+// Answers are written by a thread of the connection, a client that does not read blocks
+// that thread and never a worker.
+#[allow(clippy::io_other_error)]
+fn serve_connection(
+    stream: TcpStream,
+    ctx: &Arc<Context>,
+    opts: TcpOptions,
+    pool: &WorkerPool,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(opts.idle_timeout))?;
+    stream.set_write_timeout(Some(opts.idle_timeout))?;
+    stream.set_nodelay(true)?;
+    let peer = stream.peer_addr()?.ip();
+    // a query counts as in flight until its answer is written
+    let in_flight = Arc::new(InFlight::new());
+    let (answers, pending) = mpsc::channel::<Vec<u8>>();
+    let writer = {
+        let stream = stream.try_clone()?;
+        let ctx = ctx.clone();
+        let in_flight = in_flight.clone();
+        thread::spawn(move || write_answers(stream, &pending, &ctx, &in_flight))
+    };
+    let mut reader = stream;
+
+    let result = loop {
+        let query = match read_frame(&mut reader) {
+            Ok(Some(query)) => query,
+            Ok(None) => break Ok(()),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                break Ok(());
+            }
+            Err(e) => break Err(e),
+        };
+
+        in_flight.acquire(opts.max_pipelined);
+        let answers = answers.clone();
+        let ctx = ctx.clone();
+        let done = in_flight.clone();
+        let job = move || {
+            let sent = match ctx.handle(&query, Transport::Tcp, peer) {
+                Some(resp) => answers.send(resp).is_ok(),
+                None => false,
+            };
+            if !sent {
+                done.release();
+            }
+        };
+        if let Err(e) = pool.submit(job) {
+            in_flight.release();
+            break Err(io::Error::new(ErrorKind::Other, e.to_string()));
+        }
+    };
+
+    // queries already read still get their answers before the connection is closed
+    in_flight.wait_idle();
+    drop(answers);
+    let _ = writer.join();
+    let _ = reader.shutdown(Shutdown::Both);
+    return result;
+}
+
+// Write the answers of one connection until every sender is gone. After a failed write,
+// the connection is shut down so its reader stops too, and the rest is discarded.
+fn write_answers(
+    mut stream: TcpStream,
+    pending: &mpsc::Receiver<Vec<u8>>,
+    ctx: &Context,
+    in_flight: &InFlight,
+) {
+    let mut failed = false;
+    for resp in pending.iter() {
+        if !failed {
+            if let Err(e) = write_frame(&mut stream, &resp) {
+                ctx.stats().inc_send_errors();
+                crate::warn!("Error sending response: {}", e);
+                let _ = stream.shutdown(Shutdown::Both);
+                failed = true;
+            }
+        }
+        in_flight.release();
+    }
+}
+
+// Queries of one connection that are not answered yet.
+struct InFlight {
+    count: Mutex<usize>,
+    released: Condvar,
+}
+
+impl InFlight {
+    fn new() -> Self {
+        Self {
+            count: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    // Wait until fewer than `max` queries are in flight, then count one more.
+    fn acquire(&self, max: usize) {
+        let mut count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+        while *count >= max {
+            count = self.released.wait(count).unwrap_or_else(|e| e.into_inner());
+        }
+        *count += 1;
+    }
+
+    fn release(&self) {
+        let mut count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+        *count -= 1;
+        self.released.notify_all();
+    }
+
+    // Wait until every query has been answered.
+    fn wait_idle(&self) {
+        let mut count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+        while *count > 0 {
+            count = self.released.wait(count).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+// Read one length-prefixed message, `None` on a clean end of stream.
+pub fn read_frame<R: Read>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len = [0_u8; 2];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let mut msg = vec![0_u8; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut msg)?;
+
+    return Ok(Some(msg));
+}
+
+// Write one message with its two-byte length prefix in a single write.
+pub fn write_frame<W: Write>(writer: &mut W, msg: &[u8]) -> std::io::Result<()> {
+    if msg.len() > u16::MAX as usize {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "message exceeds 65535 bytes",
+        ));
+    }
+    let mut frame = Vec::<u8>::with_capacity(msg.len() + 2);
+    frame.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+    frame.extend_from_slice(msg);
+
+    return writer.write_all(&frame);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::DNS;
+    use std::collections::HashSet;
+
+    fn query(id: u16) -> Vec<u8> {
+        let mut raw = id.to_be_bytes().to_vec();
+        raw.extend_from_slice(&[0x00, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        raw.extend_from_slice(b"\x03www\x06google\x03com\x00\x00\x01\x00\x01");
+        return raw;
+    }
+
+    fn start(opts: TcpOptions) -> (SocketAddr, Arc<Context>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let ctx = Arc::new(Context::new());
+        let server_ctx = ctx.clone();
+        let pool = Arc::new(WorkerPool::new(4, 64));
+        thread::spawn(move || serve(&listener, server_ctx, opts, pool));
+        return (addr, ctx);
+    }
+
+    #[test]
+    pub fn test_frame_round_trip() {
+        let mut buf = Vec::<u8>::new();
+        write_frame(&mut buf, b"abc").unwrap();
+        write_frame(&mut buf, b"").unwrap();
+        assert_eq!(vec![0, 3, b'a', b'b', b'c', 0, 0], buf);
+
+        let mut reader = buf.as_slice();
+        assert_eq!(Some(b"abc".to_vec()), read_frame(&mut reader).unwrap());
+        assert_eq!(Some(vec![]), read_frame(&mut reader).unwrap());
+        assert_eq!(None, read_frame(&mut reader).unwrap());
+
+        // length says 3 but only 1 byte follows
+        let mut reader: &[u8] = &[0, 3, b'a'];
+        assert!(read_frame(&mut reader).is_err());
+
+        assert!(write_frame(&mut Vec::new(), &vec![0; 65536]).is_err());
+    }
+
+    #[test]
+    pub fn test_serve_pipelined() {
+        let (addr, _) = start(TcpOptions::new());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        // several queries in one segment
+        let mut buf = Vec::<u8>::new();
+        for id in 1..=5 {
+            write_frame(&mut buf, &query(id)).unwrap();
+        }
+        stream.write_all(&buf).unwrap();
+
+        let mut ids = HashSet::new();
+        for _ in 1..=5 {
+            let resp = read_frame(&mut stream).unwrap().unwrap();
+            let resp = DNS::decode_strict(&resp).unwrap();
+            assert_eq!(1, resp.head().qr());
+            ids.insert(resp.head().id());
+        }
+        assert_eq!((1..=5).collect::<HashSet<u16>>(), ids);
+
+        // a malformed query gets a FORMERR on the same connection
+        write_frame(&mut stream, &[0x00, 0x09, 0x00]).unwrap();
+        let resp = DNS::decode(&read_frame(&mut stream).unwrap().unwrap()).unwrap();
+        assert_eq!(9, resp.head().id());
+    }
+
+    #[test]
+    pub fn test_bind_both_wildcards() {
+        let v6 = bind("[::]:0".parse().unwrap()).unwrap();
+        let port = v6.local_addr().unwrap().port();
+        // the IPv6 wildcard does not take the IPv4 port
+        let v4 = bind(SocketAddr::from(([0, 0, 0, 0], port))).unwrap();
+        assert_eq!(port, v4.local_addr().unwrap().port());
+
+        let ctx = Arc::new(Context::new());
+        let pool = Arc::new(WorkerPool::new(2, 16));
+        thread::spawn(move || serve(&v4, ctx, TcpOptions::new(), pool));
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write_frame(&mut stream, &query(4)).unwrap();
+        assert!(read_frame(&mut stream).unwrap().is_some());
+    }
+
+    #[test]
+    pub fn test_serve_idle_timeout() {
+        let mut opts = TcpOptions::new();
+        opts.with_idle_timeout(Duration::from_millis(100));
+        let (addr, _) = start(opts);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0_u8; 1];
+        // closed by the server without an answer
+        assert!(matches!(stream.read(&mut buf), Ok(0) | Err(_)));
+    }
+
+    #[test]
+    pub fn test_serve_max_connections() {
+        let mut opts = TcpOptions::new();
+        opts.with_max_connections(1);
+        let (addr, ctx) = start(opts);
+
+        let mut first = TcpStream::connect(addr).unwrap();
+        first
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        write_frame(&mut first, &query(1)).unwrap();
+        assert!(read_frame(&mut first).unwrap().is_some());
+
+        let mut second = TcpStream::connect(addr).unwrap();
+        second
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let _ = write_frame(&mut second, &query(2));
+        assert!(!matches!(read_frame(&mut second), Ok(Some(_))));
+        assert_eq!(1, ctx.stats().dropped());
+
+        // the first connection is still served
+        write_frame(&mut first, &query(3)).unwrap();
+        assert!(read_frame(&mut first).unwrap().is_some());
+    }
+}
@@ -0,0 +1,528 @@
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    thread,
+};
+
+use super::{pool::WorkerPool, Context, Transport};
+
+pub const READ_LENGTH: usize = 1024;
+
+// How the UDP sockets of one address are opened.
+#[derive(Debug, Clone, Copy)]
+pub struct UdpOptions {
+    sockets: usize,
+    recv_buffer: Option<usize>,
+    send_buffer: Option<usize>,
+    batch_size: usize,
+}
+
+impl UdpOptions {
+    pub fn new() -> Self {
+        Self {
+            sockets: 1,
+            recv_buffer: None,
+            send_buffer: None,
+            batch_size: 1,
+        }
+    }
+
+    // Sockets bound to the same address with SO_REUSEPORT, the kernel spreads the
+    // clients over them. More than one is only supported on Linux.
+    pub fn sockets(&self) -> usize {
+        return self.sockets;
+    }
+
+    pub fn with_sockets(&mut self, sockets: usize) -> &mut Self {
+        self.sockets = sockets.max(1);
+        return self;
+    }
+
+    // One socket per available core.
+    pub fn with_socket_per_core(&mut self) -> &mut Self {
+        return self.with_sockets(thread::available_parallelism().map_or(1, |n| n.get()));
+    }
+
+    // SO_RCVBUF in bytes, the system default when unset.
+    pub fn recv_buffer(&self) -> Option<usize> {
+        return self.recv_buffer;
+    }
+
+    pub fn with_recv_buffer(&mut self, size: usize) -> &mut Self {
+        self.recv_buffer = Some(size);
+        return self;
+    }
+
+    // SO_SNDBUF in bytes, the system default when unset.
+    pub fn send_buffer(&self) -> Option<usize> {
+        return self.send_buffer;
+    }
+
+    pub fn with_send_buffer(&mut self, size: usize) -> &mut Self {
+        self.send_buffer = Some(size);
+        return self;
+    }
+
+    // Datagrams received and sent per syscall with recvmmsg/sendmmsg. 1 keeps the
+    // portable recv_from/send_to loop, which is also used where batching is unavailable.
+    pub fn batch_size(&self) -> usize {
+        return self.batch_size;
+    }
+
+    pub fn with_batch_size(&mut self, batch_size: usize) -> &mut Self {
+        self.batch_size = batch_size.max(1);
+        return self;
+    }
+}
+
+impl Default for UdpOptions {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+// Open the sockets for `addr`. With port 0 the first socket picks the port and the
+// others join it. Wildcard sockets record the destination of every query, so the reply
+// can be sent from that address.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+))]
+pub fn bind(addr: SocketAddr, opts: &UdpOptions) -> io::Result<Vec<UdpSocket>> {
+    use super::sys;
+
+    let mut addr = addr;
+    let mut sockets = Vec::with_capacity(opts.sockets);
+    for _ in 0..opts.sockets {
+        let socket = sys::udp_socket(&addr)?;
+        if opts.sockets > 1 {
+            sys::set_option(&socket, sys::SOL_SOCKET, sys::SO_REUSEPORT, 1)?;
+        }
+        if let Some(size) = opts.recv_buffer {
+            sys::set_option(&socket, sys::SOL_SOCKET, sys::SO_RCVBUF, buffer_size(size))?;
+        }
+        if let Some(size) = opts.send_buffer {
+            sys::set_option(&socket, sys::SOL_SOCKET, sys::SO_SNDBUF, buffer_size(size))?;
+        }
+        if addr.ip().is_unspecified() {
+            sys::enable_pktinfo(&socket, &addr)?;
+        }
+        sys::bind_to(&socket, &addr)?;
+        addr = socket.local_addr()?;
+        sockets.push(socket);
+    }
+
+    return Ok(sockets);
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+))]
+fn buffer_size(size: usize) -> i32 {
+    return size.min(i32::MAX as usize) as i32;
+}
+
+// Without SO_REUSEPORT support there is a single socket with the default buffers.
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+)))]
+pub fn bind(addr: SocketAddr, opts: &UdpOptions) -> io::Result<Vec<UdpSocket>> {
+    if opts.sockets > 1 || opts.recv_buffer.is_some() || opts.send_buffer.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "socket tuning is only supported on Linux",
+        ));
+    }
+    return Ok(vec![UdpSocket::bind(addr)?]);
+}
+
+// Receive buffer size the kernel actually granted, Linux reports twice the requested
+// size to account for its bookkeeping.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+))]
+pub fn recv_buffer_size(socket: &UdpSocket) -> io::Result<usize> {
+    use super::sys;
+
+    return Ok(sys::get_option(socket, sys::SOL_SOCKET, sys::SO_RCVBUF)? as usize);
+}
+
+// Receive datagrams until the socket is gone and hand them to the worker pool, the reply
+// goes out through the same socket to the source of the query. Errors of a single packet
+// are logged and counted and the loop keeps serving, any other error ends it.
+pub fn serve(socket: Arc<UdpSocket>, ctx: Arc<Context>, pool: &WorkerPool) {
+    let mut buf = [0; READ_LENGTH];
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((size, source)) => {
+                let query = buf[0..size].to_vec();
+                let socket = socket.clone();
+                let ctx = ctx.clone();
+                let job = move || {
+                    let resp = match ctx.handle(&query, Transport::Udp, source.ip()) {
+                        Some(resp) => resp,
+                        None => return,
+                    };
+                    if let Err(e) = socket.send_to(&resp, source) {
+                        ctx.stats().inc_send_errors();
+                        crate::warn!("Error sending response to {}: {}", source, e);
+                    }
+                };
+                // blocks while the queue is full, further datagrams wait in the socket buffer
+                if let Err(e) = pool.submit(job) {
+                    crate::error!("Error dispatching query from {}: {}", source, e);
+                    return;
+                }
+            }
+            Err(e) => {
+                ctx.stats().inc_recv_errors();
+                if !per_packet(&e) {
+                    crate::error!("Stopped receiving: {}", e);
+                    return;
+                }
+                crate::warn!("Error receiving data: {}", e);
+            }
+        }
+    }
+}
+
+// Whether a receive error is about one packet only, like the ICMP error of an earlier
+// reply, so the next receive can succeed. Anything else leaves the socket unusable.
+fn per_packet(e: &io::Error) -> bool {
+    return matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+    );
+}
+
+// Like `serve`, with batched receive and send on Linux. Replies are collected by a sender
+// thread per socket and written with one sendmmsg for whatever is ready. Wildcard sockets
+// always take this path since it carries the packet info. Falls back to `serve` when
+// batching is off or the kernel lacks recvmmsg.
+pub fn serve_batched(
+    socket: Arc<UdpSocket>,
+    ctx: Arc<Context>,
+    pool: &WorkerPool,
+    batch_size: usize,
+) {
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    ))]
+    if batch_size > 1
+        || socket
+            .local_addr()
+            .is_ok_and(|addr| addr.ip().is_unspecified())
+    {
+        return batched::serve_batched(socket, ctx, pool, batch_size);
+    }
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    )))]
+    let _ = batch_size;
+    return serve(socket, ctx, pool);
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    target_env = "gnu"
+))]
+mod batched {
+    use std::{
+        net::UdpSocket,
+        sync::{
+            mpsc::{self, Receiver},
+            Arc,
+        },
+        thread,
+    };
+
+    use super::{
+        super::mmsg::{self, Datagram},
+        super::sys,
+        READ_LENGTH,
+    };
+    use crate::server::{pool::WorkerPool, Context, Transport};
+
+    pub fn serve_batched(
+        socket: Arc<UdpSocket>,
+        ctx: Arc<Context>,
+        pool: &WorkerPool,
+        batch_size: usize,
+    ) {
+        let (replies, outgoing) = mpsc::channel::<Datagram>();
+        {
+            let socket = socket.clone();
+            let ctx = ctx.clone();
+            thread::spawn(move || send_loop(&socket, &ctx, &outgoing, batch_size));
+        }
+
+        let mut batch = mmsg::RecvBatch::new(batch_size, READ_LENGTH);
+        // set once recvmmsg turns out to be missing, recvmsg still keeps the packet info
+        let mut one_at_a_time = false;
+        loop {
+            let received = if one_at_a_time {
+                batch.recv_one(&socket)
+            } else {
+                batch.recv(&socket)
+            };
+            let count = match received {
+                Ok(count) => count,
+                Err(e) if !one_at_a_time && e.raw_os_error() == Some(sys::ENOSYS) => {
+                    crate::warn!("recvmmsg is not available, receiving one packet at a time");
+                    one_at_a_time = true;
+                    continue;
+                }
+                Err(e) => {
+                    ctx.stats().inc_recv_errors();
+                    if !super::per_packet(&e) {
+                        crate::error!("Stopped receiving: {}", e);
+                        return;
+                    }
+                    crate::warn!("Error receiving data: {}", e);
+                    continue;
+                }
+            };
+
+            for i in 0..count {
+                let (query, source, pktinfo) = match batch.packet(i) {
+                    Some((query, source, pktinfo)) => (query.to_vec(), source, pktinfo),
+                    None => continue,
+                };
+                let ctx = ctx.clone();
+                let replies = replies.clone();
+                let job = move || {
+                    if let Some(resp) = ctx.handle(&query, Transport::Udp, source.ip()) {
+                        let _ = replies.send((resp, source, pktinfo));
+                    }
+                };
+                if let Err(e) = pool.submit(job) {
+                    crate::error!("Error dispatching query from {}: {}", source, e);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Send replies as they come, batching whatever queued up meanwhile. Stops once every
+    // reply sender is gone.
+    fn send_loop(
+        socket: &UdpSocket,
+        ctx: &Context,
+        outgoing: &Receiver<Datagram>,
+        batch_size: usize,
+    ) {
+        let mut pending = Vec::<Datagram>::with_capacity(batch_size);
+        while let Ok(reply) = outgoing.recv() {
+            pending.push(reply);
+            while pending.len() < batch_size {
+                match outgoing.try_recv() {
+                    Ok(reply) => pending.push(reply),
+                    Err(_) => break,
+                }
+            }
+
+            let mut sent = 0;
+            while sent < pending.len() {
+                match mmsg::send_batch(socket, &pending[sent..]) {
+                    Ok(0) => break,
+                    Ok(count) => sent += count,
+                    // the first reply failed or sendmmsg is unavailable, send that one on
+                    // its own so the rest are not held up, still from the address it was
+                    // sent to
+                    Err(_) => {
+                        if let Err(e) = mmsg::send_one(socket, &pending[sent]) {
+                            ctx.stats().inc_send_errors();
+                            crate::warn!("Error sending response to {}: {}", pending[sent].1, e);
+                        }
+                        sent += 1;
+                    }
+                }
+            }
+            pending.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::{rcode::Rcode, DNS};
+    use std::{collections::HashSet, time::Duration};
+
+    #[test]
+    pub fn test_serve_survives_bad_packets() {
+        let server = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = server.local_addr().unwrap();
+        let ctx = Arc::new(Context::new());
+        let server_ctx = ctx.clone();
+        thread::spawn(move || serve(server, server_ctx, &WorkerPool::new(2, 16)));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0; READ_LENGTH];
+
+        // too short to reply to, dropped
+        client.send_to(&[0x01], addr).unwrap();
+        // readable ID but truncated, FORMERR
+        client
+            .send_to(&[0x04, 0xd2, 0x00, 0x00, 0x00], addr)
+            .unwrap();
+        let size = client.recv(&mut buf).unwrap();
+        let resp = DNS::decode(&buf[..size]).unwrap();
+        assert_eq!(1234, resp.head().id());
+        assert_eq!(Rcode::FormErr, resp.rcode());
+
+        // still serving
+        let mut query = vec![0x00, 0x07, 0x00, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(b"\x03www\x00\x00\x01\x00\x01");
+        client.send_to(&query, addr).unwrap();
+        let size = client.recv(&mut buf).unwrap();
+        let resp = DNS::decode(&buf[..size]).unwrap();
+        assert_eq!(7, resp.head().id());
+        assert_eq!(Rcode::NoError, resp.rcode());
+
+        assert_eq!(1, ctx.stats().dropped());
+        assert_eq!(1, ctx.stats().formerr());
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        target_env = "gnu"
+    ))]
+    #[test]
+    pub fn test_bind_reuseport() {
+        let mut opts = UdpOptions::new();
+        opts.with_sockets(4).with_recv_buffer(1 << 16);
+        let sockets = bind("127.0.0.1:0".parse().unwrap(), &opts).unwrap();
+        assert_eq!(4, sockets.len());
+        let addr = sockets[0].local_addr().unwrap();
+        assert_ne!(0, addr.port());
+        for socket in &sockets {
+            assert_eq!(addr, socket.local_addr().unwrap());
+            assert!(recv_buffer_size(socket).unwrap() >= 1 << 16);
+        }
+
+        // the port is taken for sockets without SO_REUSEPORT
+        assert!(UdpSocket::bind(addr).is_err());
+
+        let ctx = Arc::new(Context::new());
+        let pool = Arc::new(WorkerPool::new(2, 16));
+        for socket in sockets {
+            let ctx = ctx.clone();
+            let pool = pool.clone();
+            thread::spawn(move || serve(Arc::new(socket), ctx, &pool));
+        }
+
+        // clients on different ports are spread over the sockets, all get answered
+        for id in 0..16_u16 {
+            let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut query = id.to_be_bytes().to_vec();
+            query.extend_from_slice(&[0x00, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+            query.extend_from_slice(b"\x03www\x00\x00\x01\x00\x01");
+            client.send_to(&query, addr).unwrap();
+            let mut buf = [0; READ_LENGTH];
+            let size = client.recv(&mut buf).unwrap();
+            assert_eq!(id, DNS::decode(&buf[..size]).unwrap().head().id());
+        }
+    }
+
+    #[test]
+    pub fn test_per_packet_errors() {
+        assert!(per_packet(&io::Error::from(
+            io::ErrorKind::ConnectionRefused
+        )));
+        assert!(per_packet(&io::Error::from(io::ErrorKind::Interrupted)));
+        // EBADF and ENOTSOCK
+        assert!(!per_packet(&io::Error::from_raw_os_error(9)));
+        assert!(!per_packet(&io::Error::from_raw_os_error(88)));
+    }
+
+    #[test]
+    pub fn test_serve_batched() {
+        let server = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = server.local_addr().unwrap();
+        let ctx = Arc::new(Context::new());
+        let server_ctx = ctx.clone();
+        thread::spawn(move || serve_batched(server, server_ctx, &WorkerPool::new(2, 64), 8));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // a burst larger than one batch, plus a packet that is dropped
+        client.send_to(&[0x01], addr).unwrap();
+        for id in 0..20_u16 {
+            let mut query = id.to_be_bytes().to_vec();
+            query.extend_from_slice(&[0x00, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+            query.extend_from_slice(b"\x03www\x00\x00\x01\x00\x01");
+            client.send_to(&query, addr).unwrap();
+        }
+        let mut ids = HashSet::new();
+        let mut buf = [0; READ_LENGTH];
+        for _ in 0..20 {
+            let size = client.recv(&mut buf).unwrap();
+            ids.insert(DNS::decode(&buf[..size]).unwrap().head().id());
+        }
+        assert_eq!((0..20).collect::<HashSet<u16>>(), ids);
+        assert_eq!(1, ctx.stats().dropped());
+    }
+
+    #[test]
+    pub fn test_serve_concurrent_clients() {
+        let server = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = server.local_addr().unwrap();
+        let ctx = Arc::new(Context::new());
+        thread::spawn(move || serve(server, ctx, &WorkerPool::new(4, 2)));
+
+        // every client gets its own replies, even when the queue is smaller than the load
+        let clients: Vec<_> = (0..4_u16)
+            .map(|c| {
+                thread::spawn(move || {
+                    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+                    client
+                        .set_read_timeout(Some(Duration::from_secs(5)))
+                        .unwrap();
+                    let mut ids = HashSet::new();
+                    for i in 0..10_u16 {
+                        let id = c * 100 + i;
+                        let mut query = id.to_be_bytes().to_vec();
+                        query.extend_from_slice(&[0x00, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+                        query.extend_from_slice(b"\x03www\x00\x00\x01\x00\x01");
+                        client.send_to(&query, addr).unwrap();
+                        let mut buf = [0; READ_LENGTH];
+                        let size = client.recv(&mut buf).unwrap();
+                        ids.insert(DNS::decode(&buf[..size]).unwrap().head().id());
+                    }
+                    assert_eq!((c * 100..c * 100 + 10).collect::<HashSet<u16>>(), ids);
+                })
+            })
+            .collect();
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+}
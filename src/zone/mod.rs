@@ -0,0 +1,458 @@
+pub mod parser;
+
+use anyhow::Error;
+use std::{collections::HashMap, path::Path};
+
+use crate::dns::{
+    answer::ResourceRecord, question::Question, rcode::Rcode, rtype, DomainName, DNS,
+};
+
+// Longest CNAME chain followed inside the zones of one answer.
+const MAX_CNAMES: usize = 8;
+
+// The records of one zone, grouped by owner name.
+// Every name between the apex and an owner is present, names without records are the
+// empty non-terminals that exist without data (RFC 8020).
+#[derive(Debug, Clone)]
+pub struct Zone {
+    origin: DomainName,
+    soa: ResourceRecord,
+    names: HashMap<DomainName, Vec<ResourceRecord>>,
+}
+
+impl Zone {
+    // A zone of `records`, which must hold exactly one SOA and at least one NS record at
+    // `origin` and nothing outside it.
+    pub fn new(origin: DomainName, records: Vec<ResourceRecord>) -> Result<Self, Error> {
+        let mut names = HashMap::<DomainName, Vec<ResourceRecord>>::new();
+        names.insert(origin.clone(), vec![]);
+        for rr in records {
+            if !rr.name().is_subdomain_of(&origin) {
+                return Err(Error::msg(format!(
+                    "{} is outside the zone {}",
+                    rr.name(),
+                    origin
+                )));
+            }
+            let mut name = rr.name().clone();
+            while name != origin && !names.contains_key(&name) {
+                names.insert(name.clone(), vec![]);
+                name = name.parent().unwrap_or_else(DomainName::root);
+            }
+            let rrs = names.get_mut(rr.name()).unwrap();
+            // the same record listed twice is kept once (RFC 2181 5)
+            if !rrs
+                .iter()
+                .any(|r| r.typ() == rr.typ() && r.rdata() == rr.rdata())
+            {
+                rrs.push(rr);
+            }
+        }
+
+        for (name, rrs) in names.iter() {
+            let cname = rrs.iter().any(|rr| rr.typ() == rtype::CNAME);
+            if cname && rrs.len() > 1 {
+                return Err(Error::msg(format!(
+                    "{} has a CNAME and other data (RFC 1034 3.6.2)",
+                    name
+                )));
+            }
+        }
+        let apex = &names[&origin];
+        let soas: Vec<&ResourceRecord> = apex.iter().filter(|rr| rr.typ() == rtype::SOA).collect();
+        if soas.len() != 1 {
+            return Err(Error::msg(format!(
+                "the zone {} needs exactly one SOA record at its apex",
+                origin
+            )));
+        }
+        if !apex.iter().any(|rr| rr.typ() == rtype::NS) {
+            return Err(Error::msg(format!(
+                "the zone {} has no NS records at its apex",
+                origin
+            )));
+        }
+        let soa = soas[0].clone();
+
+        return Ok(Zone { origin, soa, names });
+    }
+
+    // Read the zone `origin` from a zone file.
+    pub fn load(origin: DomainName, path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::msg(format!("{}: {}", path.display(), e)))?;
+        let records = parser::parse(&text, &origin)
+            .map_err(|e| Error::msg(format!("{}: {}", path.display(), e)))?;
+        return Zone::new(origin, records)
+            .map_err(|e| Error::msg(format!("{}: {}", path.display(), e)));
+    }
+
+    pub fn origin(&self) -> &DomainName {
+        return &self.origin;
+    }
+
+    pub fn soa(&self) -> &ResourceRecord {
+        return &self.soa;
+    }
+
+    // Number of names, empty non-terminals included.
+    pub fn len(&self) -> usize {
+        return self.names.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.names.is_empty();
+    }
+
+    // Add the answer to `ques` to `resp` (RFC 1034 4.3.2), `ques` being inside the zone.
+    // Returns the name a CNAME chain leaves the zone with, if it does.
+    fn answer(&self, ques: &Question, resp: &mut DNS) -> Option<DomainName> {
+        let qname = ques.name();
+        if let Some(cut) = self.delegation(qname) {
+            self.refer(&cut, resp);
+            return None;
+        }
+
+        let rrs = match self.names.get(qname) {
+            Some(rrs) => rrs,
+            None => {
+                resp.with_rcode(Rcode::NXDomain);
+                resp.with_authority(self.negative_soa());
+                return None;
+            }
+        };
+        let matching: Vec<&ResourceRecord> = rrs
+            .iter()
+            .filter(|rr| ques.typ() == rtype::ANY || rr.typ() == ques.typ())
+            .collect();
+        if !matching.is_empty() {
+            for rr in matching {
+                resp.with_answer(rr.clone());
+                self.add_glue(rr, resp);
+            }
+            return None;
+        }
+        if let Some(cname) = rrs.iter().find(|rr| rr.typ() == rtype::CNAME) {
+            resp.with_answer(cname.clone());
+            return DomainName::decode(cname.rdata(), 0)
+                .ok()
+                .map(|(target, _)| target);
+        }
+
+        // NODATA, the name exists (maybe as an empty non-terminal) without this type
+        resp.with_authority(self.negative_soa());
+        return None;
+    }
+
+    // The highest zone cut between the apex and `name`, `name` itself included.
+    fn delegation(&self, name: &DomainName) -> Option<DomainName> {
+        let depth = name.len() - self.origin.len();
+        let labels: Vec<&[u8]> = name.iter().collect();
+        for skip in (0..depth).rev() {
+            let cut = DomainName::from_labels(labels[skip..].iter().map(|l| l.to_vec())).ok()?;
+            let rrs = self.names.get(&cut)?;
+            if rrs.iter().any(|rr| rr.typ() == rtype::NS) {
+                return Some(cut);
+            }
+        }
+        return None;
+    }
+
+    // A referral to the servers of the child zone at `cut`, with the addresses of those
+    // servers this zone holds as glue.
+    fn refer(&self, cut: &DomainName, resp: &mut DNS) {
+        resp.head_mut().with_aa(0);
+        for ns in self.names[cut].iter().filter(|rr| rr.typ() == rtype::NS) {
+            resp.with_authority(ns.clone());
+            self.add_glue(ns, resp);
+        }
+    }
+
+    // Addresses of the name an NS or MX record points to, when the zone holds them.
+    fn add_glue(&self, rr: &ResourceRecord, resp: &mut DNS) {
+        let target = match rr.typ() {
+            rtype::NS => DomainName::decode(rr.rdata(), 0),
+            rtype::MX if rr.rdata().len() > 2 => DomainName::decode(rr.rdata(), 2),
+            _ => return,
+        };
+        let target = match target {
+            Ok((target, _)) => target,
+            Err(_) => return,
+        };
+        for addr in self.names.get(&target).into_iter().flatten() {
+            if matches!(addr.typ(), rtype::A | rtype::AAAA) {
+                let encoded = addr.encode();
+                if !resp.additionals().iter().any(|r| r.encode() == encoded) {
+                    resp.with_additional(addr.clone());
+                }
+            }
+        }
+    }
+
+    // The SOA of a negative answer, its TTL is the negative caching time (RFC 2308 3).
+    fn negative_soa(&self) -> ResourceRecord {
+        let mut soa = self.soa.clone();
+        let rdata = soa.rdata();
+        if rdata.len() >= 4 {
+            let minimum = u32::from_be_bytes(rdata[rdata.len() - 4..].try_into().unwrap());
+            soa.with_ttl(soa.ttl().min(minimum));
+        }
+        return soa;
+    }
+}
+
+// The zones the server is authoritative for.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    zones: HashMap<DomainName, Zone>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    // Replaces a zone of the same origin.
+    pub fn with_zone(&mut self, zone: Zone) -> &mut Self {
+        self.zones.insert(zone.origin().clone(), zone);
+        return self;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.zones.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.zones.is_empty();
+    }
+
+    // The closest zone `name` is inside of.
+    pub fn find(&self, name: &DomainName) -> Option<&Zone> {
+        let mut name = name.clone();
+        loop {
+            if let Some(zone) = self.zones.get(&name) {
+                return Some(zone);
+            }
+            name = name.parent()?;
+        }
+    }
+
+    // The authoritative reply to a query about a name in one of the zones, `None` for
+    // anything else. Only queries with a single question of class IN are answered.
+    pub fn answer(&self, query: &DNS) -> Option<DNS> {
+        let ques = match query.questions().iter().collect::<Vec<_>>()[..] {
+            [ques] if ques.class() == rtype::CLASS_IN => ques,
+            _ => return None,
+        };
+        let zone = self.find(ques.name())?;
+
+        let mut resp = DNS::response_for(query);
+        if resp.rcode() != Rcode::NoError {
+            return Some(resp);
+        }
+        resp.head_mut().with_aa(1);
+
+        let mut zone = zone;
+        let mut seen = vec![ques.name().clone()];
+        let mut next = zone.answer(ques, &mut resp);
+        // follow the CNAME chain as long as it stays in our zones (RFC 1034 4.3.2 3a)
+        while let Some(target) = next {
+            if seen.contains(&target) || seen.len() > MAX_CNAMES {
+                break;
+            }
+            zone = match self.find(&target) {
+                Some(zone) => zone,
+                None => break,
+            };
+            let ques = Question::from_parts(target.clone(), ques.typ(), ques.class());
+            seen.push(target);
+            next = zone.answer(&ques, &mut resp);
+        }
+
+        return Some(resp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = r#"
+$TTL 3600
+@           SOA     ns1 hostmaster 1 7200 900 1209600 300
+            NS      ns1
+            NS      ns2.other.example.
+            MX      10 mail
+ns1         A       192.0.2.53
+mail        A       192.0.2.25
+www         A       192.0.2.1
+            AAAA    2001:db8::1
+alias       CNAME   www
+outside     CNAME   www.other.example.
+loop        CNAME   loop
+a.b.c       TXT     "deep"
+child       NS      ns.child
+            NS      ns1.example.com.
+ns.child    A       192.0.2.99
+"#;
+
+    fn catalog() -> Catalog {
+        let origin: DomainName = "example.com.".parse().unwrap();
+        let records = parser::parse(ZONE, &origin).unwrap();
+        let mut catalog = Catalog::new();
+        catalog.with_zone(Zone::new(origin, records).unwrap());
+        return catalog;
+    }
+
+    fn query(name: &str, typ: u16) -> DNS {
+        let mut query = DNS::new();
+        query.head_mut().with_id(7).with_rd(1);
+        query.with_question(Question::from_parts(
+            name.parse().unwrap(),
+            typ,
+            rtype::CLASS_IN,
+        ));
+        return query;
+    }
+
+    fn names(records: &crate::dns::answer::Answers) -> Vec<String> {
+        return records
+            .iter()
+            .map(|rr| format!("{} {}", rr.name(), rr.typ()))
+            .collect();
+    }
+
+    #[test]
+    pub fn test_zone_answer() {
+        let catalog = catalog();
+        let resp = catalog
+            .answer(&query("WWW.example.com.", rtype::A))
+            .unwrap();
+        assert_eq!(7, resp.head().id());
+        assert_eq!(1, resp.head().aa());
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert_eq!(
+            &[192, 0, 2, 1],
+            resp.answers().iter().next().unwrap().rdata()
+        );
+
+        let resp = catalog
+            .answer(&query("www.example.com.", rtype::ANY))
+            .unwrap();
+        assert_eq!(2, resp.answers().len());
+
+        // the mail server address comes along
+        let resp = catalog.answer(&query("example.com.", rtype::MX)).unwrap();
+        assert_eq!(vec!["mail.example.com. 1"], names(resp.additionals()));
+
+        assert!(catalog.answer(&query("example.org.", rtype::A)).is_none());
+    }
+
+    #[test]
+    pub fn test_zone_negative() {
+        let catalog = catalog();
+        let resp = catalog
+            .answer(&query("missing.example.com.", rtype::A))
+            .unwrap();
+        assert_eq!(1, resp.head().aa());
+        assert_eq!(Rcode::NXDomain, resp.rcode());
+        assert!(resp.answers().is_empty());
+        let soa = resp.authorities().iter().next().unwrap();
+        assert_eq!(rtype::SOA, soa.typ());
+        assert_eq!(300, soa.ttl());
+
+        // NODATA
+        let resp = catalog
+            .answer(&query("www.example.com.", rtype::MX))
+            .unwrap();
+        assert_eq!(Rcode::NoError, resp.rcode());
+        assert!(resp.answers().is_empty());
+        assert_eq!(vec!["example.com. 6"], names(resp.authorities()));
+
+        // b.c and c exist without records
+        for name in ["b.c.example.com.", "c.example.com."] {
+            let resp = catalog.answer(&query(name, rtype::A)).unwrap();
+            assert_eq!(Rcode::NoError, resp.rcode());
+            assert_eq!(1, resp.authorities().len());
+        }
+        let resp = catalog
+            .answer(&query("x.c.example.com.", rtype::A))
+            .unwrap();
+        assert_eq!(Rcode::NXDomain, resp.rcode());
+    }
+
+    #[test]
+    pub fn test_zone_cname() {
+        let catalog = catalog();
+        let resp = catalog
+            .answer(&query("alias.example.com.", rtype::AAAA))
+            .unwrap();
+        assert_eq!(
+            vec!["alias.example.com. 5", "www.example.com. 28"],
+            names(resp.answers())
+        );
+        assert_eq!(1, resp.head().aa());
+
+        // the CNAME itself
+        let resp = catalog
+            .answer(&query("alias.example.com.", rtype::CNAME))
+            .unwrap();
+        assert_eq!(vec!["alias.example.com. 5"], names(resp.answers()));
+
+        // the resolver follows targets elsewhere
+        let resp = catalog
+            .answer(&query("outside.example.com.", rtype::A))
+            .unwrap();
+        assert_eq!(1, resp.answers().len());
+        assert_eq!(Rcode::NoError, resp.rcode());
+
+        let resp = catalog
+            .answer(&query("loop.example.com.", rtype::A))
+            .unwrap();
+        assert_eq!(1, resp.answers().len());
+    }
+
+    #[test]
+    pub fn test_zone_referral() {
+        let catalog = catalog();
+        for name in ["child.example.com.", "www.child.example.com."] {
+            let resp = catalog.answer(&query(name, rtype::A)).unwrap();
+            assert_eq!(0, resp.head().aa());
+            assert_eq!(Rcode::NoError, resp.rcode());
+            assert!(resp.answers().is_empty());
+            assert_eq!(
+                vec!["child.example.com. 2", "child.example.com. 2"],
+                names(resp.authorities())
+            );
+            assert_eq!(
+                vec!["ns.child.example.com. 1", "ns1.example.com. 1"],
+                names(resp.additionals())
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_zone_invalid() {
+        let origin: DomainName = "example.com.".parse().unwrap();
+        let zone = |text: &str| {
+            let records = parser::parse(text, &origin).unwrap();
+            return Zone::new(origin.clone(), records).unwrap_err().to_string();
+        };
+        assert_eq!(
+            "the zone example.com. needs exactly one SOA record at its apex",
+            zone("$TTL 60\n@ NS ns1")
+        );
+        assert_eq!(
+            "the zone example.com. has no NS records at its apex",
+            zone("$TTL 60\n@ SOA ns1 host 1 2 3 4 5")
+        );
+        assert_eq!(
+            "www.example.org. is outside the zone example.com.",
+            zone("$TTL 60\n@ SOA ns1 host 1 2 3 4 5\n@ NS ns1\nwww.example.org. A 192.0.2.1")
+        );
+        assert_eq!(
+            "www.example.com. has a CNAME and other data (RFC 1034 3.6.2)",
+            zone("$TTL 60\n@ SOA ns1 host 1 2 3 4 5\n@ NS ns1\nwww CNAME @\nwww A 192.0.2.1")
+        );
+    }
+}
@@ -0,0 +1,540 @@
+// Zone files in master file format (RFC 1035 5).
+//
+// Supported are `$ORIGIN` and `$TTL` (RFC 2308 4), `@`, names relative to the origin,
+// owners inherited from the previous record, parentheses spanning lines, comments, TTLs
+// with unit suffixes (`1h30m`), the class IN, the types A, AAAA, NS, CNAME, SOA, PTR, MX
+// and TXT, and any type in the generic `TYPEnnn \# LEN HEX` form (RFC 3597).
+use anyhow::Error;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::dns::{answer::ResourceRecord, rtype, DomainName};
+
+// The records of `text`, names relative to `origin` unless `$ORIGIN` says otherwise.
+// Errors name the line they were found on.
+pub fn parse(text: &str, origin: &DomainName) -> Result<Vec<ResourceRecord>, Error> {
+    let mut parser = Parser {
+        origin: origin.clone(),
+        default_ttl: None,
+        last_owner: None,
+        last_ttl: None,
+    };
+    let mut records = Vec::<ResourceRecord>::new();
+    for entry in entries(text)? {
+        if let Some(rr) = parser
+            .entry(&entry.tokens, entry.inherits_owner)
+            .map_err(|e| Error::msg(format!("line {}: {}", entry.line, e)))?
+        {
+            records.push(rr);
+        }
+    }
+    return Ok(records);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    // quoted tokens are never names, numbers or keywords
+    quoted: bool,
+}
+
+// One logical line, parentheses joined.
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    inherits_owner: bool,
+    tokens: Vec<Token>,
+}
+
+// Split `text` into entries of tokens, dropping comments and empty lines.
+fn entries(text: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::<Entry>::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let entry = current.get_or_insert_with(|| Entry {
+            line: number,
+            inherits_owner: line.starts_with([' ', '\t']),
+            tokens: vec![],
+        });
+
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                ';' => break,
+                '(' => depth += 1,
+                ')' => {
+                    if depth == 0 {
+                        return Err(Error::msg(format!("line {}: unbalanced ')'", number)));
+                    }
+                    depth -= 1;
+                }
+                '"' => {
+                    let mut text = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => {
+                                text.push('\\');
+                                if let Some(escaped) = chars.next() {
+                                    text.push(escaped);
+                                }
+                            }
+                            Some(c) => text.push(c),
+                            None => {
+                                return Err(Error::msg(format!(
+                                    "line {}: unterminated string",
+                                    number
+                                )))
+                            }
+                        }
+                    }
+                    entry.tokens.push(Token { text, quoted: true });
+                }
+                _ => {
+                    let mut text = c.to_string();
+                    let mut escaped = c == '\\';
+                    while let Some(&next) = chars.peek() {
+                        if !escaped && matches!(next, ' ' | '\t' | '\r' | ';' | '(' | ')' | '"') {
+                            break;
+                        }
+                        escaped = !escaped && next == '\\';
+                        text.push(next);
+                        chars.next();
+                    }
+                    entry.tokens.push(Token {
+                        text,
+                        quoted: false,
+                    });
+                }
+            }
+        }
+
+        if depth == 0 {
+            if let Some(entry) = current.take() {
+                if !entry.tokens.is_empty() {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+    if depth > 0 {
+        return Err(Error::msg("unbalanced '(' at the end of the file"));
+    }
+
+    return Ok(entries);
+}
+
+struct Parser {
+    origin: DomainName,
+    default_ttl: Option<u32>,
+    last_owner: Option<DomainName>,
+    last_ttl: Option<u32>,
+}
+
+impl Parser {
+    // The record of one entry, `None` for directives.
+    fn entry(
+        &mut self,
+        tokens: &[Token],
+        inherits_owner: bool,
+    ) -> Result<Option<ResourceRecord>, Error> {
+        let mut tokens = tokens.iter().peekable();
+        if !inherits_owner {
+            let first = tokens.peek().map(|t| t.text.as_str());
+            match first {
+                Some("$ORIGIN") => {
+                    tokens.next();
+                    let name = expect(&mut tokens, "a name")?;
+                    self.origin = self.name(name)?;
+                    return Ok(None);
+                }
+                Some("$TTL") => {
+                    tokens.next();
+                    self.default_ttl = Some(ttl(&expect(&mut tokens, "a TTL")?.text)?);
+                    return Ok(None);
+                }
+                Some(directive) if directive.starts_with('$') => {
+                    return Err(Error::msg(format!("unsupported directive {}", directive)));
+                }
+                _ => {}
+            }
+        }
+
+        let owner = if inherits_owner {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| Error::msg("no previous owner to inherit"))?
+        } else {
+            self.name(expect(&mut tokens, "an owner")?)?
+        };
+
+        // TTL and class come in either order, both are optional
+        let mut record_ttl = None;
+        let mut typ = None;
+        for token in tokens.by_ref() {
+            let text = token.text.as_str();
+            if text.eq_ignore_ascii_case("IN") {
+                continue;
+            }
+            if let Some(class) = ["CH", "HS", "CS"]
+                .iter()
+                .find(|c| text.eq_ignore_ascii_case(c))
+            {
+                return Err(Error::msg(format!("unsupported class {}", class)));
+            }
+            if record_ttl.is_none() && text.starts_with(|c: char| c.is_ascii_digit()) {
+                record_ttl = Some(ttl(text)?);
+                continue;
+            }
+            typ = Some(rr_type(text)?);
+            break;
+        }
+        let typ = typ.ok_or_else(|| Error::msg("the record has no type"))?;
+
+        let ttl = match record_ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(ttl) => ttl,
+            None => return Err(Error::msg("the record has no TTL and there is no $TTL")),
+        };
+        let rest: Vec<&Token> = tokens.collect();
+        let rdata = self.rdata(typ, &rest)?;
+
+        let mut rr = ResourceRecord::new();
+        rr.with_domain_name(owner.clone())
+            .with_type(typ)
+            .with_class(rtype::CLASS_IN)
+            .with_ttl(ttl)
+            .with_raw_rdata(rdata);
+        self.last_owner = Some(owner);
+        self.last_ttl = Some(ttl);
+
+        return Ok(Some(rr));
+    }
+
+    // An absolute name, or one relative to the origin. `@` is the origin itself.
+    fn name(&self, token: &Token) -> Result<DomainName, Error> {
+        let text = token.text.as_str();
+        if text == "@" {
+            return Ok(self.origin.clone());
+        }
+        let name: DomainName = text.parse()?;
+        if is_absolute(text) {
+            return Ok(name);
+        }
+        let labels = name.iter().chain(self.origin.iter()).map(|l| l.to_vec());
+        return DomainName::from_labels(labels);
+    }
+
+    fn rdata(&self, typ: u16, tokens: &[&Token]) -> Result<Vec<u8>, Error> {
+        if tokens.first().is_some_and(|t| t.text == "\\#" && !t.quoted) {
+            return generic_rdata(&tokens[1..]);
+        }
+
+        let count = |n: usize| -> Result<(), Error> {
+            if tokens.len() != n {
+                return Err(Error::msg(format!(
+                    "expected {} RDATA fields, found {}",
+                    n,
+                    tokens.len()
+                )));
+            }
+            return Ok(());
+        };
+        let mut rdata = Vec::<u8>::new();
+        match typ {
+            rtype::A => {
+                count(1)?;
+                let ip: Ipv4Addr = tokens[0]
+                    .text
+                    .parse()
+                    .map_err(|_| Error::msg(format!("invalid IPv4 address {}", tokens[0].text)))?;
+                rdata.extend_from_slice(&ip.octets());
+            }
+            rtype::AAAA => {
+                count(1)?;
+                let ip: Ipv6Addr = tokens[0]
+                    .text
+                    .parse()
+                    .map_err(|_| Error::msg(format!("invalid IPv6 address {}", tokens[0].text)))?;
+                rdata.extend_from_slice(&ip.octets());
+            }
+            rtype::NS | rtype::CNAME | rtype::PTR => {
+                count(1)?;
+                rdata.extend(self.name(tokens[0])?.encode());
+            }
+            rtype::MX => {
+                count(2)?;
+                rdata.extend_from_slice(&number::<u16>(&tokens[0].text)?.to_be_bytes());
+                rdata.extend(self.name(tokens[1])?.encode());
+            }
+            rtype::SOA => {
+                count(7)?;
+                rdata.extend(self.name(tokens[0])?.encode());
+                rdata.extend(self.name(tokens[1])?.encode());
+                rdata.extend_from_slice(&number::<u32>(&tokens[2].text)?.to_be_bytes());
+                for token in &tokens[3..] {
+                    rdata.extend_from_slice(&ttl(&token.text)?.to_be_bytes());
+                }
+            }
+            rtype::TXT => {
+                if tokens.is_empty() {
+                    return Err(Error::msg("TXT needs at least one string"));
+                }
+                for token in tokens {
+                    let text = unescape(&token.text)?;
+                    if text.len() > 255 {
+                        return Err(Error::msg("TXT strings are 255 bytes at most"));
+                    }
+                    rdata.push(text.len() as u8);
+                    rdata.extend(text);
+                }
+            }
+            _ => {
+                return Err(Error::msg(format!(
+                    "TYPE{} needs its RDATA in the \\# form",
+                    typ
+                )))
+            }
+        }
+
+        return Ok(rdata);
+    }
+}
+
+fn expect<'a>(
+    tokens: &mut impl Iterator<Item = &'a Token>,
+    what: &str,
+) -> Result<&'a Token, Error> {
+    return tokens
+        .next()
+        .ok_or_else(|| Error::msg(format!("expected {}", what)));
+}
+
+// A trailing unescaped `.` makes a name absolute.
+fn is_absolute(text: &str) -> bool {
+    match text.strip_suffix('.') {
+        Some(rest) => return rest.chars().rev().take_while(|c| *c == '\\').count() % 2 == 0,
+        None => return false,
+    }
+}
+
+fn rr_type(text: &str) -> Result<u16, Error> {
+    let known = [
+        ("A", rtype::A),
+        ("NS", rtype::NS),
+        ("CNAME", rtype::CNAME),
+        ("SOA", rtype::SOA),
+        ("PTR", rtype::PTR),
+        ("MX", rtype::MX),
+        ("TXT", rtype::TXT),
+        ("AAAA", rtype::AAAA),
+    ];
+    if let Some((_, typ)) = known
+        .iter()
+        .find(|(name, _)| text.eq_ignore_ascii_case(name))
+    {
+        return Ok(*typ);
+    }
+    let upper = text.to_ascii_uppercase();
+    if let Some(digits) = upper.strip_prefix("TYPE") {
+        let typ = number::<u16>(digits)?;
+        if typ == rtype::OPT || typ == rtype::ANY {
+            return Err(Error::msg(format!("{} cannot be served", text)));
+        }
+        return Ok(typ);
+    }
+    return Err(Error::msg(format!("unknown type {}", text)));
+}
+
+fn number<T: std::str::FromStr>(text: &str) -> Result<T, Error> {
+    return text
+        .parse::<T>()
+        .map_err(|_| Error::msg(format!("invalid number {}", text)));
+}
+
+// Seconds, or a sum of numbers with the units w, d, h, m and s.
+fn ttl(text: &str) -> Result<u32, Error> {
+    if let Ok(seconds) = text.parse::<u32>() {
+        return Ok(seconds);
+    }
+    let invalid = || Error::msg(format!("invalid TTL {}", text));
+    let mut total: u64 = 0;
+    let mut value: Option<u64> = None;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = Some(value.unwrap_or(0) * 10 + digit as u64);
+            if value > Some(u32::MAX as u64) {
+                return Err(invalid());
+            }
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604800,
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        total += value.take().ok_or_else(invalid)? * unit;
+    }
+    if value.is_some() || total > u32::MAX as u64 {
+        return Err(invalid());
+    }
+    return Ok(total as u32);
+}
+
+// The bytes of a character string with `\X` and `\DDD` escapes resolved.
+fn unescape(text: &str) -> Result<Vec<u8>, Error> {
+    let mut result = Vec::<u8>::new();
+    let mut chars = text.chars();
+    let mut utf8 = [0_u8; 4];
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        let escaped = chars.next().ok_or_else(|| Error::msg("dangling escape"))?;
+        if escaped.is_ascii_digit() {
+            let digits: String = [Some(escaped), chars.next(), chars.next()]
+                .into_iter()
+                .flatten()
+                .collect();
+            let byte = digits
+                .parse::<u8>()
+                .map_err(|_| Error::msg(format!("invalid escape \\{}", digits)))?;
+            result.push(byte);
+        } else {
+            result.extend_from_slice(escaped.encode_utf8(&mut utf8).as_bytes());
+        }
+    }
+    return Ok(result);
+}
+
+// `\# LEN HEX...` of RFC 3597 5.
+#[allow(clippy::manual_is_multiple_of)]
+fn generic_rdata(tokens: &[&Token]) -> Result<Vec<u8>, Error> {
+    let (length, hex) = match tokens.split_first() {
+        Some((length, hex)) => (number::<usize>(&length.text)?, hex),
+        None => return Err(Error::msg("\\# needs the RDATA length")),
+    };
+    let hex: String = hex.iter().map(|t| t.text.as_str()).collect();
+    if hex.len() % 2 != 0 {
+        return Err(Error::msg("odd number of hex digits"));
+    }
+    let rdata = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| Error::msg(format!("invalid hex {}", hex)))?;
+    if rdata.len() != length {
+        return Err(Error::msg(format!(
+            "RDATA has {} bytes, {} announced",
+            rdata.len(),
+            length
+        )));
+    }
+    return Ok(rdata);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = r#"
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024010101 ; serial
+            3600 900 1w 300 )
+    IN  NS  ns1
+    IN  NS  ns.other.example.
+    IN  MX  10 mail
+ns1 300 IN A 192.0.2.53
+www     A 192.0.2.1
+        AAAA 2001:db8::1
+txt IN 60 TXT "hello world" "a\"b" plain
+blob    TYPE65280 \# 3 abcd EF
+$ORIGIN sub.example.com.
+deep    CNAME www.example.com.
+"#;
+
+    #[test]
+    pub fn test_zone_parse() {
+        let origin: DomainName = "example.com.".parse().unwrap();
+        let records = parse(ZONE, &origin).unwrap();
+        assert_eq!(10, records.len());
+
+        let soa = &records[0];
+        assert_eq!("example.com.", soa.name().to_string());
+        assert_eq!(rtype::SOA, soa.typ());
+        assert_eq!(3600, soa.ttl());
+        let (mname, pos) = DomainName::decode(soa.rdata(), 0).unwrap();
+        assert_eq!("ns1.example.com.", mname.to_string());
+        let (_, pos) = DomainName::decode(soa.rdata(), pos).unwrap();
+        assert_eq!(&2024010101_u32.to_be_bytes(), &soa.rdata()[pos..pos + 4]);
+        assert_eq!(&604800_u32.to_be_bytes(), &soa.rdata()[pos + 12..pos + 16]);
+
+        // the owner is inherited by indented lines
+        assert_eq!("example.com.", records[1].name().to_string());
+        let (ns, _) = DomainName::decode(records[2].rdata(), 0).unwrap();
+        assert_eq!("ns.other.example.", ns.to_string());
+        assert_eq!(&[0, 10], &records[3].rdata()[..2]);
+        assert_eq!(300, records[4].ttl());
+        assert_eq!(&[192, 0, 2, 1], records[5].rdata());
+        assert_eq!("www.example.com.", records[6].name().to_string());
+        assert_eq!(rtype::AAAA, records[6].typ());
+
+        let txt = &records[7];
+        assert_eq!(60, txt.ttl());
+        assert_eq!(b"\x0bhello world\x03a\"b\x05plain".to_vec(), txt.rdata());
+        assert_eq!(65280, records[8].typ());
+        assert_eq!(&[0xab, 0xcd, 0xef], records[8].rdata());
+
+        assert_eq!("deep.sub.example.com.", records[9].name().to_string());
+        assert_eq!(rtype::CNAME, records[9].typ());
+    }
+
+    #[test]
+    pub fn test_zone_parse_errors() {
+        let origin: DomainName = "example.com.".parse().unwrap();
+        let error = |text: &str| parse(text, &origin).unwrap_err().to_string();
+
+        assert_eq!(
+            "line 1: the record has no TTL and there is no $TTL",
+            error("www A 192.0.2.1")
+        );
+        assert_eq!("line 2: unknown type BOGUS", error("$TTL 60\nwww BOGUS 1"));
+        assert_eq!(
+            "line 1: invalid IPv4 address 192.0.2",
+            error("www 60 A 192.0.2")
+        );
+        assert_eq!(
+            "line 1: expected 1 RDATA fields, found 2",
+            error("www 60 A 192.0.2.1 192.0.2.2")
+        );
+        assert_eq!("line 1: invalid TTL 1x", error("www 1x A 192.0.2.1"));
+        assert_eq!(
+            "unbalanced '(' at the end of the file",
+            error("@ 60 SOA ns host ( 1 2 3 4 5")
+        );
+        assert_eq!(
+            "line 1: unsupported directive $INCLUDE",
+            error("$INCLUDE other.zone")
+        );
+        assert_eq!(
+            "line 1: no previous owner to inherit",
+            error("  60 A 192.0.2.1")
+        );
+    }
+
+    #[test]
+    pub fn test_zone_parse_ttl() {
+        assert_eq!(5400, ttl("1h30m").unwrap());
+        assert_eq!(86400 * 8, ttl("1W1D").unwrap());
+        assert_eq!(42, ttl("42").unwrap());
+        assert!(ttl("h").is_err());
+        assert!(ttl("10m5").is_err());
+        assert!(ttl("9999999999").is_err());
+    }
+}